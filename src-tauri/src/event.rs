@@ -2,9 +2,12 @@ pub mod cassandra;
 pub mod ssh_tunnel;
 use serde::{Serialize, Deserialize};
 use tauri::window::Window;
-use crate::plugins::ssh_tunnel::{TunnelControl, TunnelsReceiver};
+use crate::plugins::ssh_tunnel::{TunnelControl, TunnelNoticeReceiver};
+use crate::plugins::ssh_tunnel::interactive::Prompter;
+use crate::plugins::ssh_tunnel::link::TunnelLink;
+use crate::plugins::datax::cassandra::PeerCache;
 use tokio::sync::mpsc::Sender;
-use crate::plugins::error::Error as PluginError;
+use crate::plugins::error::{Error as PluginError, ErrorKind};
 
 pub type SerdeError = serde_json::error::Error;
 
@@ -14,29 +17,43 @@ pub trait ConvertToEventError {
 
 impl ConvertToEventError for PluginError {
     fn convert(&self) -> EventError {
-        EventError { code: (self.code), message: (self.message.clone()) }
+        EventError {
+            code: self.code(),
+            kind: String::from(self.kind.as_str()),
+            message: self.message.clone(),
+            retryable: self.retryable,
+            context: self.context.clone(),
+            causes: self.causes(),
+        }
     }
 }
 
 impl ConvertToEventError for SerdeError {
     fn convert(&self) -> EventError {
-        EventError::build(0, self.to_string().as_str())
+        EventError::build(ErrorKind::Validation, self.to_string().as_str())
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EventError {
     code: i32,
+    kind: String,
     message: String,
+    retryable: bool,
+    // key and value pairs in the order they were added, a wrapped error may repeat a key of its cause
+    context: Vec<(String, String)>,
+    causes: Vec<String>,
 }
 
 impl EventError {
-    pub fn build(code: i32, message: &str) -> EventError {
-        EventError { code: (code), message: (String::from(message)) }
+    pub fn build(kind: ErrorKind, message: &str) -> EventError {
+        PluginError::build(kind, message).convert()
     }
 
-    pub fn unknow() -> EventError {
-        EventError { code: 0, message: String::from("unknow error")}
+    pub fn missing(param: &str) -> EventError {
+        PluginError::build(ErrorKind::Validation, format!("{} is required", param).as_str())
+            .with_context("param", param)
+            .convert()
     }
 }
 
@@ -52,18 +69,46 @@ struct Response<T = String> where T: Clone + Serialize {
     err: Option<EventError>,
 }
 
-pub fn bind_ssh_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
-    ssh_tunnel::req_view_tunnel(w, &sx, rx);
-    ssh_tunnel::req_register_tunnel(w, &sx, rx);
-    ssh_tunnel::req_sync_tunnel(w, &sx, rx);
-    ssh_tunnel::req_delete_tunnel(w, &sx, rx);
-    ssh_tunnel::req_state_tunnel(w, &sx, rx);
+pub fn bind_ssh_tunnel(w: &Window, sx: &Sender<TunnelControl>, prompter: &Prompter, notices: &TunnelNoticeReceiver) {
+    ssh_tunnel::req_view_tunnel(w, &sx);
+    ssh_tunnel::req_register_tunnel(w, &sx);
+    ssh_tunnel::req_sync_tunnel(w, &sx);
+    ssh_tunnel::req_delete_tunnel(w, &sx);
+    ssh_tunnel::req_state_tunnel(w, &sx);
+    ssh_tunnel::req_clients_tunnel(w, &sx);
+    ssh_tunnel::req_kill_tunnel(w, &sx);
+    ssh_tunnel::req_group_tunnel(w, &sx);
+    ssh_tunnel::req_view_tunnel_group(w, &sx);
+    ssh_tunnel::req_test_tunnel(w, prompter);
+    ssh_tunnel::req_view_hostkey(w);
+    ssh_tunnel::req_remove_hostkey(w);
+    ssh_tunnel::req_trust_hostkey(w);
+    ssh_tunnel::req_view_agent_identity(w);
+    ssh_tunnel::req_import_tunnel(w);
+    ssh_tunnel::push_auth_prompt(w, prompter);
+    ssh_tunnel::req_answer_auth(w, prompter);
+    ssh_tunnel::push_tunnel_notice(w, notices);
 }
 
-pub fn bind_cql_event(w: &Window) {
-    cassandra::req_cql_keyspaces(w);
-    cassandra::req_cql_tables(w);
-    cassandra::req_cql_table_info(w);
-    cassandra::req_cql_data(w);
-    cassandra::req_cql_detail_data(w);
+pub fn bind_cql_event(w: &Window, link: &TunnelLink, peers: &PeerCache) {
+    cassandra::req_cql_keyspaces(w, link, peers);
+    cassandra::req_cql_tables(w, link, peers);
+    cassandra::req_cql_table_info(w, link, peers);
+    cassandra::req_cql_data(w, link, peers);
+    cassandra::req_cql_detail_data(w, link, peers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn context_keeps_repeated_keys_in_order() {
+        let cause = PluginError::build(ErrorKind::Validation, "DEPENDENCY CYCLE!!!").with_context("tunnel", "jump");
+        let mut e = PluginError::build(ErrorKind::Tunnel, "TUNNEL FAILED TO START!!!").with_context("tunnel", "app");
+        e.context.extend(cause.context.iter().cloned());
+        let value = serde_json::to_value(e.convert()).unwrap();
+        assert_eq!(value["context"], json!([["tunnel", "app"], ["tunnel", "jump"]]));
+    }
 }
\ No newline at end of file
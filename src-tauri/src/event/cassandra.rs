@@ -5,7 +5,8 @@ use serde_json::Value;
 use super::{Request, Response};
 use crate::plugins::datax::{Field, UDTField, FieldParam, BaseInfo, FieldType};
 use crate::plugins::datax::cassandra;
-use crate::plugins::datax::cassandra::SelectCQL;
+use crate::plugins::datax::cassandra::{CQLResult, PeerCache, SelectCQL};
+use crate::plugins::ssh_tunnel::link::TunnelLink;
 use super::{EventError,ConvertToEventError};
 use super::SerdeError;
 use tokio::task;
@@ -22,6 +23,12 @@ pub struct CQLParam {
   pub udt: Option<UDTField>,
   pub params: Option<Vec<FieldParam>>,
   pub pagination: Option<Vec<u8>>,
+  // the id of the ssh tunnel the cluster is reached through, `host` and `port` are then where it forwards to
+  #[serde(default)]
+  pub tunnel: Option<String>,
+  // where the tunnel forwards the cluster's other nodes, kept by the PeerCache and never taken from the request
+  #[serde(default, skip_deserializing)]
+  pub nodes: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,44 +55,72 @@ const RESP_CQL_DATA: &'static str = "cql-data-reply";
 const REQ_CQL_DETAIL_DATA: &'static str = "cql-detail-data";
 const RESP_CQL_DETAIL_DATA: &'static str = "cql-detail-data-reply";
 
-async fn view_keyspaces(param: CQLParam) -> Result<Vec<String>, EventError> {
-    let CQLParam{port, host, username, password, ..} = param;
-    let bi = BaseInfo{port, host: &host, username: &username, password: &password};
-    match cassandra::view_keyspace(&bi).await {
-        Ok(t) => Ok(t),
-        Err(e) => Err(e.convert()),
+fn missing_param(param: &CQLParam) -> EventError {
+    match param {
+        CQLParam{keyspace: None, ..} => EventError::missing("keyspace"),
+        _ => EventError::missing("table"),
     }
 }
 
-async fn view_tables(param: CQLParam) -> Result<Vec<String>, EventError> {
-    if let CQLParam{port, host, username, password, keyspace: Some(keyspace), ..} = param {
-        let bi = BaseInfo{port, host: &host, username: &username, password: &password};
-        match cassandra::view_tables(&keyspace, &bi).await {
-            Ok(t) => Ok(t),
-            Err(e) => Err(e.convert()),
-        }
+// the tunnel and the profile's contact point behind it, what the cluster's nodes are kept by
+fn peer_key(param: &CQLParam) -> Option<(String, String)> {
+    param.tunnel.clone().filter(|id| !id.is_empty()).map(|tunnel| (tunnel, format!("{}:{}", param.host, param.port)))
+}
+
+async fn resolve(mut param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<CQLParam, EventError> {
+    let (host, port) = match cassandra::contact_point(&param.host, param.port, param.tunnel.as_deref(), link).await {
+        Ok(contact_point) => contact_point,
+        Err(e) => return Err(e.convert()),
+    };
+    if let Some((tunnel, contact)) = peer_key(&param) {
+        let bi = BaseInfo{port, host: &host, username: &param.username, password: &param.password, nodes: &[]};
+        param.nodes = peers.nodes(&bi, &contact, param.port, &tunnel, link).await;
+    }
+    param.host = host;
+    param.port = port;
+    Ok(param)
+}
+
+// a failed request has the nodes found anew the next time, one of them may be what failed
+fn settle<T>(rs: CQLResult<T>, key: &Option<(String, String)>, peers: &PeerCache) -> CQLResult<T> {
+    if let (Err(_), Some((tunnel, contact))) = (&rs, key) {
+        peers.forget(tunnel, contact);
+    }
+    rs
+}
+
+async fn view_keyspaces(param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<Vec<String>, EventError> {
+    let key = peer_key(&param);
+    let param = resolve(param, link, peers).await?;
+    let CQLParam{port, host, username, password, nodes, ..} = param;
+    let bi = BaseInfo{port, host: &host, username: &username, password: &password, nodes: &nodes};
+    settle(cassandra::view_keyspace(&bi).await, &key, peers).map_err(|e| e.convert())
+}
+
+async fn view_tables(param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<Vec<String>, EventError> {
+    let key = peer_key(&param);
+    let param = resolve(param, link, peers).await?;
+    if let CQLParam{port, host, username, password, nodes, keyspace: Some(keyspace), ..} = param {
+        let bi = BaseInfo{port, host: &host, username: &username, password: &password, nodes: &nodes};
+        settle(cassandra::view_tables(&keyspace, &bi).await, &key, peers).map_err(|e| e.convert())
     } else {
-        Err(EventError::unknow())
+        Err(missing_param(&param))
     }
 }
 
-async fn view_table_info(param: CQLParam) -> Result<CQLTableInfo, EventError> {
-    if let CQLParam{port, host, username, password, keyspace: Some(keyspace), table: Some(table), ..} = param {
-        let bi = BaseInfo{port, host: &host, username: &username, password: &password};
-        let columns = match cassandra::view_columns(&keyspace, &table, &bi).await {
-            Ok(t) => t,
-            Err(e) => return Err(e.convert()),
-        };
+async fn view_table_info(param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<CQLTableInfo, EventError> {
+    let key = peer_key(&param);
+    let param = resolve(param, link, peers).await?;
+    if let CQLParam{port, host, username, password, nodes, keyspace: Some(keyspace), table: Some(table), ..} = param {
+        let bi = BaseInfo{port, host: &host, username: &username, password: &password, nodes: &nodes};
+        let columns = settle(cassandra::view_columns(&keyspace, &table, &bi).await, &key, peers).map_err(|e| e.convert())?;
 
         let mut fields = Vec::new();
         let mut udts = Vec::new();
 
         for column in columns {
             if let FieldType::List(type_name) = &column.vtype {
-                let type_fields = match cassandra::view_type_by_name(&keyspace, type_name, &bi).await {
-                    Ok(t) => t,
-                    Err(e) => return Err(e.convert()),
-                };
+                let type_fields = settle(cassandra::view_type_by_name(&keyspace, type_name, &bi).await, &key, peers).map_err(|e| e.convert())?;
                 udts.push(UDTField{field: column, udt: type_fields});
             } else {
                 fields.push(column);
@@ -94,47 +129,49 @@ async fn view_table_info(param: CQLParam) -> Result<CQLTableInfo, EventError> {
 
         Ok(CQLTableInfo{keyspace, table, fields, udts: Some(udts)})
     } else {
-        Err(EventError::unknow())
+        Err(missing_param(&param))
     }
 }
 
-async fn view_data(param: CQLParam) -> Result<HashMap<String, Value>, EventError> {
-    if let CQLParam{port, host, username, password, keyspace: Some(keyspace), table: Some(table), fields, params, pagination, ..} = param {
-        let bi = BaseInfo{port, host: &host, username: &username, password: &password};
+async fn view_data(param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<HashMap<String, Value>, EventError> {
+    let key = peer_key(&param);
+    let param = resolve(param, link, peers).await?;
+    if let CQLParam{port, host, username, password, nodes, keyspace: Some(keyspace), table: Some(table), fields, params, pagination, ..} = param {
+        let bi = BaseInfo{port, host: &host, username: &username, password: &password, nodes: &nodes};
         let scql = SelectCQL { keyspace: &keyspace, table_name: &table, fields: &fields, udt: &None, params: &params };
-        match cassandra::view_data(&bi, &scql, CQL_PAGE_SIZE, pagination).await {
-            Ok(t) => Ok(t),
-            Err(e) => Err(e.convert()),
-        }
+        settle(cassandra::view_data(&bi, &scql, CQL_PAGE_SIZE, pagination).await, &key, peers).map_err(|e| e.convert())
     } else {
-        Err(EventError::unknow())
+        Err(missing_param(&param))
     }
 }
 
-async fn view_detail_data(param: CQLParam) -> Result<Vec<HashMap<String, Value>>, EventError> {
-    if let CQLParam{port, host, username, password, keyspace: Some(keyspace), table: Some(table), udt, params, ..} = param {
-        let bi = BaseInfo{port, host: &host, username: &username, password: &password};
+async fn view_detail_data(param: CQLParam, link: &TunnelLink, peers: &PeerCache) -> Result<Vec<HashMap<String, Value>>, EventError> {
+    let key = peer_key(&param);
+    let param = resolve(param, link, peers).await?;
+    if let CQLParam{port, host, username, password, nodes, keyspace: Some(keyspace), table: Some(table), udt, params, ..} = param {
+        let bi = BaseInfo{port, host: &host, username: &username, password: &password, nodes: &nodes};
         let scql = SelectCQL { keyspace: &keyspace, table_name: &table, fields: &None, udt: &udt, params: &params };
-        match cassandra::view_detail_data(&bi, &scql).await {
-            Ok(t) => Ok(t),
-            Err(e) => Err(e.convert()),
-        }
+        settle(cassandra::view_detail_data(&bi, &scql).await, &key, peers).map_err(|e| e.convert())
     } else {
-        Err(EventError::unknow())
+        Err(missing_param(&param))
     }
 }
 
-pub fn req_cql_keyspaces(w: &Window) {
+pub fn req_cql_keyspaces(w: &Window, link: &TunnelLink, peers: &PeerCache) {
     let w_replica = w.clone();
+    let link_replica = link.clone();
+    let peers_replica = peers.clone();
 
     w.listen(REQ_CQL_KEYSAPCE, move |e| {
         let w_replica = w_replica.clone();
+        let link_replica = link_replica.clone();
+        let peers_replica = peers_replica.clone();
 
         task::spawn(async move {
             let req: Result<Request<CQLParam>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    match view_keyspaces(req.data).await {
+                    match view_keyspaces(req.data, &link_replica, &peers_replica).await {
                         Ok(t) => Response{status: String::from("success"), data: Some(t), err: None},
                         Err(e) => Response{status: String::from("failure"), data: None, err: Some(e)},
                     }
@@ -146,16 +183,20 @@ pub fn req_cql_keyspaces(w: &Window) {
     });
 }
 
-pub fn req_cql_tables(w: &Window) {
+pub fn req_cql_tables(w: &Window, link: &TunnelLink, peers: &PeerCache) {
     let w_replica = w.clone();
+    let link_replica = link.clone();
+    let peers_replica = peers.clone();
     w.listen(REQ_CQL_TABLES, move |e| {
         let w_replica = w_replica.clone();
+        let link_replica = link_replica.clone();
+        let peers_replica = peers_replica.clone();
 
         task::spawn(async move{
             let req: Result<Request<CQLParam>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    match view_tables(req.data).await {
+                    match view_tables(req.data, &link_replica, &peers_replica).await {
                         Ok(t) => Response{status: String::from("success"), data: Some(t), err: None},
                         Err(e) => Response{status: String::from("failure"), data: None, err: Some(e)},
                     }
@@ -167,16 +208,20 @@ pub fn req_cql_tables(w: &Window) {
     });
 }
 
-pub fn req_cql_table_info(w: &Window) {
+pub fn req_cql_table_info(w: &Window, link: &TunnelLink, peers: &PeerCache) {
     let w_replica = w.clone();
+    let link_replica = link.clone();
+    let peers_replica = peers.clone();
     w.listen(REQ_CQL_TABLE_INFO,  move |e| {
         let w_replica = w_replica.clone();
+        let link_replica = link_replica.clone();
+        let peers_replica = peers_replica.clone();
 
         task::spawn(async move{
             let req: Result<Request<CQLParam>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    match view_table_info(req.data).await {
+                    match view_table_info(req.data, &link_replica, &peers_replica).await {
                         Ok(t) => Response{status: String::from("success"), data: Some(t), err: None},
                         Err(e) => Response{status: String::from("failure"), data: None, err: Some(e)},
                     }
@@ -188,16 +233,20 @@ pub fn req_cql_table_info(w: &Window) {
     });
 }
 
-pub fn req_cql_data(w: &Window) {
+pub fn req_cql_data(w: &Window, link: &TunnelLink, peers: &PeerCache) {
     let w_replica = w.clone();
+    let link_replica = link.clone();
+    let peers_replica = peers.clone();
     w.listen(REQ_CQL_DATA,  move |e| {
         let w_replica = w_replica.clone();
+        let link_replica = link_replica.clone();
+        let peers_replica = peers_replica.clone();
 
         task::spawn(async move{
             let req: Result<Request<CQLParam>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    match view_data(req.data).await {
+                    match view_data(req.data, &link_replica, &peers_replica).await {
                         Ok(t) => Response{status: String::from("success"), data: Some(t), err: None},
                         Err(e) => Response{status: String::from("failure"), data: None, err: Some(e)},
                     }
@@ -209,16 +258,20 @@ pub fn req_cql_data(w: &Window) {
     });
 }
 
-pub fn req_cql_detail_data(w: &Window) {
+pub fn req_cql_detail_data(w: &Window, link: &TunnelLink, peers: &PeerCache) {
     let w_replica = w.clone();
+    let link_replica = link.clone();
+    let peers_replica = peers.clone();
     w.listen(REQ_CQL_DETAIL_DATA,  move |e| {
         let w_replica = w_replica.clone();
+        let link_replica = link_replica.clone();
+        let peers_replica = peers_replica.clone();
 
         task::spawn(async move{
             let req: Result<Request<CQLParam>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    match view_detail_data(req.data).await {
+                    match view_detail_data(req.data, &link_replica, &peers_replica).await {
                         Ok(t) => Response{status: String::from("success"), data: Some(t), err: None},
                         Err(e) => Response{status: String::from("failure"), data: None, err: Some(e)},
                     }
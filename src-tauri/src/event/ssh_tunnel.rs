@@ -1,54 +1,76 @@
 use tokio::sync::mpsc::{Receiver, Sender, channel};
-use crate::plugins::ssh_tunnel::{Tunnel, TunnelControl, TunnelOperator, TunnelResult, TunnelsReceiver};
+use crate::plugins::ssh_tunnel::{request, Tunnel, TunnelControl, TunnelNotice, TunnelNoticeKind, TunnelNoticeReceiver, TunnelOperator, TunnelResult};
 use crate::plugins::ssh_tunnel::api::run;
+use crate::plugins::ssh_tunnel::probe::test;
+use crate::plugins::ssh_tunnel::known_hosts::{self, HostKeyEntry, HostKeyPrompt};
+use crate::plugins::ssh_tunnel::agent::{self, AgentIdentity};
+use crate::plugins::ssh_tunnel::ssh_config::{self, ImportPreview};
+use crate::plugins::ssh_tunnel::interactive::{AuthAnswer, Prompter};
+use crate::plugins::ssh_tunnel::clients::{TunnelClient, TunnelClientKill};
+use crate::plugins::ssh_tunnel::group::{self, TunnelGroup, TunnelGroupControl};
+use tauri::Manager;
+use crate::plugins::error::Error as PluginError;
 use tauri::window::Window;
 use crate::event::{Request, Response};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::task;
+use tokio::time;
+use tokio::runtime::Handle;
+use std::time::Duration;
 use super::SerdeError;
 use super::ConvertToEventError;
 use super::EventError;
+use crate::plugins::error::ErrorKind;
 
 pub type ArcReceiver<T> = Arc<Mutex<Receiver<T>>>;
 
-pub async fn tunnel_init() -> (Sender<TunnelControl>, TunnelsReceiver) {
+pub async fn tunnel_init() -> (Sender<TunnelControl>, Prompter, TunnelNoticeReceiver) {
 
     let (sx_src, rx_src) = channel::<TunnelControl>(1024);
-    let (sx_dest, rx_dest) = channel::<TunnelResult<Vec<Tunnel>>>(1024);
-    tokio::spawn(run(sx_dest, rx_src));
-    (sx_src, Arc::new(Mutex::new(rx_dest)))
+    let (sx_notice, rx_notice) = channel::<TunnelNotice>(1024);
+    let prompter = Prompter::new();
+    tokio::spawn(run(rx_src, prompter.clone(), sx_notice));
+    (sx_src, prompter, Arc::new(Mutex::new(rx_notice)))
 }
 
-pub fn req_view_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
+// every tunnel drains its clients first, so this has to outlast the longest drain timeout
+const TUNNEL_EXIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// called from the event loop when the app goes down, blocks until every tunnel is stopped
+pub fn tunnel_exit(sx: &Sender<TunnelControl>) {
+
+    let handle = Handle::current();
+    let rs = task::block_in_place(|| handle.block_on(time::timeout(TUNNEL_EXIT_TIMEOUT, request(sx, None, TunnelOperator::EXIT))));
+    match rs {
+        Ok(Some(Ok(_))) => log::info!("TUNNELS STOPPED ON EXIT"),
+        Ok(Some(Err(e))) => log::warn!("TUNNEL EXIT: {:?}", e),
+        Ok(None) => log::warn!("TUNNEL MANAGER GONE BEFORE EXIT"),
+        Err(_) => log::warn!("TUNNEL EXIT TIMEOUT"),
+    }
+}
+
+pub fn req_view_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
 
     let w_replic = w.clone();
     let sx_replic = sx.clone();
-    let rx_replic = rx.clone();
 
     w.listen("req-view-tunnel-info", move |e| {
 
         let sx_replic = sx_replic.clone();
-        let rx_replic = rx_replic.clone();
         let w_replic = w_replic.clone();
 
         task::spawn(async move {
             let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    let tc = TunnelControl {
-                        tunnel: req.data,
-                        operator: TunnelOperator::VIEW,
-                    };
-
-                    sx_replic.send(tc).await.unwrap_or_default();
-                    match rx_replic.lock().await.recv().await {
+                    match request(&sx_replic, req.data, TunnelOperator::VIEW).await {
                         Some(Ok(e)) => {
                             println!("tunnel: {:?}", e);
                             Response{status: String::from("success"), data: Some(e), err: None}
                         },
                         Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
-                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(0, "no data!!!"))},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
                     }
                 },
                 Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
@@ -58,35 +80,27 @@ pub fn req_view_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsRecei
     });
 }
 
-pub fn req_register_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
+pub fn req_register_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
 
     let w_replic = w.clone();
     let sx_replic = sx.clone();
-    let rx_replic = rx.clone();
 
     w.listen("req-register-tunnel-info", move |e| {
 
         let sx_replic = sx_replic.clone();
-        let rx_replic = rx_replic.clone();
         let w_replic = w_replic.clone();
 
         task::spawn(async move {
             let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    let tc = TunnelControl {
-                        tunnel: req.data,
-                        operator: TunnelOperator::ADD,
-                    };
-
-                    sx_replic.send(tc).await.unwrap_or_default();
-                    match rx_replic.lock().await.recv().await {
+                    match request(&sx_replic, req.data, TunnelOperator::ADD).await {
                         Some(Ok(e)) => {
                             println!("tunnel: {:?}", e);
                             Response{status: String::from("success"), data: Some(e), err: None}
                         },
                         Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
-                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(0, "no data!!!"))},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
                     }
                 },
                 Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
@@ -96,35 +110,27 @@ pub fn req_register_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsR
     });
 }
 
-pub fn req_sync_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
+pub fn req_sync_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
 
     let w_replic = w.clone();
     let sx_replic = sx.clone();
-    let rx_replic = rx.clone();
 
     w.listen("req-sync-tunnel-info", move |e| {
 
         let sx_replic = sx_replic.clone();
-        let rx_replic = rx_replic.clone();
         let w_replic = w_replic.clone();
 
         task::spawn(async move {
             let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    let tc = TunnelControl {
-                        tunnel: req.data,
-                        operator: TunnelOperator::SYNC,
-                    };
-
-                    sx_replic.send(tc).await.unwrap_or_default();
-                    match rx_replic.lock().await.recv().await {
+                    match request(&sx_replic, req.data, TunnelOperator::SYNC).await {
                         Some(Ok(e)) => {
                             println!("tunnel: {:?}", e);
                             Response{status: String::from("success"), data: Some(e), err: None}
                         },
                         Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
-                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(0, "no data!!!"))},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
                     }
                 },
                 Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
@@ -134,35 +140,27 @@ pub fn req_sync_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsRecei
     });
 }
 
-pub fn req_delete_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
+pub fn req_delete_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
 
     let w_replic = w.clone();
     let sx_replic = sx.clone();
-    let rx_replic = rx.clone();
 
     w.listen("req-delete-tunnel-info", move |e| {
 
         let sx_replic = sx_replic.clone();
-        let rx_replic = rx_replic.clone();
         let w_replic = w_replic.clone();
 
         task::spawn(async move {
             let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    let tc = TunnelControl {
-                        tunnel: req.data,
-                        operator: TunnelOperator::DELETE,
-                    };
-
-                    sx_replic.send(tc).await.unwrap_or_default();
-                    match rx_replic.lock().await.recv().await {
+                    match request(&sx_replic, req.data, TunnelOperator::DELETE).await {
                         Some(Ok(e)) => {
                             println!("tunnel: {:?}", e);
                             Response{status: String::from("success"), data: Some(e), err: None}
                         },
                         Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
-                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(0, "no data!!!"))},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
                     }
                 },
                 Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
@@ -172,35 +170,30 @@ pub fn req_delete_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsRec
     });
 }
 
-pub fn req_state_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsReceiver) {
+pub fn req_state_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
 
     let w_replic = w.clone();
     let sx_replic = sx.clone();
-    let rx_replic = rx.clone();
 
     w.listen("req-state-tunnel-info", move |e| {
 
         let sx_replic = sx_replic.clone();
-        let rx_replic = rx_replic.clone();
         let w_replic = w_replic.clone();
 
         task::spawn(async move {
             let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
             let rs = match req {
                 Ok(req) => {
-                    let tc = TunnelControl {
-                        tunnel: req.data,
-                        operator: TunnelOperator::STATE,
-                    };
-
-                    sx_replic.send(tc).await.unwrap_or_default();
-                    match rx_replic.lock().await.recv().await {
+                    match request(&sx_replic, req.data, TunnelOperator::STATE).await {
                         Some(Ok(e)) => {
                             println!("tunnel: {:?}", e);
                             Response{status: String::from("success"), data: Some(e), err: None}
                         },
-                        Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
-                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(0, "no data!!!"))},
+                        Some(Err(e)) => {
+                            prompt_hostkey(&w_replic, &e);
+                            Response{status: String::from("failure"), data: None, err: Some(e.convert())}
+                        },
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
                     }
                 },
                 Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
@@ -208,4 +201,320 @@ pub fn req_state_tunnel(w: &Window, sx: &Sender<TunnelControl>, rx: &TunnelsRece
             w_replic.emit("resp-state-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
         });
     });
+}
+
+pub fn req_clients_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
+
+    let w_replic = w.clone();
+    let sx_replic = sx.clone();
+
+    w.listen("req-clients-tunnel-info", move |e| {
+
+        let sx_replic = sx_replic.clone();
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs: Response<Vec<TunnelClient>> = match req {
+                Ok(req) => {
+                    match request(&sx_replic, req.data, TunnelOperator::CLIENTS).await {
+                        Some(Ok(e)) => {
+                            let clients = e.into_iter().next().map(|tunnel| tunnel.clients).unwrap_or_default();
+                            Response{status: String::from("success"), data: Some(clients), err: None}
+                        },
+                        Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
+                    }
+                },
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-clients-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_kill_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
+
+    let w_replic = w.clone();
+    let sx_replic = sx.clone();
+
+    w.listen("req-kill-tunnel-client", move |e| {
+
+        let sx_replic = sx_replic.clone();
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<TunnelClientKill>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs: Response<Vec<TunnelClient>> = match req {
+                Ok(req) => {
+                    let (tunnel, operator) = match req.data {
+                        Some(kill) => (Some(kill.tunnel), TunnelOperator::KILL(kill.id)),
+                        None => (None, TunnelOperator::KILL(None)),
+                    };
+
+                    match request(&sx_replic, tunnel, operator).await {
+                        Some(Ok(e)) => {
+                            let clients = e.into_iter().next().map(|tunnel| tunnel.clients).unwrap_or_default();
+                            Response{status: String::from("success"), data: Some(clients), err: None}
+                        },
+                        Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
+                    }
+                },
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-kill-tunnel-client", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+// every member of the group with the outcome of its own start or stop
+pub fn req_group_tunnel(w: &Window, sx: &Sender<TunnelControl>) {
+
+    let w_replic = w.clone();
+    let sx_replic = sx.clone();
+
+    w.listen("req-group-tunnel-info", move |e| {
+
+        let sx_replic = sx_replic.clone();
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<TunnelGroupControl>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs = match req {
+                Ok(Request { data: Some(control), .. }) => {
+                    match request(&sx_replic, None, TunnelOperator::GROUP(control.name, control.status)).await {
+                        Some(Ok(e)) => Response{status: String::from("success"), data: Some(e), err: None},
+                        Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                        None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
+                    }
+                },
+                Ok(_) => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Validation, "GROUP IS REQUIRED!!!"))},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-group-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_view_tunnel_group(w: &Window, sx: &Sender<TunnelControl>) {
+
+    let w_replic = w.clone();
+    let sx_replic = sx.clone();
+
+    w.listen("req-view-tunnel-group", move |_| {
+
+        let sx_replic = sx_replic.clone();
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let rs: Response<Vec<TunnelGroup>> = match request(&sx_replic, None, TunnelOperator::VIEW).await {
+                Some(Ok(e)) => Response{status: String::from("success"), data: Some(group::aggregate(&e)), err: None},
+                Some(Err(e)) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                None => Response{status: String::from("failure"), data: None, err: Some(EventError::build(ErrorKind::Internal, "no data!!!"))},
+            };
+            w_replic.emit("resp-view-tunnel-group", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_test_tunnel(w: &Window, prompter: &Prompter) {
+
+    let w_replic = w.clone();
+    let prompter_replic = prompter.clone();
+
+    w.listen("req-test-tunnel-info", move |e| {
+
+        let w_replic = w_replic.clone();
+        let prompter_replic = prompter_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<Tunnel>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs = match req {
+                Ok(Request{data: Some(tunnel)}) => {
+                    let report = test(&tunnel, &prompter_replic).await;
+                    if let Some(prompt) = &report.hostkey {
+                        w_replic.emit("prompt-hostkey-tunnel-info", serde_json::to_string(prompt).unwrap()).unwrap();
+                    }
+                    Response{status: String::from(if report.success {"success"} else {"failure"}), data: Some(report), err: None}
+                },
+                Ok(_) => Response{status: String::from("failure"), data: None, err: Some(EventError::missing("tunnel"))},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-test-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+// unknown or changed host keys are handed to the ui, which pins them through req-trust-hostkey-info
+fn prompt_hostkey(w: &Window, e: &PluginError) {
+    if let Some(prompt) = HostKeyPrompt::from_error(e) {
+        w.emit("prompt-hostkey-tunnel-info", serde_json::to_string(&prompt).unwrap()).unwrap();
+    }
+}
+
+fn hostkey_response(rs: TunnelResult<Vec<HostKeyEntry>>) -> Response<Vec<HostKeyEntry>> {
+    match rs {
+        Ok(entries) => Response{status: String::from("success"), data: Some(entries), err: None},
+        Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+    }
+}
+
+pub fn req_view_hostkey(w: &Window) {
+
+    let w_replic = w.clone();
+
+    w.listen("req-view-hostkey-info", move |_| {
+
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let rs = hostkey_response(known_hosts::list());
+            w_replic.emit("resp-view-hostkey-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_remove_hostkey(w: &Window) {
+
+    let w_replic = w.clone();
+
+    w.listen("req-remove-hostkey-info", move |e| {
+
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<HostKeyEntry>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs = match req {
+                Ok(Request{data: Some(hostkey)}) => hostkey_response(known_hosts::remove(hostkey.host.as_str())),
+                Ok(_) => Response{status: String::from("failure"), data: None, err: Some(EventError::missing("hostkey"))},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-remove-hostkey-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_trust_hostkey(w: &Window) {
+
+    let w_replic = w.clone();
+
+    w.listen("req-trust-hostkey-info", move |e| {
+
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<HostKeyPrompt>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs = match req {
+                Ok(Request{data: Some(hostkey)}) => hostkey_response(known_hosts::trust(&hostkey)),
+                Ok(_) => Response{status: String::from("failure"), data: None, err: Some(EventError::missing("hostkey"))},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-trust-hostkey-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+pub fn req_view_agent_identity(w: &Window) {
+
+    let w_replic = w.clone();
+
+    w.listen("req-view-agent-identity-info", move |_| {
+
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let rs: Response<Vec<AgentIdentity>> = match agent::identities() {
+                Ok(identities) => Response{status: String::from("success"), data: Some(identities), err: None},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-view-agent-identity-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+// the candidates only, the ui registers what the user picked through req-register-tunnel-info
+pub fn req_import_tunnel(w: &Window) {
+
+    let w_replic = w.clone();
+
+    w.listen("req-import-tunnel-info", move |e| {
+
+        let w_replic = w_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<String>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs: Response<ImportPreview> = match req {
+                Ok(req) => match ssh_config::import(req.data.as_deref()) {
+                    Ok(preview) => Response{status: String::from("success"), data: Some(preview), err: None},
+                    Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                },
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-import-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
+}
+
+// keyboard-interactive challenges go to every window, whichever answers first wins
+pub fn push_auth_prompt(w: &Window, prompter: &Prompter) {
+
+    let w_replic = w.clone();
+    let prompter_replic = prompter.clone();
+
+    task::spawn(async move {
+        while let Some(prompt) = prompter_replic.next().await {
+            if let Err(e) = w_replic.emit_all("prompt-auth-tunnel-info", serde_json::to_string(&prompt).unwrap()) {
+                log::warn!("AUTH PROMPT EMIT: {:?}", e);
+            }
+        }
+    });
+}
+
+pub fn push_tunnel_notice(w: &Window, notices: &TunnelNoticeReceiver) {
+
+    let w_replic = w.clone();
+    let notices_replic = notices.clone();
+
+    task::spawn(async move {
+        loop {
+            let notice = notices_replic.lock().await.recv().await;
+            let notice = match notice {
+                Some(notice) => notice,
+                None => break,
+            };
+            let event = match notice.kind {
+                TunnelNoticeKind::Metrics => "notice-metrics-tunnel-info",
+                _ => "notice-state-tunnel-info",
+            };
+            if let Err(e) = w_replic.emit_all(event, serde_json::to_string(&notice).unwrap()) {
+                log::warn!("TUNNEL NOTICE EMIT: {:?}", e);
+            }
+        }
+    });
+}
+
+pub fn req_answer_auth(w: &Window, prompter: &Prompter) {
+
+    let w_replic = w.clone();
+    let prompter_replic = prompter.clone();
+
+    w.listen("req-answer-auth-tunnel-info", move |e| {
+
+        let w_replic = w_replic.clone();
+        let prompter_replic = prompter_replic.clone();
+
+        task::spawn(async move {
+            let req: Result<Request<Option<AuthAnswer>>, SerdeError> = serde_json::from_str(e.payload().unwrap());
+            let rs: Response<bool> = match req {
+                Ok(Request{data: Some(answer)}) => match prompter_replic.answer(answer) {
+                    Ok(_) => Response{status: String::from("success"), data: Some(true), err: None},
+                    Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+                },
+                Ok(_) => Response{status: String::from("failure"), data: None, err: Some(EventError::missing("answer"))},
+                Err(e) => Response{status: String::from("failure"), data: None, err: Some(e.convert())},
+            };
+            w_replic.emit("resp-answer-auth-tunnel-info", serde_json::to_string(&rs).unwrap()).unwrap()
+        });
+    });
 }
\ No newline at end of file
@@ -3,9 +3,11 @@
   windows_subsystem = "windows"
 )]
 
-use leviathan::event::ssh_tunnel::tunnel_init;
+use leviathan::event::ssh_tunnel::{tunnel_exit, tunnel_init};
 use leviathan::event::bind_ssh_tunnel;
 use leviathan::event::bind_cql_event;
+use leviathan::plugins::datax::cassandra::PeerCache;
+use leviathan::plugins::ssh_tunnel::link::TunnelLink;
 use leviathan::init_log;
 use tokio;
 
@@ -14,13 +16,20 @@ async fn main() {
 
   init_log().await;
 
-  let (sx, rx) = tunnel_init().await;
+  let (sx, prompter, notices) = tunnel_init().await;
   let sx_replic = sx.clone();
+  let link = TunnelLink::new(sx.clone());
+  let peers = PeerCache::default();
   
   tauri::Builder::default().on_page_load(move |w, _| {
-    bind_cql_event(&w);
-    bind_ssh_tunnel(&w, &sx_replic, &rx);
+    bind_cql_event(&w, &link, &peers);
+    bind_ssh_tunnel(&w, &sx_replic, &prompter, &notices);
     println!("OK");
-  }).run(tauri::generate_context!())
-  .expect("error while running tauri application");
+  }).build(tauri::generate_context!())
+  .expect("error while running tauri application")
+  .run(move |_, event| {
+    if let tauri::Event::Exit = event {
+      tunnel_exit(&sx);
+    }
+  });
 }
\ No newline at end of file
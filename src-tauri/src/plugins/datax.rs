@@ -70,6 +70,8 @@ pub struct BaseInfo<'a> {
     pub host: &'a str,
     pub username: &'a str,
     pub password: &'a str,
+    // `host:port` of the other nodes queries are spread over besides the contact point, none to stick to it
+    pub nodes: &'a [String],
 }
 
 impl Serialize for FieldType {
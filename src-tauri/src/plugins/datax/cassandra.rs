@@ -3,18 +3,227 @@ mod api;
 use cdrs_tokio::Error as CdrsError;
 
 use super::super::error::ConvertToPluginError;
-use super::super::error::Error as PluginError;
+use super::super::error::{Error as PluginError, ErrorKind};
+use super::super::ssh_tunnel::link::TunnelLink;
+use super::super::ssh_tunnel::targets::TunnelTarget;
+use cdrs_tokio::frame::frame_error::AdditionalErrorInfo;
 use super::{BaseInfo, Field};
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
 use serde_json::Value;
+use tokio::io::{self as async_io, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
 use api::SelectCQL as ApiSelectCQL;
 
 pub type CQLResult<T> = super::super::PluginResult<T>;
 pub type SelectCQL<'a> = ApiSelectCQL<'a>;
 
+// an OPTIONS request, protocol v4, stream 0 and no body. whatever answers it with a response frame is a node
+const CQL_OPTIONS_FRAME: [u8; 9] = [0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00];
+const CQL_HEADER_SIZE: usize = 9;
+const CQL_RESPONSE_FLAG: u8 = 0x80;
+const CQL_PEER_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// a cached peer answered before, it is not given long to do it again
+const CQL_PEER_RECHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
 impl ConvertToPluginError for CdrsError {
-    fn convert(&self) -> PluginError {
-        PluginError::build(-3, self.to_string().as_str())
+    fn convert(self) -> PluginError {
+        match self {
+            CdrsError::Io(e) => e.convert(),
+            CdrsError::Server(e) => {
+                let kind = match e.additional_info {
+                    AdditionalErrorInfo::Authentication(_) | AdditionalErrorInfo::Unauthorized(_) => ErrorKind::Auth,
+                    AdditionalErrorInfo::Unavailable(_)
+                    | AdditionalErrorInfo::Overloaded(_)
+                    | AdditionalErrorInfo::IsBootstrapping(_) => ErrorKind::Unavailable,
+                    AdditionalErrorInfo::ReadTimeout(_) | AdditionalErrorInfo::WriteTimeout(_) => ErrorKind::Timeout,
+                    AdditionalErrorInfo::Syntax(_) => ErrorKind::QuerySyntax,
+                    AdditionalErrorInfo::Invalid(_)
+                    | AdditionalErrorInfo::AlreadyExists(_)
+                    | AdditionalErrorInfo::Config(_) => ErrorKind::Schema,
+                    _ => ErrorKind::Internal,
+                };
+                PluginError::build(kind, e.message.as_str())
+                    .with_context("cql_code", e.error_code.to_string().as_str())
+            },
+            CdrsError::General(message) => PluginError::build(ErrorKind::Internal, message.as_str()),
+            e => {
+                let message = e.to_string();
+                PluginError::build(ErrorKind::Internal, message.as_str()).with_source(e)
+            },
+        }
+    }
+}
+
+// a profile linked to a tunnel reaches the cluster through the tunnel's local end, the tunnel is brought up
+// when it is down and its failure is reported as such instead of running into the connection timeout
+pub async fn contact_point(host: &str, port: i32, tunnel: Option<&str>, link: &TunnelLink) -> CQLResult<(String, i32)> {
+    let id = match tunnel.filter(|id| !id.is_empty()) {
+        Some(id) => id,
+        None => return Ok((String::from(host), port)),
+    };
+    let endpoint = link.endpoint(id).await?;
+    log::info!("CASSANDRA {}:{} THROUGH TUNNEL {} AT {}", host, port, id, endpoint);
+    Ok((endpoint.ip().to_string(), endpoint.port() as i32))
+}
+
+// a forward is set up no matter whether the ssh server gets through to the other side, a peer is only
+// handed to the driver once it answered through it. the driver would wait out its connect timeout on it otherwise.
+// the whole answer is read before hanging up, the tunnel would complain about a client gone mid-write
+async fn answers(endpoint: SocketAddr, timeout: Duration) -> CQLResult<()> {
+    let check = async {
+        let mut stream = TcpStream::connect(endpoint).await?;
+        stream.write_all(&CQL_OPTIONS_FRAME).await?;
+        let mut header = [0; CQL_HEADER_SIZE];
+        stream.read_exact(&mut header).await?;
+        let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        async_io::copy(&mut (&mut stream).take(u64::from(length)), &mut async_io::sink()).await?;
+        Ok::<u8, std::io::Error>(header[0])
+    };
+    match time::timeout(timeout, check).await {
+        Ok(Ok(version)) if version & CQL_RESPONSE_FLAG != 0 => Ok(()),
+        Ok(Ok(_)) => Err(PluginError::build(ErrorKind::Connection, "NOT A CASSANDRA NODE!!!")),
+        Ok(Err(e)) => Err(PluginError::build(ErrorKind::Connection, "CASSANDRA PEER NOT REACHABLE!!!").with_source(e)),
+        Err(_) => Err(PluginError::build(ErrorKind::Timeout, "CASSANDRA PEER DID NOT ANSWER!!!")),
+    }
+}
+
+// a peer of the cluster and the loopback endpoint of the forward the tunnel keeps for it
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    address: IpAddr,
+    port: u16,
+    endpoint: SocketAddr,
+}
+
+impl Node {
+    fn forwarded(&self, targets: &[TunnelTarget]) -> bool {
+        targets.iter().any(|target| target.host == self.address.to_string() && target.port == self.port
+            && target.bound_port == self.endpoint.port())
+    }
+}
+
+async fn unforward(address: IpAddr, port: u16, tunnel: &str, link: &TunnelLink) {
+    if let Err(e) = link.unforward(tunnel, address.to_string().as_str(), port).await {
+        log::warn!("CASSANDRA PEER {}:{} FORWARD NOT CLOSED: {}", address, port, e);
+    }
+}
+
+// a peer that does not answer has its forward closed again, it would only pile up otherwise
+async fn translate(address: IpAddr, port: u16, tunnel: &str, link: &TunnelLink) -> CQLResult<Node> {
+    let endpoint = link.forward(tunnel, address.to_string().as_str(), port).await?;
+    if let Err(e) = answers(endpoint, CQL_PEER_CHECK_TIMEOUT).await {
+        unforward(address, port, tunnel, link).await;
+        return Err(e.with_context("endpoint", endpoint.to_string().as_str()));
+    }
+    log::info!("CASSANDRA PEER {}:{} THROUGH TUNNEL {} AT {}", address, port, tunnel, endpoint);
+    Ok(Node { address, port, endpoint })
+}
+
+// the other nodes of a cluster behind a tunnel as the driver gets to them, each through a forward of its own.
+// `system.peers` only tells their private addresses, which nothing but the ssh server reaches.
+// `bi` is the contact point through the tunnel and `port` the profile's, the native port of a peer that does
+// not tell its own. a peer that can not be reached is left out, the contact point alone still does
+async fn translate_peers<'a>(bi: &BaseInfo<'a>, port: i32, tunnel: &str, link: &TunnelLink) -> CQLResult<Vec<Node>> {
+    let peers = api::acquire_peers(bi).await.map_err(|e| e.convert())?;
+    let checks: Vec<_> = peers.into_iter().map(|(address, native_port)| {
+        let tunnel = String::from(tunnel);
+        let link = link.clone();
+        tokio::spawn(async move {
+            let port = native_port.unwrap_or(port) as u16;
+            translate(address, port, &tunnel, &link).await
+                .map_err(|e| e.with_context("peer", format!("{}:{}", address, port).as_str()))
+        })
+    }).collect();
+    let mut nodes = Vec::new();
+    for check in checks {
+        match check.await {
+            Ok(Ok(node)) => nodes.push(node),
+            Ok(Err(e)) => log::warn!("CASSANDRA PEER LEFT OUT: {} {:?}", e, e.context),
+            Err(e) => log::warn!("CASSANDRA PEER CHECK: {:?}", e),
+        }
+    }
+    Ok(nodes)
+}
+
+// the driver connects to every node it is given before the first query, one cached peer that went down
+// meanwhile would fail each request until the next discovery
+async fn all_answer(nodes: &[Node]) -> bool {
+    let checks: Vec<_> = nodes.iter().map(|node| tokio::spawn(answers(node.endpoint, CQL_PEER_RECHECK_TIMEOUT))).collect();
+    for check in checks {
+        if !matches!(check.await, Ok(Ok(()))) {
+            return false;
+        }
+    }
+    true
+}
+
+// the nodes found behind a tunnel for each contact point, so `system.peers` is not gone through again on every
+// request, the peers only get a quick check. they are found anew once a request fails, a peer does not answer or
+// the tunnel let go of one of their forwards, all of them go when it restarts
+#[derive(Clone, Default)]
+pub struct PeerCache {
+    peers: Arc<StdMutex<HashMap<(String, String), Peers>>>,
+}
+
+// `stale` after a failed request, the nodes are still what the next discovery is compared with
+#[derive(Clone)]
+struct Peers {
+    nodes: Vec<Node>,
+    stale: bool,
+}
+
+impl PeerCache {
+    fn cached(&self, key: &(String, String)) -> Option<Peers> {
+        self.peers.lock().ok().and_then(|peers| peers.get(key).cloned())
+    }
+
+    // the endpoints of the nodes besides `contact`, the profile's contact point behind `tunnel`
+    pub async fn nodes<'a>(&self, bi: &BaseInfo<'a>, contact: &str, port: i32, tunnel: &str, link: &TunnelLink) -> Vec<String> {
+        let key = (String::from(tunnel), String::from(contact));
+        let cached = self.cached(&key);
+        if let Some(cached) = cached.as_ref().filter(|cached| !cached.stale) {
+            match link.view(tunnel).await {
+                Ok(t) if cached.nodes.iter().all(|node| node.forwarded(&t.targets)) => {
+                    if all_answer(&cached.nodes).await {
+                        return cached.nodes.iter().map(|node| node.endpoint.to_string()).collect();
+                    }
+                    log::info!("CASSANDRA PEERS OF {} NOT ALL ANSWERING THROUGH TUNNEL {}", contact, tunnel);
+                },
+                Ok(_) => log::info!("CASSANDRA PEERS OF {} LOST THEIR FORWARDS THROUGH TUNNEL {}", contact, tunnel),
+                Err(e) => log::warn!("CASSANDRA PEERS OF {} NOT CHECKED: {}", contact, e),
+            }
+        }
+
+        let nodes = match translate_peers(bi, port, tunnel, link).await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                log::warn!("CASSANDRA PEERS NOT DISCOVERED: {}", e);
+                return Vec::new();
+            },
+        };
+        // the ones the cluster does not tell of any more
+        let known = cached.map(|cached| cached.nodes).unwrap_or_default();
+        for gone in known.iter().filter(|node| !nodes.iter().any(|found| found.address == node.address && found.port == node.port)) {
+            unforward(gone.address, gone.port, tunnel, link).await;
+        }
+        let endpoints = nodes.iter().map(|node| node.endpoint.to_string()).collect();
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.insert(key, Peers { nodes, stale: false });
+        }
+        endpoints
+    }
+
+    // after a failed request, the cluster may have changed under it
+    pub fn forget(&self, tunnel: &str, contact: &str) {
+        if let Ok(mut peers) = self.peers.lock() {
+            if let Some(cached) = peers.get_mut(&(String::from(tunnel), String::from(contact))) {
+                cached.stale = true;
+            }
+        }
     }
 }
 
@@ -65,4 +274,180 @@ pub async fn view_detail_data<'a>(bi: &BaseInfo<'a>, scql: &SelectCQL<'a>) -> CQ
         Ok(rs) => Ok(rs),
         Err(e) => Err(e.convert()),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use crate::plugins::ssh_tunnel::{Tunnel, TunnelControl, TunnelOperator, TunnelState};
+    use serde_json::json;
+    use std::net::{Ipv4Addr, TcpListener as StdTcpListener};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::channel;
+
+    type Shared<T> = Arc<StdMutex<T>>;
+
+    const NATIVE_PORT: u16 = 9142;
+
+    // a node answering every query for `system.peers_v2` with `peers`, on native port 9142 each,
+    // and counting them in `discoveries`. without peers or for anything else it is asked it answers an error
+    async fn cql_stand_in(peers: Shared<Option<Vec<IpAddr>>>, discoveries: Shared<usize>) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_cql(stream, peers.clone(), discoveries.clone()));
+            }
+        });
+        port
+    }
+
+    async fn serve_cql(mut stream: TcpStream, peers: Shared<Option<Vec<IpAddr>>>, discoveries: Shared<usize>) -> std::io::Result<()> {
+        loop {
+            let mut header = [0; CQL_HEADER_SIZE];
+            stream.read_exact(&mut header).await?;
+            let mut body = vec![0; u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize];
+            stream.read_exact(&mut body).await?;
+            let known = peers.lock().unwrap().clone();
+            let (opcode, answer) = match (header[4], known) {
+                // STARTUP, OPTIONS and REGISTER
+                (0x01, _) => (0x02, Vec::new()),
+                (0x05, _) => (0x06, vec![0, 0]),
+                (0x0B, _) => (0x08, vec![0, 0, 0, 1]),
+                (0x07, Some(known)) if String::from_utf8_lossy(&body).contains("system.peers_v2") => {
+                    *discoveries.lock().unwrap() += 1;
+                    (0x08, peer_rows(&known))
+                },
+                _ => (0x00, [&0x2000_i32.to_be_bytes()[..], &cql_string("unsupported")].concat()),
+            };
+            let mut frame = vec![0x84, 0, header[2], header[3], opcode];
+            frame.extend_from_slice(&(answer.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&answer);
+            stream.write_all(&frame).await?;
+        }
+    }
+
+    fn cql_string(s: &str) -> Vec<u8> {
+        [&(s.len() as u16).to_be_bytes()[..], s.as_bytes()].concat()
+    }
+
+    fn cql_bytes(b: &[u8]) -> Vec<u8> {
+        [&(b.len() as i32).to_be_bytes()[..], b].concat()
+    }
+
+    fn peer_rows(peers: &[IpAddr]) -> Vec<u8> {
+        let mut rows = [2_i32, 1, 3].iter().flat_map(|i| i.to_be_bytes().to_vec()).collect::<Vec<_>>();
+        rows.extend([cql_string("system"), cql_string("peers_v2")].concat());
+        for (name, kind) in [("peer", 0x10_u16), ("native_address", 0x10), ("native_port", 0x09)].iter() {
+            rows.extend(cql_string(name));
+            rows.extend_from_slice(&kind.to_be_bytes());
+        }
+        rows.extend_from_slice(&(peers.len() as i32).to_be_bytes());
+        for peer in peers {
+            let address = match peer {
+                IpAddr::V4(ip) => ip.octets().to_vec(),
+                IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            rows.extend(cql_bytes(&address));
+            rows.extend(cql_bytes(&address));
+            rows.extend(cql_bytes(&i32::from(NATIVE_PORT).to_be_bytes()));
+        }
+        rows
+    }
+
+    // a port nothing listens on
+    fn closed_port() -> u16 {
+        StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0)).and_then(|listener| listener.local_addr()).expect("bind").port()
+    }
+
+    // the tunnel manager as far as the link goes: tunnel `t` runs, a forward to one of `reachable` leads to its
+    // port and one to any other host to a port nothing listens on
+    fn manager(reachable: HashMap<IpAddr, u16>) -> (TunnelLink, Shared<Vec<TunnelTarget>>) {
+        let targets: Shared<Vec<TunnelTarget>> = Arc::default();
+        let (sx, mut rx) = channel::<TunnelControl>(16);
+        let replica = targets.clone();
+        tokio::spawn(async move {
+            while let Some(control) = rx.recv().await {
+                let mut targets = replica.lock().unwrap();
+                match control.operator {
+                    TunnelOperator::FORWARD(host, port) if !targets.iter().any(|target| target.host == host && target.port == port) => {
+                        let bound_port = host.parse().ok().and_then(|ip| reachable.get(&ip).copied()).unwrap_or_else(closed_port);
+                        targets.push(TunnelTarget { host, port, bound_port });
+                    },
+                    TunnelOperator::UNFORWARD(host, port) => targets.retain(|target| target.host != host || target.port != port),
+                    _ => {},
+                }
+                let t = Tunnel {
+                    targets: targets.clone(),
+                    ..tunnel(json!({ "id": "t", "status": TunnelState::RUNNING }))
+                };
+                let _ = control.reply.send(Ok(vec![t]));
+            }
+        });
+        (TunnelLink::new(sx), targets)
+    }
+
+    fn forwarded(targets: &Shared<Vec<TunnelTarget>>) -> Vec<String> {
+        targets.lock().unwrap().iter().map(|target| target.host.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn peers_are_found_once_and_kept_to_the_cluster() {
+        let ip = |last: u8| IpAddr::from([10, 0, 0, last]);
+        let peers = Arc::new(StdMutex::new(Some(vec![ip(2), ip(3), ip(4)])));
+        let discoveries = Arc::new(StdMutex::new(0));
+        let contact = cql_stand_in(peers.clone(), discoveries.clone()).await;
+        let second = cql_stand_in(peers.clone(), discoveries.clone()).await;
+        let third = cql_stand_in(peers.clone(), discoveries.clone()).await;
+        // 10.0.0.4 is told of but the ssh server does not get through to it
+        let (link, targets) = manager(vec![(ip(2), second), (ip(3), third)].into_iter().collect());
+        let endpoint = |port: u16| format!("127.0.0.1:{}", port);
+        let bi = BaseInfo { port: i32::from(contact), host: "127.0.0.1", username: "u", password: "p", nodes: &[] };
+        let cache = PeerCache::default();
+        let nodes = || cache.nodes(&bi, "10.0.0.1:9142", 9142, "t", &link);
+
+        assert_eq!(nodes().await, vec![endpoint(second), endpoint(third)]);
+        assert_eq!(forwarded(&targets), vec!["10.0.0.2", "10.0.0.3"], "the forward to the dead peer is closed");
+        assert_eq!(*discoveries.lock().unwrap(), 1);
+
+        assert_eq!(nodes().await, vec![endpoint(second), endpoint(third)]);
+        assert_eq!(*discoveries.lock().unwrap(), 1, "taken from the cache");
+
+        // 10.0.0.3 went down, its forward is still there
+        let down = closed_port();
+        targets.lock().unwrap().iter_mut().filter(|target| target.host == "10.0.0.3").for_each(|target| target.bound_port = down);
+        assert_eq!(nodes().await, vec![endpoint(second)]);
+        assert_eq!(forwarded(&targets), vec!["10.0.0.2"], "the forward to the peer down is closed");
+        assert_eq!(*discoveries.lock().unwrap(), 2, "found again once a peer did not answer");
+
+        // a restart takes every forward along
+        targets.lock().unwrap().clear();
+        assert_eq!(nodes().await, vec![endpoint(second), endpoint(third)]);
+        assert_eq!(*discoveries.lock().unwrap(), 3, "found again after a restart");
+
+        // 10.0.0.3 left the cluster and the next request failed
+        *peers.lock().unwrap() = Some(vec![ip(2), ip(4)]);
+        assert_eq!(nodes().await, vec![endpoint(second), endpoint(third)], "a cluster change alone goes unnoticed");
+        cache.forget("t", "10.0.0.1:9142");
+        assert_eq!(nodes().await, vec![endpoint(second)]);
+        assert_eq!(forwarded(&targets), vec!["10.0.0.2"], "the forward to the peer gone is closed");
+        assert_eq!(*discoveries.lock().unwrap(), 4);
+
+        // a discovery that fails leaves what was found last to be compared with
+        cache.forget("t", "10.0.0.1:9142");
+        *peers.lock().unwrap() = None;
+        assert_eq!(nodes().await, Vec::<String>::new());
+        assert_eq!(forwarded(&targets), vec!["10.0.0.2"]);
+        *peers.lock().unwrap() = Some(Vec::new());
+        assert_eq!(nodes().await, Vec::<String>::new());
+        assert_eq!(forwarded(&targets), Vec::<String>::new());
+        assert_eq!(*discoveries.lock().unwrap(), 5);
+
+        // each contact point and tunnel on its own
+        cache.forget("t", "10.0.0.9:9142");
+        *peers.lock().unwrap() = Some(vec![ip(2)]);
+        assert_eq!(cache.nodes(&bi, "10.0.0.9:9142", 9142, "t", &link).await, vec![endpoint(second)]);
+        assert_eq!(nodes().await, Vec::<String>::new(), "the other contact point is still cached");
+    }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 use cdrs_tokio::authenticators::StaticPasswordAuthenticatorProvider;
 use cdrs_tokio::cluster::{ClusterTcpConfig, NodeTcpConfigBuilder, session::new as new_session};
@@ -155,18 +156,45 @@ impl IntoField for CDRSType {
 
 const CASSANDRA_CONNECTION_TIMEOUT: Duration = Duration::from_secs(55);
 
+// the contact point and the nodes `bi` knows of besides it. cdrs-tokio 5 routes by load balancing alone, it
+// has no token-aware strategy, so a query goes to whichever node is next rather than a replica of its partition
 async fn create_session<'a, LB>(bi: &BaseInfo<'a>, load_balancing: LB) -> cdrs_tokio::Result<cdrs_tokio::cluster::session::Session<LB>>
 where LB: LoadBalancingStrategy<TcpConnectionPool> {
     let addr = String::from(bi.host);
     let addr = addr + &":" + format!("{}", bi.port).as_str();
-    let node = NodeTcpConfigBuilder::new(addr.as_str()
-        , Arc::new(StaticPasswordAuthenticatorProvider::new(bi.username, bi.password)))
-        .connection_timeout(CASSANDRA_CONNECTION_TIMEOUT)
-        .build();
-    let cluster_config = ClusterTcpConfig(vec![node]);
+    let authenticator = Arc::new(StaticPasswordAuthenticatorProvider::new(bi.username, bi.password));
+    let nodes = std::iter::once(&addr).chain(bi.nodes.iter())
+        .map(|addr| NodeTcpConfigBuilder::new(addr.as_str(), authenticator.clone())
+            .connection_timeout(CASSANDRA_CONNECTION_TIMEOUT)
+            .build())
+        .collect();
+    let cluster_config = ClusterTcpConfig(nodes);
     Ok(new_session(&cluster_config, load_balancing, Box::new(DefaultRetryPolicy::default())).await?)
 }
 
+// the other nodes the contact point knows of and the address they take CQL on. `system.peers_v2` has their
+// native port since 4.0, before that they are taken to listen on the contact point's.
+// a node bound to every interface tells 0.0.0.0, the address it gossips on is the one to use then
+pub async fn acquire_peers<'a>(bi: &BaseInfo<'a>) -> cdrs_tokio::Result<Vec<(IpAddr, Option<i32>)>> {
+    let s = create_session(bi, RoundRobin::default()).await?;
+    let mut peers = Vec::new();
+    let (rows, address_column, port_column) = match s.query("select peer, native_address, native_port from system.peers_v2").await {
+        Ok(r) => (r.body()?.into_rows(), "native_address", Some("native_port")),
+        Err(_) => (s.query("select peer, rpc_address from system.peers").await?.body()?.into_rows(), "rpc_address", None),
+    };
+    for row in rows.unwrap_or_default().iter() {
+        let address = IpAddr::from_cdrs_by_name(row, address_column)?.filter(|address| !address.is_unspecified());
+        let port = match port_column {
+            Some(column) => i32::from_cdrs_by_name(row, column)?,
+            None => None,
+        };
+        if let Some(address) = address.or(IpAddr::from_cdrs_by_name(row, "peer")?) {
+            peers.push((address, port));
+        }
+    }
+    Ok(peers)
+}
+
 pub async fn acquire_keyspace<'a>(bi: &BaseInfo<'a>) -> cdrs_tokio::Result<Vec<String>> {
     let s = create_session(bi, RoundRobin::default()).await?;
     let  r = s.query("select keyspace_name from system_schema.keyspaces").await?.body()?;
@@ -1,23 +1,133 @@
 use serde_json::Error as SerdeError;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug)]
+pub type ErrorSource = Arc<dyn StdError + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Connection,
+    Auth,
+    Timeout,
+    Schema,
+    QuerySyntax,
+    Unavailable,
+    Tunnel,
+    Validation,
+    Internal,
+}
+
+#[derive(Debug, Clone)]
 pub struct Error {
-    pub code: i32,
+    pub kind: ErrorKind,
     pub message: String,
+    pub retryable: bool,
+    pub context: Vec<(String, String)>,
+    pub source: Option<ErrorSource>,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Connection => "connection",
+            ErrorKind::Auth => "auth",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Schema => "schema",
+            ErrorKind::QuerySyntax => "query-syntax",
+            ErrorKind::Unavailable => "unavailable",
+            ErrorKind::Tunnel => "tunnel",
+            ErrorKind::Validation => "validation",
+            ErrorKind::Internal => "internal",
+        }
+    }
+
+    // stable numeric code kept for callers that still switch on `code`
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::Connection => 1001,
+            ErrorKind::Auth => 1002,
+            ErrorKind::Timeout => 1003,
+            ErrorKind::Schema => 1004,
+            ErrorKind::QuerySyntax => 1005,
+            ErrorKind::Unavailable => 1006,
+            ErrorKind::Tunnel => 1007,
+            ErrorKind::Validation => 1008,
+            ErrorKind::Internal => 1000,
+        }
+    }
+
+    pub fn retryable(&self) -> bool {
+        matches!(self, ErrorKind::Connection | ErrorKind::Timeout | ErrorKind::Unavailable)
+    }
 }
 
 impl Error {
-    pub fn build(code: i32, message: &str) -> Error {
-        Error { code: (code), message: (String::from(message)) }
+    pub fn build(kind: ErrorKind, message: &str) -> Error {
+        Error {
+            kind,
+            message: String::from(message),
+            retryable: kind.retryable(),
+            context: Vec::new(),
+            source: None,
+        }
+    }
+
+    pub fn with_source<E>(mut self, source: E) -> Error
+    where E: StdError + Send + Sync + 'static {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    pub fn with_context(mut self, key: &str, value: &str) -> Error {
+        self.context.push((String::from(key), String::from(value)));
+        self
+    }
+
+    pub fn with_retryable(mut self, retryable: bool) -> Error {
+        self.retryable = retryable;
+        self
+    }
+
+    pub fn code(&self) -> i32 {
+        self.kind.code()
+    }
+
+    pub fn causes(&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        let mut source = self.source.as_ref().map(|e| e.as_ref() as &(dyn StdError + 'static));
+        while let Some(e) = source {
+            causes.push(e.to_string());
+            source = e.source();
+        }
+        causes
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind.as_str(), self.message)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as &(dyn StdError + 'static))
     }
 }
 
 pub trait ConvertToPluginError {
-    fn convert(&self) -> Error;
+    fn convert(self) -> Error;
+}
+
+impl ConvertToPluginError for Error {
+    fn convert(self) -> Error {
+        self
+    }
 }
 
 impl ConvertToPluginError for SerdeError {
-    fn convert(&self) -> Error {
-        Error { code: (-1), message: (self.to_string()) }
+    fn convert(self) -> Error {
+        Error::build(ErrorKind::Validation, self.to_string().as_str()).with_source(self)
     }
 }
\ No newline at end of file
@@ -1,25 +1,163 @@
+pub mod agent;
 pub mod api;
+pub mod bind;
+pub mod cert;
+pub mod clients;
+pub mod forward;
+pub mod group;
+pub mod interactive;
+pub mod keys;
+pub mod known_hosts;
+pub mod link;
+pub mod metrics;
+pub mod probe;
+pub mod proxy;
 pub mod server;
+pub mod session;
+pub mod socks;
+pub mod ssh_config;
+pub mod targets;
 
 use serde::{Serialize, Deserialize};
 use std::cmp::PartialEq;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::{channel, error::{SendError, TrySendError}, Receiver, Sender};
+use tokio::sync::oneshot;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, Ordering};
 use tokio::sync::Mutex;
 use std::result::Result;
 use super::error::Error as PluginError;
-use super::error::ConvertToPluginError;
-use std::io::Error as IOError;
+use super::error::{ConvertToPluginError, ErrorKind};
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+use ssh2::ErrorCode as SSH2ErrorCode;
+use self::interactive::Prompter;
+use self::cert::CertificateInfo;
+use self::clients::{TunnelClient, TunnelClients};
+use self::metrics::{TunnelMetrics, TunnelMetricsSnapshot};
+use self::targets::{TunnelTarget, TunnelTargets};
 
 pub type SSH2Error = ssh2::Error;
 pub type TunnelResult<T> = Result<T, PluginError>;
 pub type TunnelStateReceiver = Arc<Mutex<Receiver<TunnelResult<TunnelState>>>>;
-pub type TunnelsReceiver = Arc<Mutex<Receiver<TunnelResult<Vec<Tunnel>>>>>;
+pub type TunnelReply = oneshot::Sender<TunnelResult<Vec<Tunnel>>>;
+pub type TunnelEventReceiver = Receiver<(String, TunnelEvent)>;
+pub type TunnelNoticeReceiver = Arc<Mutex<Receiver<TunnelNotice>>>;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum  TunnelState {
+    #[default]
     STOP,
     RUNNING,
+    CONNECTING,
+    RECONNECTING,
+    FAILED,
+}
+
+impl TunnelState {
+    // a tunnel in one of these has no task behind it and can be started, changed or deleted
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, TunnelState::STOP | TunnelState::FAILED)
+    }
+}
+
+// Local: our listener forwards to `remote_host:remote_port`, Dynamic: our listener speaks SOCKS5 and
+// every client names its own destination, Remote: the server listens on `remote_host:remote_port`
+// and its connections come back to `local_host:local_port`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelKind {
+    #[default]
+    Local,
+    Dynamic,
+    Remote,
+}
+
+#[derive(Debug, Clone)]
+pub enum TunnelEvent {
+    // how the start went, the listener, the probe and for a remote tunnel the server's forward are behind it
+    Started(TunnelResult<()>),
+    Error(PluginError),
+    State(TunnelState),
+    // a client connection was handed to the forwarder, with its peer address
+    Connected(String),
+    Closed(String),
+}
+
+// every task of one tunnel reports to the manager through this, tagged with the tunnel's id.
+// the counters travel along and are updated in place, they change far too often for the channel
+#[derive(Debug, Clone)]
+pub struct TunnelEventSender {
+    id: String,
+    sx: Sender<(String, TunnelEvent)>,
+    metrics: Arc<TunnelMetrics>,
+    clients: Arc<TunnelClients>,
+    bound_port: Arc<AtomicU16>,
+}
+
+impl TunnelEventSender {
+    pub fn new(id: String, sx: Sender<(String, TunnelEvent)>) -> TunnelEventSender {
+        TunnelEventSender {
+            id, sx,
+            metrics: Arc::new(TunnelMetrics::default()),
+            clients: Arc::new(TunnelClients::default()),
+            bound_port: Arc::new(AtomicU16::new(0)),
+        }
+    }
+
+    // the port the running tunnel actually listens on, 0 once it is down
+    pub fn bound(&self, port: u16) {
+        self.bound_port.store(port, Ordering::Relaxed);
+    }
+
+    pub fn bound_port(&self) -> Option<u16> {
+        Some(self.bound_port.load(Ordering::Relaxed)).filter(|port| *port != 0)
+    }
+
+    pub fn metrics(&self) -> &TunnelMetrics {
+        &self.metrics
+    }
+
+    pub fn clients(&self) -> &TunnelClients {
+        &self.clients
+    }
+
+    pub fn try_send(&self, event: TunnelEvent) -> Result<(), TrySendError<(String, TunnelEvent)>> {
+        self.sx.try_send((self.id.clone(), event))
+    }
+
+    // for an event that must not get lost, the manager takes them out all the time
+    pub async fn send(&self, event: TunnelEvent) -> Result<(), SendError<(String, TunnelEvent)>> {
+        self.sx.send((self.id.clone(), event)).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelNoticeKind {
+    Started,
+    Stopped,
+    Connected,
+    Closed,
+    Error,
+    // any other change of the status, reconnecting and back to running
+    State,
+    // the periodic snapshot of a running tunnel's counters
+    Metrics,
+}
+
+// what the manager pushes to every window whenever a tunnel changes
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelNotice {
+    pub id: String,
+    pub local_port: u16,
+    pub bound_port: Option<u16>,
+    pub kind: TunnelNoticeKind,
+    pub status: TunnelState,
+    pub peer: Option<String>,
+    pub message: Option<String>,
+    pub failures: u64,
+    pub metrics: TunnelMetricsSnapshot,
+    pub at: i64, //ms
 }
 
 pub enum TunnelOperator {
@@ -28,11 +166,25 @@ pub enum TunnelOperator {
     DELETE,
     SYNC,
     STATE,
+    // the live client connections of one tunnel
+    CLIENTS,
+    // drops one client connection of a tunnel by id, all of them without
+    KILL(Option<u64>),
+    // starts (RUNNING) or stops (STOP) every member of the named group, one after the other
+    GROUP(String, TunnelState),
+    // a loopback port of a running local tunnel reaching another `host:port` behind its ssh server
+    FORWARD(String, u16),
+    // gives up the loopback port FORWARD opened to `host:port`, clients already on it keep going
+    UNFORWARD(String, u16),
     EXIT,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// `local_port` 0 listens on whatever port is free, `bound_port` tells which one while it runs,
+// the server side one for a remote tunnel
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Tunnel {
+    #[serde(default)]
+    pub id: String,
     pub local_port: u16,
     pub remote_port: u16,
     pub remote_host: String,
@@ -40,8 +192,146 @@ pub struct Tunnel {
     pub ssh_host: String,
     pub username: String,
     pub password: Option<String>,
+    // the path of a key file or the key itself, OpenSSH, PEM, PKCS#8 or PuTTY
     pub private_key: Option<String>,
+    // unlocks an encrypted `private_key`, `password` does when it is not set
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    // an OpenSSH user certificate for `private_key`, its path or the line itself, `<key>-cert.pub` when not set
+    #[serde(default)]
+    pub certificate: Option<String>,
     pub status: TunnelState,
+    #[serde(default)]
+    pub kind: TunnelKind,
+    #[serde(default)]
+    pub socks_auth: Option<SocksAuth>,
+    #[serde(default)]
+    pub local_host: Option<String>,
+    #[serde(default)]
+    pub bind_address: Option<String>,
+    #[serde(default)]
+    pub bind_wildcard: bool,
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    #[serde(default)]
+    pub max_channels: Option<u32>,
+    #[serde(default)]
+    pub keepalive_interval: Option<u32>,
+    #[serde(default)]
+    pub keepalive_count_max: Option<u32>,
+    #[serde(default)]
+    pub reconnect_attempts: Option<u32>,
+    #[serde(default)]
+    pub reconnect_delay: Option<u64>,
+    #[serde(default)]
+    pub drain_timeout: Option<u32>,
+    #[serde(default)]
+    pub system_known_hosts: bool,
+    #[serde(default)]
+    pub agent: bool,
+    #[serde(default)]
+    pub agent_identity: Option<String>,
+    #[serde(default)]
+    pub interactive: bool,
+    #[serde(default)]
+    pub jump_hosts: Vec<JumpHost>,
+    // how the first ssh host of the chain is reached, directly when not set
+    #[serde(default)]
+    pub proxy: Option<TunnelProxy>,
+    #[serde(default)]
+    pub group: Option<String>,
+    // ids of the tunnels that have to run before this one can start, a jump tunnel it connects through
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_deserializing)]
+    pub bound_port: Option<u16>,
+    #[serde(default, skip_deserializing)]
+    pub failures: u64,
+    #[serde(default, skip_deserializing)]
+    pub last_error: Option<String>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub certificate_info: Option<CertificateInfo>,
+    #[serde(default, skip_deserializing)]
+    pub metrics: TunnelMetricsSnapshot,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub clients: Vec<TunnelClient>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<TunnelTarget>,
+}
+
+// credentials a SOCKS5 client has to present to a dynamic tunnel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocksAuth {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    Http,
+    Socks5,
+    // a ProxyCommand, its stdin and stdout carry the ssh session
+    Command,
+}
+
+// an upstream proxy in front of the first ssh host, `host` and `port` for HTTP CONNECT and SOCKS5,
+// `command` with the %h, %p and %r tokens of ssh(1) for a ProxyCommand
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelProxy {
+    pub kind: ProxyKind,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+// a bastion passed on the way to `ssh_host`, in the order they are traversed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JumpHost {
+    pub ssh_host: String,
+    pub ssh_port: u16,
+    pub username: String,
+    pub password: Option<String>,
+    pub private_key: Option<String>,
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    #[serde(default)]
+    pub certificate: Option<String>,
+    #[serde(default)]
+    pub agent: bool,
+    #[serde(default)]
+    pub agent_identity: Option<String>,
+    #[serde(default)]
+    pub interactive: bool,
+}
+
+impl Tunnel {
+    // every ssh login of the chain as a tunnel of its own, the jump hosts first and this tunnel last
+    pub fn hops(&self) -> Vec<Tunnel> {
+        let mut hops: Vec<Tunnel> = self.jump_hosts.iter().map(|jump| Tunnel {
+            ssh_host: jump.ssh_host.clone(),
+            ssh_port: jump.ssh_port,
+            username: jump.username.clone(),
+            password: jump.password.clone(),
+            private_key: jump.private_key.clone(),
+            passphrase: jump.passphrase.clone(),
+            certificate: jump.certificate.clone(),
+            agent: jump.agent,
+            agent_identity: jump.agent_identity.clone(),
+            interactive: jump.interactive,
+            jump_hosts: Vec::new(),
+            ..self.clone()
+        }).collect();
+        hops.push(Tunnel { jump_hosts: Vec::new(), ..self.clone() });
+        hops
+    }
 }
 
 impl Serialize for TunnelState {
@@ -51,6 +341,9 @@ impl Serialize for TunnelState {
         match self {
             TunnelState::STOP => serializer.serialize_str("stop"),
             TunnelState::RUNNING => serializer.serialize_str("run"),
+            TunnelState::CONNECTING => serializer.serialize_str("connecting"),
+            TunnelState::RECONNECTING => serializer.serialize_str("reconnecting"),
+            TunnelState::FAILED => serializer.serialize_str("failed"),
         }
     }
 }
@@ -62,6 +355,9 @@ impl <'de> Deserialize<'de> for TunnelState {
         match String::deserialize(deserializer)?.as_str() {
             "run" => Ok(TunnelState::RUNNING),
             "stop" => Ok(TunnelState::STOP),
+            "connecting" => Ok(TunnelState::CONNECTING),
+            "reconnecting" => Ok(TunnelState::RECONNECTING),
+            "failed" => Ok(TunnelState::FAILED),
             _ => Ok(TunnelState::STOP),
         }
     }
@@ -73,33 +369,178 @@ pub struct TunnelWrapper {
     pub rx: TunnelStateReceiver,
     pub sx: Sender<TunnelResult<TunnelState>>,
     pub sx_dest: Option<Sender<TunnelState>>,
+    pub event_sx: TunnelEventSender,
+    pub failures: u64,
+    pub last_error: Option<PluginError>,
+    pub prompter: Prompter,
+    pub targets: TunnelTargets,
+    // the certificate as of the last add, sync or start
+    pub certificate_info: Option<CertificateInfo>,
 }
 
 impl TunnelWrapper {
-    pub fn from_tunnel(tunnel: Tunnel) -> TunnelWrapper {
+    pub fn from_tunnel(tunnel: Tunnel, prompter: Prompter, events: Sender<(String, TunnelEvent)>) -> TunnelWrapper {
         let (sx, rx) = channel::<TunnelResult<TunnelState>>(1024);
+        let event_sx = TunnelEventSender::new(tunnel.id.clone(), events);
+        let certificate_info = cert::inspect(&tunnel);
 
         TunnelWrapper {
             tunnel,
             sx, rx: Arc::new(Mutex::new(rx)),
             sx_dest: None,
+            event_sx,
+            failures: 0,
+            last_error: None,
+            prompter,
+            targets: TunnelTargets::default(),
+            certificate_info,
+        }
+    }
+
+    // a new definition of the same tunnel, its certificate is read again
+    pub fn redefine(&mut self, tunnel: Tunnel) {
+        self.certificate_info = cert::inspect(&tunnel);
+        self.tunnel = tunnel;
+    }
+
+    pub fn notice(&self, kind: TunnelNoticeKind, peer: Option<String>, message: Option<String>) -> TunnelNotice {
+        TunnelNotice {
+            id: self.tunnel.id.clone(),
+            local_port: self.tunnel.local_port,
+            bound_port: self.event_sx.bound_port(),
+            kind,
+            status: self.tunnel.status.clone(),
+            peer,
+            message,
+            failures: self.failures,
+            metrics: self.event_sx.metrics().snapshot(self.failures),
+            at: chrono::Utc::now().timestamp_millis(),
         }
     }
+
+    pub fn view(&self) -> Tunnel {
+        let mut tunnel = self.tunnel.clone();
+        tunnel.bound_port = self.event_sx.bound_port();
+        tunnel.failures = self.failures;
+        tunnel.last_error = self.last_error.as_ref().map(|e| e.to_string());
+        tunnel.metrics = self.event_sx.metrics().snapshot(self.failures);
+        tunnel.certificate_info = self.certificate_info.clone().map(|info| info.at(chrono::Utc::now().timestamp_millis()));
+        tunnel.targets = self.targets.list();
+        tunnel
+    }
+
+    pub fn view_clients(&self) -> Tunnel {
+        let mut tunnel = self.view();
+        tunnel.clients = self.event_sx.clients().list();
+        tunnel
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 pub struct TunnelControl {
     pub tunnel: Option<Tunnel>,
     pub operator: TunnelOperator,
+    pub reply: TunnelReply,
+}
+
+// every request gets its answer on a channel of its own, whoever else talks to the manager meanwhile.
+// `None` once the manager is gone
+pub async fn request(sx: &Sender<TunnelControl>, tunnel: Option<Tunnel>, operator: TunnelOperator) -> Option<TunnelResult<Vec<Tunnel>>> {
+    let (reply, answer) = oneshot::channel();
+    sx.send(TunnelControl { tunnel, operator, reply }).await.ok()?;
+    answer.await.ok()
 }
 
+// libssh2 session error codes, see libssh2.h
+const LIBSSH2_ERROR_BANNER_RECV: i32 = -2;
+const LIBSSH2_ERROR_SOCKET_SEND: i32 = -7;
+const LIBSSH2_ERROR_TIMEOUT: i32 = -9;
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: i32 = -13;
+const LIBSSH2_ERROR_PASSWORD_EXPIRED: i32 = -15;
+const LIBSSH2_ERROR_FILE: i32 = -16;
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: i32 = -18;
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: i32 = -19;
+const LIBSSH2_ERROR_CHANNEL_FAILURE: i32 = -21;
+const LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED: i32 = -22;
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: i32 = -30;
+const LIBSSH2_ERROR_REQUEST_DENIED: i32 = -32;
+const LIBSSH2_ERROR_METHOD_NOT_SUPPORTED: i32 = -33;
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+const LIBSSH2_ERROR_SOCKET_RECV: i32 = -43;
+const LIBSSH2_ERROR_KEYFILE_AUTH_FAILED: i32 = -48;
+
 impl ConvertToPluginError for SSH2Error {
-    fn convert(&self) -> PluginError {
-        PluginError::build(-2, self.message())
+    fn convert(self) -> PluginError {
+        let kind = match self.code() {
+            SSH2ErrorCode::Session(code) => match code {
+                LIBSSH2_ERROR_AUTHENTICATION_FAILED
+                | LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED
+                | LIBSSH2_ERROR_PASSWORD_EXPIRED
+                | LIBSSH2_ERROR_METHOD_NOT_SUPPORTED
+                | LIBSSH2_ERROR_KEYFILE_AUTH_FAILED => ErrorKind::Auth,
+                LIBSSH2_ERROR_TIMEOUT | LIBSSH2_ERROR_SOCKET_TIMEOUT => ErrorKind::Timeout,
+                LIBSSH2_ERROR_BANNER_RECV
+                | LIBSSH2_ERROR_SOCKET_SEND
+                | LIBSSH2_ERROR_SOCKET_RECV
+                | LIBSSH2_ERROR_SOCKET_DISCONNECT => ErrorKind::Connection,
+                LIBSSH2_ERROR_CHANNEL_FAILURE
+                | LIBSSH2_ERROR_CHANNEL_REQUEST_DENIED
+                | LIBSSH2_ERROR_REQUEST_DENIED => ErrorKind::Tunnel,
+                LIBSSH2_ERROR_FILE => ErrorKind::Validation,
+                _ => ErrorKind::Internal,
+            },
+            SSH2ErrorCode::SFTP(_) => ErrorKind::Internal,
+        };
+        let code = format!("{}", self.code());
+        PluginError::build(kind, self.message())
+            .with_context("ssh_code", code.as_str())
+            .with_source(self)
     }
 }
 
 impl ConvertToPluginError for IOError {
-    fn convert(&self) -> PluginError {
-        PluginError::build(-2, self.to_string().as_str())
+    fn convert(self) -> PluginError {
+        let kind = match self.kind() {
+            IOErrorKind::ConnectionRefused
+            | IOErrorKind::ConnectionReset
+            | IOErrorKind::ConnectionAborted
+            | IOErrorKind::NotConnected
+            | IOErrorKind::AddrNotAvailable
+            | IOErrorKind::BrokenPipe
+            | IOErrorKind::UnexpectedEof => ErrorKind::Connection,
+            IOErrorKind::TimedOut => ErrorKind::Timeout,
+            IOErrorKind::AddrInUse | IOErrorKind::PermissionDenied => ErrorKind::Tunnel,
+            IOErrorKind::NotFound | IOErrorKind::InvalidInput => ErrorKind::Validation,
+            _ => ErrorKind::Internal,
+        };
+        PluginError::build(kind, self.to_string().as_str()).with_source(self)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Tunnel;
+    use serde_json::{json, Value};
+
+    // a stopped local tunnel to 127.0.0.1 over 127.0.0.1:22, `overrides` sets whatever else a test needs
+    pub fn tunnel(overrides: Value) -> Tunnel {
+        let mut value = json!({
+            "local_port": 0,
+            "remote_port": 22,
+            "remote_host": "127.0.0.1",
+            "ssh_port": 22,
+            "ssh_host": "127.0.0.1",
+            "username": "u",
+            "password": null,
+            "private_key": null,
+            "status": "stop",
+        });
+        if let (Some(value), Value::Object(overrides)) = (value.as_object_mut(), overrides) {
+            value.extend(overrides);
+        }
+        serde_json::from_value(value).expect("tunnel")
     }
 }
\ No newline at end of file
@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+use ssh2::{Agent, PublicKey, Session};
+use std::env;
+
+use super::{to_hex, PluginError, Tunnel, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+const SSH_AUTH_SOCK: &str = "SSH_AUTH_SOCK";
+
+// `id` is the hex encoded key blob, that is what a Tunnel stores in `agent_identity`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentIdentity {
+    pub id: String,
+    pub key_type: String,
+    pub comment: String,
+}
+
+// the blob starts with the key type as an ssh string
+fn key_type(blob: &[u8]) -> String {
+    let len = match blob.get(..4) {
+        Some(len) => u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize,
+        None => return String::from("unknown"),
+    };
+    match blob.get(4..4 + len) {
+        Some(name) => String::from_utf8_lossy(name).into_owned(),
+        None => String::from("unknown"),
+    }
+}
+
+fn open(s: &Session) -> TunnelResult<Agent> {
+    if cfg!(unix) && env::var_os(SSH_AUTH_SOCK).is_none() {
+        return Err(PluginError::build(ErrorKind::Auth, "SSH_AUTH_SOCK IS NOT SET!!!").with_retryable(false));
+    }
+    let mut agent = s.agent().map_err(|e| e.convert())?;
+    agent.connect().map_err(|e| PluginError::build(ErrorKind::Auth, "SSH AGENT UNREACHABLE!!!").with_source(e))?;
+    agent.list_identities().map_err(|e| e.convert())?;
+    Ok(agent)
+}
+
+fn keys(agent: &Agent) -> TunnelResult<Vec<PublicKey>> {
+    agent.identities().map_err(|e| e.convert())
+}
+
+fn identity(key: &PublicKey) -> AgentIdentity {
+    AgentIdentity {
+        id: to_hex(key.blob()),
+        key_type: key_type(key.blob()),
+        comment: String::from(key.comment()),
+    }
+}
+
+fn close(mut agent: Agent) {
+    if let Err(e) = agent.disconnect() {
+        log::warn!("SSH AGENT DISCONNECT: {:?}", e);
+    }
+}
+
+// the identity picked for the tunnel goes first, the rest of the agent follows in its own order
+fn arrange(mut keys: Vec<PublicKey>, t: &Tunnel) -> TunnelResult<Vec<PublicKey>> {
+    if let Some(id) = t.agent_identity.as_ref().filter(|id| !id.is_empty()) {
+        match keys.iter().position(|key| to_hex(key.blob()) == *id) {
+            Some(i) => keys[..=i].rotate_right(1),
+            None => return Err(PluginError::build(ErrorKind::Auth, "AGENT IDENTITY NOT FOUND!!!").with_retryable(false)),
+        }
+    }
+    Ok(keys)
+}
+
+pub fn identities() -> TunnelResult<Vec<AgentIdentity>> {
+    let s = Session::new().map_err(|e| e.convert())?;
+    let agent = open(&s)?;
+    let identities = keys(&agent)?.iter().map(identity).collect();
+    close(agent);
+    Ok(identities)
+}
+
+// the identities in the order `authenticate` offers them for the tunnel
+pub fn candidates(t: &Tunnel) -> TunnelResult<Vec<AgentIdentity>> {
+    let s = Session::new().map_err(|e| e.convert())?;
+    let agent = open(&s)?;
+    let keys = keys(&agent).and_then(|keys| arrange(keys, t));
+    close(agent);
+    Ok(keys?.iter().map(identity).collect())
+}
+
+pub fn authenticate(s: &Session, t: &Tunnel) -> TunnelResult<()> {
+    let agent = open(s)?;
+    let keys = arrange(keys(&agent)?, t)?;
+    if keys.is_empty() {
+        return Err(PluginError::build(ErrorKind::Auth, "NO AGENT IDENTITIES!!!").with_retryable(false));
+    }
+
+    let mut last = None;
+    for key in keys.iter() {
+        match agent.userauth(t.username.as_str(), key) {
+            Ok(_) => {
+                log::info!("SSH AGENT IDENTITY ACCEPTED: {}", key.comment());
+                last = None;
+                break;
+            },
+            Err(e) => {
+                log::info!("SSH AGENT IDENTITY REJECTED: {} {:?}", key.comment(), e);
+                last = Some(e);
+            },
+        }
+    }
+    close(agent);
+    match last {
+        None => Ok(()),
+        Some(e) => Err(e.convert()
+            .with_context("agent_identities", keys.len().to_string().as_str())),
+    }
+}
\ No newline at end of file
@@ -1,170 +1,697 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use tokio::select;
 use tokio::task;
 use tokio::sync::mpsc::{Receiver, Sender, channel};
 use tokio::time;
+use super::bind::shares_address;
+use super::group::{start_order, stop_order};
+use super::interactive::Prompter;
 use super::server::poll;
 use super::PluginError;
+use crate::plugins::error::ErrorKind;
 use std::time::Duration;
 
-use super::{Tunnel, TunnelControl, TunnelWrapper, TunnelOperator, TunnelState, TunnelResult};
+use super::{Tunnel, TunnelControl, TunnelEvent, TunnelEventReceiver, TunnelKind, TunnelNotice, TunnelNoticeKind, TunnelReply, TunnelWrapper, TunnelOperator, TunnelState, TunnelResult};
 
-type TunnelWrapperMap = HashMap<u16, TunnelWrapper>;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// what a stop may take beyond the drain, to close the listener and log out
+const STOP_GRACE: Duration = Duration::from_secs(5);
 
-pub async fn run(sx: Sender<TunnelResult<Vec<Tunnel>>>, mut rx: Receiver<TunnelControl>) {
-    let mut tunnels = HashMap::<u16, TunnelWrapper>::new();
+type TunnelWrapperMap = HashMap<String, TunnelWrapper>;
+
+// who waits for a starting tunnel, the one that asked to start it alone or the group it is started with
+enum Starting {
+    Tunnel(TunnelReply),
+    Group(GroupRun),
+}
+
+type StartingMap = HashMap<String, Starting>;
+
+// a group going through its members one after the other, `order` are the ones still to go
+struct GroupRun {
+    name: String,
+    order: VecDeque<String>,
+    results: Vec<Tunnel>,
+    reply: TunnelReply,
+}
+
+pub async fn run(mut rx: Receiver<TunnelControl>, prompter: Prompter, notices: Sender<TunnelNotice>) {
+    let mut tunnels = HashMap::<String, TunnelWrapper>::new();
+    let mut starting = StartingMap::new();
+    let (event_sx, mut event_rx) = channel::<(String, TunnelEvent)>(1024);
+    let mut tick = time::interval(HEALTH_CHECK_INTERVAL);
 
     loop {
-        let mut tick = time::interval(Duration::from_secs(60));
         select! {
             tc = rx.recv() => {
-                let tc = match tc {
+                let TunnelControl { tunnel, operator, reply } = match tc {
                     Some(tc) => tc,
-                    None => {
-                        tokio::task::yield_now().await;
-                        continue;
-                    },
+                    None => break,
                 };
 
-                let rs = match tc.operator {
-                    TunnelOperator::VIEW => Ok(make_tunels(&tunnels).await),
-                    TunnelOperator::ADD => add_tunnel(tc, &mut tunnels).await,
-                    TunnelOperator::DELETE => delete_tunnel(tc, &mut tunnels).await,
-                    TunnelOperator::SYNC => change_tunnel(tc, &mut tunnels).await,
-                    TunnelOperator::STATE => state_tunnel(tc, &mut tunnels).await,
-                    TunnelOperator::EXIT => break,
+                // whatever the tunnels reported so far goes into the answer
+                event_drain(&mut event_rx, &mut tunnels, &mut starting, &notices);
+
+                let exit = matches!(operator, TunnelOperator::EXIT);
+                let rs = match operator {
+                    TunnelOperator::VIEW => Ok(make_tunels(&tunnels)),
+                    TunnelOperator::ADD => add_tunnel(tunnel, &mut tunnels, &prompter, &event_sx).await,
+                    TunnelOperator::DELETE => delete_tunnel(tunnel, &mut tunnels).await,
+                    TunnelOperator::SYNC => change_tunnel(tunnel, &mut tunnels).await,
+                    // a start is answered once the tunnel came up or gave up, the manager goes on meanwhile
+                    TunnelOperator::STATE => {
+                        state_tunnel(tunnel, reply, &mut tunnels, &mut starting, &notices).await;
+                        continue;
+                    },
+                    TunnelOperator::CLIENTS => list_clients(tunnel, &tunnels),
+                    TunnelOperator::KILL(id) => kill_clients(tunnel, id, &tunnels),
+                    TunnelOperator::GROUP(name, state) => {
+                        group_tunnels(name, state, reply, &mut tunnels, &mut starting, &notices).await;
+                        continue;
+                    },
+                    TunnelOperator::FORWARD(host, port) => forward_target(tunnel, host, port, &tunnels),
+                    TunnelOperator::UNFORWARD(host, port) => unforward_target(tunnel, host, port, &tunnels),
+                    TunnelOperator::EXIT => exit_tunnels(&mut tunnels, &notices).await,
                 };
-                sx.send(rs).await.unwrap();
+                answer(reply, rs);
+                if exit {
+                    break;
+                }
+            },
+            event = event_rx.recv() => {
+                if let Some((id, event)) = event {
+                    event_apply(id, event, &mut tunnels, &mut starting, &notices);
+                }
             },
             _ = tick.tick() => {
-                state_check(& mut tunnels).await
+                state_check(&mut tunnels, &notices);
+                metrics_check(&tunnels, &notices);
             }
         }
     }
 }
 
-async fn state_check(tunnels: &mut TunnelWrapperMap) {
-    for (_, wrapper) in tunnels.iter_mut() {
-        match wrapper.rx.try_lock() {
+// the one asking may have given up waiting
+fn answer(reply: TunnelReply, rs: TunnelResult<Vec<Tunnel>>) {
+    if reply.send(rs).is_err() {
+        log::warn!("TUNNEL ANSWER DROPPED");
+    }
+}
+
+fn publish(notices: &Sender<TunnelNotice>, notice: TunnelNotice) {
+    if let Err(e) = notices.try_send(notice) {
+        log::warn!("TUNNEL NOTICE DROPPED: {}", e);
+    }
+}
+
+// a running tunnel only answers by itself once it is over, FAILED when it could not be kept up
+fn state_check(tunnels: &mut TunnelWrapperMap, notices: &Sender<TunnelNotice>) {
+    for wrapper in tunnels.values_mut() {
+        let rs = match wrapper.rx.try_lock() {
             Err(_) => continue,
-            Ok(mut mutex) => {
-                match mutex.try_recv() {
-                    Err(_) => continue,
-                    _ => wrapper.tunnel.status = TunnelState::STOP,
-                }
+            Ok(mut mutex) => match mutex.try_recv() {
+                Err(_) => continue,
+                Ok(rs) => rs,
+            },
+        };
+        let message = match rs {
+            Ok(state) => {
+                wrapper.tunnel.status = state;
+                wrapper.last_error.as_ref().map(|e| e.to_string())
+            },
+            Err(e) => {
+                let message = e.to_string();
+                wrapper.last_error = Some(e);
+                wrapper.tunnel.status = TunnelState::STOP;
+                Some(message)
+            },
+        };
+        publish(notices, wrapper.notice(TunnelNoticeKind::Stopped, None, message));
+    }
+}
+
+// one snapshot per running tunnel and tick, the ui draws its charts from the series
+fn metrics_check(tunnels: &TunnelWrapperMap, notices: &Sender<TunnelNotice>) {
+    for wrapper in tunnels.values().filter(|wrapper| !wrapper.tunnel.status.is_stopped()) {
+        publish(notices, wrapper.notice(TunnelNoticeKind::Metrics, None, None));
+    }
+}
+
+fn event_drain(events: &mut TunnelEventReceiver, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    while let Ok((id, event)) = events.try_recv() {
+        event_apply(id, event, tunnels, starting, notices);
+    }
+}
+
+fn event_apply(id: String, event: TunnelEvent, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    // the tunnel may have been deleted while its last connections were closing
+    let wrapper = match tunnels.get_mut(&id) {
+        Some(wrapper) => wrapper,
+        None => return,
+    };
+    let notice = match event {
+        TunnelEvent::Started(rs) => return start_done(id, rs, tunnels, starting, notices),
+        TunnelEvent::Error(e) => {
+            log::warn!("TUNNEL ERROR {}: {}", id, e);
+            let message = e.to_string();
+            wrapper.failures += 1;
+            wrapper.last_error = Some(e);
+            wrapper.notice(TunnelNoticeKind::Error, None, Some(message))
+        },
+        // a late change of a tunnel that was stopped meanwhile is of no interest
+        TunnelEvent::State(state) => {
+            if wrapper.tunnel.status.is_stopped() {
+                return;
             }
+            log::info!("TUNNEL STATE {}: {:?}", id, state);
+            wrapper.tunnel.status = state;
+            wrapper.notice(TunnelNoticeKind::State, None, None)
+        },
+        TunnelEvent::Connected(peer) => wrapper.notice(TunnelNoticeKind::Connected, Some(peer), None),
+        TunnelEvent::Closed(peer) => wrapper.notice(TunnelNoticeKind::Closed, Some(peer), None),
+    };
+    publish(notices, notice);
+}
+
+// the outcome of a start, whoever waited for it is answered or its group goes on with the next member
+fn start_done(id: String, rs: TunnelResult<()>, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    if let Some(wrapper) = tunnels.get_mut(&id) {
+        match rs.as_ref() {
+            Ok(()) => {
+                wrapper.tunnel.status = TunnelState::RUNNING;
+                publish(notices, wrapper.notice(TunnelNoticeKind::Started, None, None));
+            },
+            // there is nothing to stop, the grid tells it apart from a tunnel that was stopped
+            Err(e) => {
+                wrapper.tunnel.status = TunnelState::FAILED;
+                wrapper.last_error = Some(e.clone());
+                publish(notices, wrapper.notice(TunnelNoticeKind::Error, None, Some(e.to_string())));
+            },
         }
     }
+    match starting.remove(&id) {
+        Some(Starting::Tunnel(reply)) => answer(reply, rs.map(|_| make_tunels(tunnels))),
+        Some(Starting::Group(mut group)) => {
+            group.done(&id, rs, tunnels);
+            group_start(group, tunnels, starting, notices);
+        },
+        None => {},
+    }
 }
 
-async fn make_tunels(tunnel_map: &TunnelWrapperMap) -> Vec<Tunnel> {
+fn make_tunels(tunnel_map: &TunnelWrapperMap) -> Vec<Tunnel> {
     let mut tunnels = Vec::new();
     for elem in tunnel_map.values() {
-        tunnels.push(elem.tunnel.clone());
+        tunnels.push(elem.view());
     }
     tunnels
 }
 
-async fn add_tunnel(tc: TunnelControl, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
-    let tunnel = tc.tunnel.unwrap();
-    let wrapper = tunnels.get(&tunnel.local_port);
-    if let None = wrapper  {
-        let wrapper = TunnelWrapper::from_tunnel(tunnel);
-        tunnels.insert(wrapper.tunnel.local_port, wrapper);
-        Ok(make_tunels(tunnels).await)
-    } else {
-        Err(PluginError::build(-2, "DUPLICATE NODE!!!"))
+fn require_tunnel(tunnel: Option<Tunnel>) -> TunnelResult<Tunnel> {
+    match tunnel {
+        Some(tunnel) => Ok(tunnel),
+        None => Err(PluginError::build(ErrorKind::Validation, "TUNNEL IS REQUIRED!!!")),
     }
 }
 
-async fn delete_tunnel(tc: TunnelControl, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
-    let wrapper = tunnels.get(&tc.tunnel.unwrap().local_port);
+fn not_exists(tunnel: &Tunnel) -> PluginError {
+    PluginError::build(ErrorKind::Validation, "NODE DOES NOT EXISTS!!!")
+        .with_context("id", tunnel.id.as_str())
+        .with_context("local_port", tunnel.local_port.to_string().as_str())
+}
+
+// definitions may share a port as long as they never listen at the same time, that is checked on start
+async fn add_tunnel(tunnel: Option<Tunnel>, tunnels: &mut TunnelWrapperMap, prompter: &Prompter, events: &Sender<(String, TunnelEvent)>) -> TunnelResult<Vec<Tunnel>> {
+    let mut tunnel = require_tunnel(tunnel)?;
+    if tunnel.id.is_empty() {
+        tunnel.id = uuid::Uuid::new_v4().to_string();
+    }
+    if tunnels.contains_key(&tunnel.id) {
+        return Err(PluginError::build(ErrorKind::Validation, "DUPLICATE NODE!!!")
+            .with_context("id", tunnel.id.as_str()));
+    }
+    let wrapper = TunnelWrapper::from_tunnel(tunnel, prompter.clone(), events.clone());
+    tunnels.insert(wrapper.tunnel.id.clone(), wrapper);
+    Ok(make_tunels(tunnels))
+}
+
+async fn delete_tunnel(tunnel: Option<Tunnel>, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    let wrapper = tunnels.get(&tunnel.id);
     match wrapper {
-        Some(wrapper) if wrapper.tunnel.status == TunnelState::STOP => {
-            let local_port = wrapper.tunnel.local_port;
-            tunnels.remove(&local_port).unwrap();
-            Ok(make_tunels(tunnels).await)
+        Some(wrapper) if wrapper.tunnel.status.is_stopped() => {
+            tunnels.remove(&tunnel.id).unwrap();
+            Ok(make_tunels(tunnels))
         }
-        _ => Err(PluginError::build(-2, "NODE DOES NOT EXISTS!!!")),
+        _ => Err(not_exists(&tunnel)),
     }
 }
 
-async fn change_tunnel(tc: TunnelControl, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
-    let tunnel = tc.tunnel.unwrap();
-    let wrapper = tunnels.get_mut(&tunnel.local_port);
+// the id stays, everything else including the local port may change
+async fn change_tunnel(tunnel: Option<Tunnel>, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    let wrapper = tunnels.get_mut(&tunnel.id);
     match wrapper {
-        Some(wrapper) if wrapper.tunnel.status == TunnelState::STOP => {
-            wrapper.tunnel = tunnel;
-            Ok(make_tunels(tunnels).await)
+        Some(wrapper) if wrapper.tunnel.status.is_stopped() => {
+            wrapper.redefine(tunnel);
+            Ok(make_tunels(tunnels))
         },
-        _ => Err(PluginError::build(-2, "NODE DOES NOT EXISTS!!!")),
+        _ => Err(not_exists(&tunnel)),
     }
 }
 
-async fn state_tunnel(tc: TunnelControl, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+fn list_clients(tunnel: Option<Tunnel>, tunnels: &TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    match tunnels.get(&tunnel.id) {
+        Some(wrapper) => Ok(vec![wrapper.view_clients()]),
+        None => Err(not_exists(&tunnel)),
+    }
+}
 
-    let tunnel = tc.tunnel.unwrap();
+fn kill_clients(tunnel: Option<Tunnel>, id: Option<u64>, tunnels: &TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    let wrapper = match tunnels.get(&tunnel.id) {
+        Some(wrapper) => wrapper,
+        None => return Err(not_exists(&tunnel)),
+    };
+    if !wrapper.event_sx.clients().kill(id) {
+        return Err(PluginError::build(ErrorKind::Validation, "NO SUCH CLIENT!!!")
+            .with_context("tunnel", tunnel.id.as_str())
+            .with_context("id", id.unwrap_or_default().to_string().as_str()));
+    }
+    Ok(vec![wrapper.view_clients()])
+}
 
-    match tunnel.status {
-        TunnelState::RUNNING => start_tunnel(tunnel, tunnels).await,
-        TunnelState::STOP => stop_tunnel(tunnel, tunnels).await,
+// the destination came with the operator, the control is down to its tunnel
+fn forward_target(tunnel: Option<Tunnel>, host: String, port: u16, tunnels: &TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    let wrapper = match tunnels.get(&tunnel.id) {
+        Some(wrapper) => wrapper,
+        None => return Err(not_exists(&tunnel)),
+    };
+    if wrapper.tunnel.kind != TunnelKind::Local {
+        return Err(PluginError::build(ErrorKind::Validation, "ONLY A LOCAL TUNNEL FORWARDS TO OTHER TARGETS!!!")
+            .with_context("id", tunnel.id.as_str()));
     }
+    wrapper.targets.open(host.as_str(), port)
+        .map_err(|e| e.with_context("id", tunnel.id.as_str()).with_context("destination", format!("{}:{}", host, port).as_str()))?;
+    Ok(vec![wrapper.view()])
 }
 
-pub async fn start_tunnel(tunnel: Tunnel, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+// a target that is already gone is fine, it may have gone with a restart of the tunnel
+fn unforward_target(tunnel: Option<Tunnel>, host: String, port: u16, tunnels: &TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+    let tunnel = require_tunnel(tunnel)?;
+    let wrapper = match tunnels.get(&tunnel.id) {
+        Some(wrapper) => wrapper,
+        None => return Err(not_exists(&tunnel)),
+    };
+    wrapper.targets.close(host.as_str(), port);
+    Ok(vec![wrapper.view()])
+}
 
-    let wrapper = tunnels.get_mut(&tunnel.local_port);
-    match wrapper {
-        Some(wrapper) if wrapper.tunnel.status == TunnelState::STOP => {
+// `reply` of a start waits in `starting` until the tunnel came up or gave up
+async fn state_tunnel(tunnel: Option<Tunnel>, reply: TunnelReply, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    let tunnel = match require_tunnel(tunnel) {
+        Ok(tunnel) => tunnel,
+        Err(e) => return answer(reply, Err(e)),
+    };
+    let rs = match tunnel.status {
+        TunnelState::RUNNING => {
+            let id = tunnel.id.clone();
+            match start_tunnel(tunnel, tunnels, notices) {
+                Ok(()) => {
+                    starting.insert(id, Starting::Tunnel(reply));
+                    return;
+                },
+                Err(e) => Err(e),
+            }
+        },
+        TunnelState::STOP => stop_tunnel(tunnel, tunnels, notices).await,
+        _ => Err(PluginError::build(ErrorKind::Validation, "ONLY RUN OR STOP CAN BE REQUESTED!!!")),
+    };
+    answer(reply, rs);
+}
+
+// registration lets two definitions share a port, only one of them can listen on it at a time.
+// a remote tunnel has no listener of ours, its local port is where the connections go
+fn port_conflict(tunnel: &Tunnel, tunnels: &TunnelWrapperMap) -> TunnelResult<()> {
+    if tunnel.kind == TunnelKind::Remote {
+        if tunnel.local_port == 0 {
+            return Err(PluginError::build(ErrorKind::Validation, "LOCAL PORT IS REQUIRED!!!")
+                .with_context("id", tunnel.id.as_str()));
+        }
+        return Ok(());
+    }
+    if tunnel.local_port == 0 {
+        return Ok(());
+    }
+    let running = tunnels.values().filter(|wrapper| wrapper.tunnel.id != tunnel.id
+        && wrapper.tunnel.kind != TunnelKind::Remote
+        && !wrapper.tunnel.status.is_stopped());
+    for wrapper in running {
+        let port = wrapper.event_sx.bound_port().unwrap_or(wrapper.tunnel.local_port);
+        if port == tunnel.local_port && shares_address(tunnel, &wrapper.tunnel) {
+            return Err(PluginError::build(ErrorKind::Tunnel, "LOCAL PORT IN USE!!!")
+                .with_retryable(false)
+                .with_context("local_port", port.to_string().as_str())
+                .with_context("conflicting_tunnel", wrapper.tunnel.id.as_str()));
+        }
+    }
+    Ok(())
+}
 
-            let mut wrapper_replic = wrapper.clone();
-            wrapper_replic.tunnel = tunnel.clone();
+// a tunnel that connects through another one needs it up first, starting its group takes care of the order
+fn dependency_check(tunnel: &Tunnel, tunnels: &TunnelWrapperMap) -> TunnelResult<()> {
+    for dependency in tunnel.depends_on.iter() {
+        match tunnels.get(dependency) {
+            Some(wrapper) if wrapper.tunnel.status == TunnelState::RUNNING => {},
+            Some(_) => return Err(PluginError::build(ErrorKind::Tunnel, "DEPENDENCY NOT RUNNING!!!")
+                .with_retryable(false)
+                .with_context("dependency", dependency.as_str())),
+            None => return Err(PluginError::build(ErrorKind::Validation, "DEPENDENCY DOES NOT EXIST!!!")
+                .with_context("dependency", dependency.as_str())),
+        }
+    }
+    Ok(())
+}
 
+// the listener, the probe and the login run on their own, the tunnel is CONNECTING until they report back
+pub fn start_tunnel(tunnel: Tunnel, tunnels: &mut TunnelWrapperMap, notices: &Sender<TunnelNotice>) -> TunnelResult<()> {
+
+    dependency_check(&tunnel, tunnels)?;
+    port_conflict(&tunnel, tunnels)?;
+    let wrapper = tunnels.get_mut(&tunnel.id);
+    match wrapper {
+        Some(wrapper) if wrapper.tunnel.status.is_stopped() => {
+            wrapper.redefine(tunnel);
+            wrapper.tunnel.status = TunnelState::CONNECTING;
+            publish(notices, wrapper.notice(TunnelNoticeKind::State, None, None));
             let (sx, rx) = channel::<TunnelState>(1024);
-            task::spawn(poll(wrapper_replic, rx));
+            wrapper.sx_dest = Some(sx);
+            log::info!("TUNNEL STARTING: {:?}", wrapper.tunnel);
+            task::spawn(poll(wrapper.clone(), rx));
+            Ok(())
+        },
+        Some(_) => Err(PluginError::build(ErrorKind::Tunnel, "NODE STATE IS RUNNING!!!")),
+        _ => Err(not_exists(&tunnel)),
+    }
+}
 
-            log::info!("START RECEIVER WAIT: {:?}", tunnel);
-            let rs = wrapper.rx.lock().await.recv().await.unwrap();
-            log::info!("START RECEIVER: {:?}", tunnel);
-            match rs {
-                Err(e) => Err(e),
-                Ok(state) => {
-                    let mut wrapper = wrapper.clone();
-                    wrapper.tunnel = tunnel;
-                    wrapper.tunnel.status = state;
-                    wrapper.sx_dest = Some(sx);
-                    tunnels.insert(wrapper.tunnel.local_port, wrapper);
-                    Ok(make_tunels(tunnels).await)
-                },
+// every member goes through start or stop on its own and keeps going when another one fails,
+// the answer lists each member with the outcome of its own step. members already there are left alone
+async fn group_tunnels(name: String, state: TunnelState, reply: TunnelReply, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    let views = make_tunels(tunnels);
+    let order = match state {
+        TunnelState::RUNNING => start_order(name.as_str(), &views),
+        TunnelState::STOP => stop_order(name.as_str(), &views),
+        _ => Err(PluginError::build(ErrorKind::Validation, "ONLY RUN OR STOP CAN BE REQUESTED!!!")),
+    };
+    let order = match order {
+        Ok(order) => order,
+        Err(e) => return answer(reply, Err(e)),
+    };
+    log::info!("GROUP {} {:?}: {:?}", name, state, order);
+
+    let mut group = GroupRun { name, order: order.into(), results: Vec::new(), reply };
+    if state == TunnelState::RUNNING {
+        return group_start(group, tunnels, starting, notices);
+    }
+    while let Some(id) = group.order.pop_front() {
+        let tunnel = match tunnels.get(&id) {
+            Some(wrapper) => wrapper.tunnel.clone(),
+            None => continue,
+        };
+        let rs = match tunnel.status.is_stopped() {
+            true => Ok(()),
+            false => stop_tunnel(tunnel, tunnels, notices).await.map(|_| ()),
+        };
+        group.done(&id, rs, tunnels);
+    }
+    answer(group.reply, Ok(group.results));
+}
+
+// a member may depend on the one before it, the next one is started once it came up or gave up
+fn group_start(mut group: GroupRun, tunnels: &mut TunnelWrapperMap, starting: &mut StartingMap, notices: &Sender<TunnelNotice>) {
+    while let Some(id) = group.order.pop_front() {
+        let tunnel = match tunnels.get(&id) {
+            Some(wrapper) => wrapper.tunnel.clone(),
+            None => continue,
+        };
+        if !tunnel.status.is_stopped() {
+            group.done(&id, Ok(()), tunnels);
+            continue;
+        }
+        match start_tunnel(Tunnel { status: TunnelState::RUNNING, ..tunnel }, tunnels, notices) {
+            Ok(()) => {
+                starting.insert(id, Starting::Group(group));
+                return;
+            },
+            Err(e) => group.done(&id, Err(e), tunnels),
+        }
+    }
+    answer(group.reply, Ok(group.results));
+}
+
+impl GroupRun {
+    // the member as its step left it, with the error of the step when it failed
+    fn done(&mut self, id: &str, rs: TunnelResult<()>, tunnels: &mut TunnelWrapperMap) {
+        if let Some(wrapper) = tunnels.get_mut(id) {
+            let mut view = wrapper.view();
+            view.last_error = rs.as_ref().err().map(|e| e.to_string());
+            if let Err(e) = rs {
+                log::warn!("GROUP {} MEMBER {}: {}", self.name, id, e);
+                wrapper.last_error = Some(e);
             }
-        },
-        Some(_) => Err(PluginError::build(-2, "NODE STATE IS RUNNING!!!")),
-        _ => Err(PluginError::build(-2, "NODE DOES NOT EXISTS!!!")),
+            self.results.push(view);
+        }
     }
 }
 
-pub async fn stop_tunnel(tunnel: Tunnel, tunnels: &mut TunnelWrapperMap) -> TunnelResult<Vec<Tunnel>> {
+// the app is going away, every running tunnel is told at once so their drains run side by side.
+// one still connecting is not waited for, it finds the manager gone once it is through
+async fn exit_tunnels(tunnels: &mut TunnelWrapperMap, notices: &Sender<TunnelNotice>) -> TunnelResult<Vec<Tunnel>> {
+    let running: Vec<String> = tunnels.values()
+        .filter(|wrapper| !wrapper.tunnel.status.is_stopped() && wrapper.tunnel.status != TunnelState::CONNECTING)
+        .map(|wrapper| wrapper.tunnel.id.clone())
+        .collect();
+    for wrapper in tunnels.values_mut().filter(|wrapper| !wrapper.tunnel.status.is_stopped()) {
+        if let Some(sx_dest) = wrapper.sx_dest.as_mut() {
+            if sx_dest.send(TunnelState::STOP).await.is_err() {
+                log::info!("TUNNEL ALREADY OVER: {}", wrapper.tunnel.id);
+            }
+        }
+    }
+    for id in running {
+        let wrapper = match tunnels.get_mut(&id) {
+            Some(wrapper) => wrapper,
+            None => continue,
+        };
+        wrapper.tunnel.status = match stopped(wrapper).await {
+            Ok(state) => state,
+            Err(e) => {
+                wrapper.last_error = Some(e);
+                TunnelState::STOP
+            },
+        };
+        publish(notices, wrapper.notice(TunnelNoticeKind::Stopped, None, None));
+    }
+    log::info!("TUNNEL MANAGER EXIT");
+    Ok(make_tunels(tunnels))
+}
+
+pub async fn stop_tunnel(tunnel: Tunnel, tunnels: &mut TunnelWrapperMap, notices: &Sender<TunnelNotice>) -> TunnelResult<Vec<Tunnel>> {
 
-    let wrapper = tunnels.get_mut(&tunnel.local_port);
+    let wrapper = tunnels.get_mut(&tunnel.id);
     match wrapper {
-        Some(wrapper) if wrapper.tunnel.status == TunnelState::RUNNING => {
+        // its listener and session are not there yet to be closed
+        Some(wrapper) if wrapper.tunnel.status == TunnelState::CONNECTING => Err(PluginError::build(ErrorKind::Tunnel, "TUNNEL IS STILL CONNECTING!!!")
+            .with_retryable(true)
+            .with_context("id", tunnel.id.as_str())),
+        Some(wrapper) if !wrapper.tunnel.status.is_stopped() => {
 
-            wrapper.sx_dest.as_mut().unwrap().send(TunnelState::STOP).await.unwrap();
-            log::info!("STOP RECEIVER WAIT: {:?}", tunnel);
-            let rs = wrapper.rx.lock().await.recv().await.unwrap();
-            log::info!("STOP RECEIVER: {:?}", tunnel);
-            match rs {
-                Err(e) => Err(e),
-                Ok(state) => {
-                    let mut wrapper = wrapper.clone();
-                    wrapper.tunnel = tunnel;
-                    wrapper.tunnel.status = state;
-                    tunnels.insert(wrapper.tunnel.local_port, wrapper);
-                    Ok(make_tunels(tunnels).await)
-                },
+            // a tunnel that gave up on its own has already answered, its answer is read below
+            if let Some(sx_dest) = wrapper.sx_dest.as_mut() {
+                if sx_dest.send(TunnelState::STOP).await.is_err() {
+                    log::info!("TUNNEL ALREADY OVER: {:?}", tunnel);
+                }
             }
+            log::info!("TUNNEL STOPPING: {}", tunnel.id);
+            // the definition stays the one it was started with, a stop only asks for the status
+            let state = stopped(wrapper).await?;
+            wrapper.tunnel.status = state;
+            publish(notices, wrapper.notice(TunnelNoticeKind::Stopped, None, None));
+            Ok(make_tunels(tunnels))
         },
-        Some(_) => Err(PluginError::build(-2, "NODE STATE IS STOP!!!")),
-        _ => Err(PluginError::build(-2, "NODE DOES NOT EXISTS!!!")),
+        Some(_) => Err(PluginError::build(ErrorKind::Tunnel, "NODE STATE IS STOP!!!")),
+        _ => Err(not_exists(&tunnel)),
+    }
+}
+
+// the state a tunnel told to stop ends in, after its drain. the wrapper holds a sender of the channel itself,
+// a task that is gone without an answer would keep the manager waiting for good
+async fn stopped(wrapper: &TunnelWrapper) -> TunnelResult<TunnelState> {
+    let drain = Duration::from_secs(u64::from(wrapper.tunnel.drain_timeout.unwrap_or(0)));
+    match time::timeout(drain + STOP_GRACE, async { wrapper.rx.lock().await.recv().await }).await {
+        Ok(Some(rs)) => rs,
+        Ok(None) => Err(PluginError::build(ErrorKind::Internal, "TUNNEL TASK IS GONE!!!")
+            .with_context("id", wrapper.tunnel.id.as_str())),
+        Err(_) => Err(PluginError::build(ErrorKind::Timeout, "TUNNEL DID NOT STOP IN TIME!!!")
+            .with_retryable(true)
+            .with_context("id", wrapper.tunnel.id.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::request;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+    use std::net::{Ipv4Addr, TcpListener as StdTcpListener};
+    use tokio::net::TcpListener;
+
+    // a manager of its own and what it pushes to the windows
+    fn manager() -> (Sender<TunnelControl>, Receiver<TunnelNotice>) {
+        let (sx, rx) = channel(16);
+        let (notices, pushed) = channel(64);
+        tokio::spawn(run(rx, Prompter::new(), notices));
+        (sx, pushed)
+    }
+
+    async fn add(sx: &Sender<TunnelControl>, t: Tunnel) {
+        request(sx, Some(t), TunnelOperator::ADD).await.expect("manager").expect("add");
+    }
+
+    async fn status(sx: &Sender<TunnelControl>, id: &str) -> TunnelState {
+        let tunnels = request(sx, None, TunnelOperator::VIEW).await.expect("manager").expect("view");
+        tunnels.into_iter().find(|t| t.id == id).expect("tunnel").status
+    }
+
+    // a port nothing listens on
+    fn closed_port() -> u16 {
+        StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0)).and_then(|listener| listener.local_addr()).expect("bind").port()
+    }
+
+    // the probe blocks a thread of its own while it logs in
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_start_does_not_hold_up_the_manager() {
+        // an ssh server that takes the connection and never says a word
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.expect("bind");
+        let (sx, mut pushed) = manager();
+        let t = tunnel(json!({ "id": "slow", "ssh_port": server.local_addr().expect("addr").port(), "password": "p" }));
+        add(&sx, t.clone()).await;
+
+        let starting = sx.clone();
+        let mut start = tokio::spawn(async move {
+            request(&starting, Some(Tunnel { status: TunnelState::RUNNING, ..t }), TunnelOperator::STATE).await
+        });
+        let (stream, _) = server.accept().await.expect("accept");
+        assert_eq!(status(&sx, "slow").await, TunnelState::CONNECTING, "answered while the probe waits");
+        let stop = request(&sx, Some(tunnel(json!({ "id": "slow" }))), TunnelOperator::STATE).await.expect("manager");
+        assert_eq!(stop.map_err(|e| e.message).err(), Some(String::from("TUNNEL IS STILL CONNECTING!!!")));
+        assert!(time::timeout(Duration::from_millis(200), &mut start).await.is_err(), "the start is answered once the probe is through");
+
+        drop(stream);
+        let rs = start.await.expect("start").expect("manager");
+        assert!(rs.is_err(), "the server hung up before the handshake");
+        assert_eq!(status(&sx, "slow").await, TunnelState::FAILED);
+
+        let mut notices = Vec::new();
+        while let Ok(notice) = pushed.try_recv() {
+            if notice.kind != TunnelNoticeKind::Metrics {
+                notices.push((notice.kind, notice.status, notice.message.is_some()));
+            }
+        }
+        assert_eq!(notices, vec![
+            (TunnelNoticeKind::State, TunnelState::CONNECTING, false),
+            (TunnelNoticeKind::Error, TunnelState::FAILED, true),
+        ]);
+    }
+
+    // a running tunnel whose task answers a stop with `answer`, never when there is none
+    fn running(t: Tunnel, answer: Option<TunnelState>) -> TunnelWrapperMap {
+        let (events, _) = channel(16);
+        let mut wrapper = TunnelWrapper::from_tunnel(Tunnel { status: TunnelState::RUNNING, ..t }, Prompter::new(), events);
+        let (sx_dest, mut rx) = channel(16);
+        wrapper.sx_dest = Some(sx_dest);
+        let sx = wrapper.sx.clone();
+        tokio::spawn(async move {
+            if let (Some(TunnelState::STOP), Some(answer)) = (rx.recv().await, answer) {
+                let _ = sx.send(Ok(answer)).await;
+            }
+        });
+        vec![(wrapper.tunnel.id.clone(), wrapper)].into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn a_stop_keeps_the_definition() {
+        let (notices, _pushed) = channel(64);
+        let mut tunnels = running(tunnel(json!({ "id": "t", "remote_host": "10.0.0.5" })), Some(TunnelState::STOP));
+        let asked = tunnel(json!({ "id": "t", "remote_host": "10.9.9.9", "status": "stop" }));
+        let views = stop_tunnel(asked, &mut tunnels, &notices).await.expect("stop");
+        assert_eq!(views[0].status, TunnelState::STOP);
+        assert_eq!(views[0].remote_host, "10.0.0.5", "a stop only asks for the status");
+    }
+
+    #[tokio::test]
+    async fn a_stop_does_not_wait_for_good() {
+        let (notices, _pushed) = channel(64);
+        let mut tunnels = running(tunnel(json!({ "id": "t", "drain_timeout": 1 })), None);
+        let asked = time::Instant::now();
+        let e = stop_tunnel(tunnel(json!({ "id": "t", "status": "stop" })), &mut tunnels, &notices).await.expect_err("stop");
+        assert_eq!((e.message.as_str(), e.retryable), ("TUNNEL DID NOT STOP IN TIME!!!", true));
+        assert!(asked.elapsed() >= STOP_GRACE + Duration::from_secs(1), "the drain is waited for");
+        assert!(asked.elapsed() < STOP_GRACE + Duration::from_secs(2));
+        assert_eq!(tunnels["t"].tunnel.status, TunnelState::RUNNING, "its status comes with the answer, if ever");
+    }
+
+    #[tokio::test]
+    async fn a_port_is_taken_only_on_an_address_both_listen_on() {
+        let tunnels = running(tunnel(json!({ "id": "taken", "local_port": 17000, "bind_address": "127.0.0.1" })), None);
+        let cases = vec![
+            ("same port, default bind", json!({ "local_port": 17000 }), Err("LOCAL PORT IN USE!!!")),
+            ("same port, every interface", json!({ "local_port": 17000, "bind_address": "0.0.0.0", "bind_wildcard": true }), Err("LOCAL PORT IN USE!!!")),
+            ("same port, another address", json!({ "local_port": 17000, "bind_address": "127.0.0.2" }), Ok(())),
+            ("another port", json!({ "local_port": 17001 }), Ok(())),
+            ("any free port", json!({ "local_port": 0 }), Ok(())),
+            ("remote, same port", json!({ "kind": "remote", "local_port": 17000 }), Ok(())),
+            ("remote, no port", json!({ "kind": "remote", "local_port": 0 }), Err("LOCAL PORT IS REQUIRED!!!")),
+        ];
+        for (name, spec, expected) in cases {
+            let mut spec = spec;
+            spec["id"] = json!("asking");
+            let rs = port_conflict(&tunnel(spec), &tunnels).map_err(|e| e.message);
+            assert_eq!(rs, expected.map_err(String::from), "{}", name);
+        }
+        // the tunnel itself does not count, and the one in the way is named
+        assert!(port_conflict(&tunnel(json!({ "id": "taken", "local_port": 17000 })), &tunnels).is_ok());
+        let e = port_conflict(&tunnel(json!({ "id": "asking", "local_port": 17000 })), &tunnels).unwrap_err();
+        assert!(e.context.contains(&(String::from("conflicting_tunnel"), String::from("taken"))), "{:?}", e.context);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_group_starts_member_after_member() {
+        let (sx, _pushed) = manager();
+        let dead = closed_port();
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.expect("bind");
+        // `first` can not be reached and `second` depends on it, `third` gets a server that hangs up
+        add(&sx, tunnel(json!({ "id": "first", "group": "g", "ssh_port": dead, "password": "p" }))).await;
+        add(&sx, tunnel(json!({ "id": "second", "group": "g", "depends_on": ["first"], "ssh_port": dead, "password": "p" }))).await;
+        add(&sx, tunnel(json!({ "id": "third", "group": "g", "ssh_port": server.local_addr().expect("addr").port(), "password": "p" }))).await;
+
+        let starting = sx.clone();
+        let group = tokio::spawn(async move {
+            request(&starting, None, TunnelOperator::GROUP(String::from("g"), TunnelState::RUNNING)).await
+        });
+        let (stream, _) = server.accept().await.expect("accept");
+        assert_eq!(status(&sx, "first").await, TunnelState::FAILED, "over before the next member started");
+        assert_eq!(status(&sx, "third").await, TunnelState::CONNECTING);
+        drop(stream);
+
+        let members = group.await.expect("group").expect("manager").expect("members");
+        let ids: Vec<_> = members.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, vec!["first", "second", "third"]);
+        assert!(members.iter().all(|t| t.last_error.is_some()), "{:?}", members.iter().map(|t| &t.last_error).collect::<Vec<_>>());
+        assert!(members[1].last_error.as_deref().unwrap_or_default().contains("DEPENDENCY NOT RUNNING"));
     }
 }
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use super::{PluginError, Tunnel, TunnelResult};
+use crate::plugins::error::ErrorKind;
+
+const BIND_DEFAULT: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// where the local listener of a tunnel goes, loopback unless told otherwise.
+// a wildcard address opens the tunnel to the whole network and has to be asked for explicitly
+pub fn bind_addr(t: &Tunnel) -> TunnelResult<SocketAddr> {
+    let ip = match t.bind_address.as_ref().map(|addr| addr.trim()).filter(|addr| !addr.is_empty()) {
+        None => BIND_DEFAULT,
+        Some(addr) => addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+            .map_err(|_| PluginError::build(ErrorKind::Validation, "INVALID BIND ADDRESS!!!")
+                .with_context("bind_address", addr))?,
+    };
+    if ip.is_unspecified() && !t.bind_wildcard {
+        return Err(PluginError::build(ErrorKind::Validation, "WILDCARD BIND NOT ALLOWED!!!")
+            .with_retryable(false)
+            .with_context("bind_address", ip.to_string().as_str()));
+    }
+    Ok(SocketAddr::new(ip, t.local_port))
+}
+
+// whether two listeners on the same port would get in each other's way, a wildcard takes every address.
+// one that cannot be bound at all fails on its own
+pub fn shares_address(a: &Tunnel, b: &Tunnel) -> bool {
+    match (bind_addr(a), bind_addr(b)) {
+        (Ok(a), Ok(b)) => a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified(),
+        _ => false,
+    }
+}
+
+// a v4 client of a dual stack listener shows up as ::ffff:a.b.c.d
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, hi, lo] => IpAddr::V4(Ipv4Addr::new((hi >> 8) as u8, hi as u8, (lo >> 8) as u8, lo as u8)),
+            _ => IpAddr::V6(v6),
+        },
+        v4 => v4,
+    }
+}
+
+fn prefix_match(a: IpAddr, b: IpAddr, prefix: u8) -> bool {
+    let (a, b, bits) = match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => (u128::from(u32::from(a)), u128::from(u32::from(b)), 32),
+        (IpAddr::V6(a), IpAddr::V6(b)) => (u128::from(a), u128::from(b), 128),
+        _ => return false,
+    };
+    let prefix = u32::from(prefix).min(bits);
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    (a >> shift) == (b >> shift)
+}
+
+// clients allowed to use a tunnel, single addresses or CIDR ranges. empty lets everybody in
+#[derive(Debug, Clone)]
+pub struct Allowlist {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl Allowlist {
+    pub fn parse(t: &Tunnel) -> TunnelResult<Allowlist> {
+        let mut ranges = Vec::new();
+        for entry in t.allowed_clients.iter().map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+            let invalid = || PluginError::build(ErrorKind::Validation, "INVALID CLIENT ALLOWLIST ENTRY!!!")
+                .with_context("allowed_client", entry);
+            let (ip, prefix) = match entry.find('/') {
+                Some(i) => (&entry[..i], Some(&entry[i + 1..])),
+                None => (entry, None),
+            };
+            let written = ip.parse::<IpAddr>().map_err(|_| invalid())?;
+            let ip = unmap(written);
+            // a v4-mapped range counts its prefix over the whole v6 address
+            let (max, mapped) = match (written, ip) {
+                (IpAddr::V6(_), IpAddr::V4(_)) => (128, 96),
+                (_, IpAddr::V4(_)) => (32, 0),
+                _ => (128, 0),
+            };
+            let prefix = match prefix {
+                Some(prefix) => prefix.parse::<u8>().ok()
+                    .filter(|prefix| *prefix <= max)
+                    .and_then(|prefix| prefix.checked_sub(mapped))
+                    .ok_or_else(invalid)?,
+                None => max - mapped,
+            };
+            ranges.push((ip, prefix));
+        }
+        Ok(Allowlist { ranges })
+    }
+
+    pub fn allows(&self, peer: IpAddr) -> bool {
+        let peer = unmap(peer);
+        self.ranges.is_empty() || self.ranges.iter().any(|(ip, prefix)| prefix_match(*ip, peer, *prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+
+    fn allowlist(entries: &[&str]) -> TunnelResult<Allowlist> {
+        Allowlist::parse(&tunnel(json!({ "allowed_clients": entries })))
+    }
+
+    #[test]
+    fn allowlist_allows() {
+        // entries, peer, allowed
+        let cases: &[(&[&str], &str, bool)] = &[
+            (&[], "203.0.113.9", true),
+            (&["", "  "], "203.0.113.9", true),
+            (&["127.0.0.1"], "127.0.0.1", true),
+            (&["127.0.0.1"], "127.0.0.2", false),
+            (&[" 10.1.0.0/16 "], "10.1.255.1", true),
+            (&["10.1.0.0/16"], "10.2.0.1", false),
+            (&["10.1.2.3/8"], "10.200.0.1", true),
+            (&["0.0.0.0/0"], "198.51.100.1", true),
+            (&["0.0.0.0/0"], "::1", false),
+            (&["192.168.1.0/24", "::1"], "::1", true),
+            (&["192.168.1.0/24", "::1"], "192.168.2.1", false),
+            (&["2001:db8::/32"], "2001:db8:ffff::1", true),
+            (&["2001:db8::/32"], "2001:db9::1", false),
+            (&["::/0"], "fe80::1", true),
+            // a v4 client of a dual stack listener
+            (&["10.0.0.0/8"], "::ffff:10.9.8.7", true),
+            (&["10.0.0.0/8"], "::ffff:11.9.8.7", false),
+            (&["127.0.0.1"], "::ffff:127.0.0.1", true),
+            // a v4-mapped entry is a v4 one
+            (&["::ffff:192.0.2.1"], "192.0.2.1", true),
+            (&["::ffff:192.0.2.0/120"], "::ffff:192.0.2.77", true),
+            (&["::ffff:192.0.2.0/120"], "192.0.3.1", false),
+            (&["::ffff:10.0.0.0/104"], "10.9.8.7", true),
+        ];
+        for (entries, peer, allowed) in cases {
+            let list = allowlist(entries).unwrap();
+            assert_eq!(list.allows(peer.parse().unwrap()), *allowed, "{:?} {}", entries, peer);
+        }
+    }
+
+    #[test]
+    fn allowlist_rejects() {
+        let cases: &[&str] = &["localhost", "10.0.0.0/33", "::1/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0/8", "::ffff:10.0.0.0/64", "::ffff:10.0.0.0/129"];
+        for entry in cases {
+            let e = allowlist(&["127.0.0.1", entry]).unwrap_err();
+            assert_eq!(e.message, "INVALID CLIENT ALLOWLIST ENTRY!!!", "{}", entry);
+            assert!(e.context.iter().any(|(key, value)| key == "allowed_client" && value == entry.trim()), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn unmaps_v4_mapped_only() {
+        let cases = [
+            ("::ffff:1.2.3.4", "1.2.3.4"),
+            ("::ffff:0.0.0.0", "0.0.0.0"),
+            ("::1.2.3.4", "::1.2.3.4"),
+            ("64:ff9b::1.2.3.4", "64:ff9b::102:304"),
+            ("1.2.3.4", "1.2.3.4"),
+        ];
+        for (ip, expected) in cases.iter() {
+            assert_eq!(unmap(ip.parse().unwrap()), expected.parse::<IpAddr>().unwrap(), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn bind_address_needs_wildcard_opt_in() {
+        let addr = |bind: Option<&str>, wildcard: bool| bind_addr(&tunnel(json!({
+            "local_port": 17000,
+            "bind_address": bind,
+            "bind_wildcard": wildcard,
+        })));
+        assert_eq!(addr(None, false).unwrap(), "127.0.0.1:17000".parse().unwrap());
+        assert_eq!(addr(Some(" "), false).unwrap(), "127.0.0.1:17000".parse().unwrap());
+        assert_eq!(addr(Some("[::1]"), false).unwrap(), "[::1]:17000".parse().unwrap());
+        assert_eq!(addr(Some("0.0.0.0"), false).unwrap_err().message, "WILDCARD BIND NOT ALLOWED!!!");
+        assert_eq!(addr(Some("::"), true).unwrap(), "[::]:17000".parse().unwrap());
+        assert_eq!(addr(Some("my-host"), true).unwrap_err().message, "INVALID BIND ADDRESS!!!");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,344 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use super::keys::{self, Reader};
+use super::{PluginError, Tunnel, TunnelResult};
+use crate::plugins::error::ErrorKind;
+
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+const CERT_FILE_SUFFIX: &str = "-cert.pub";
+const SSH_CERT_TYPE_USER: u32 = 1;
+// a login with less than this left on its certificate warns, the next reconnect may be refused
+const CERTIFICATE_EXPIRY_WARNING: Duration = Duration::from_secs(60 * 60);
+
+// what the ui shows of a user certificate, times in milliseconds, `valid_before` None for one that never expires
+#[derive(Debug, Clone, Serialize)]
+pub struct CertificateInfo {
+    pub key_type: String,
+    pub key_id: String,
+    pub serial: u64,
+    pub principals: Vec<String>,
+    pub valid_after: i64,
+    pub valid_before: Option<i64>,
+    pub signing_ca: Option<String>,
+    pub expiring: bool,
+    pub expired: bool,
+}
+
+// a certificate to present, `path` None when it was pasted
+pub struct Certificate {
+    pub path: Option<String>,
+    pub line: String,
+    pub info: CertificateInfo,
+}
+
+fn invalid(message: &str) -> PluginError {
+    PluginError::build(ErrorKind::Validation, message).with_retryable(false)
+}
+
+fn is_inline(certificate: &str) -> bool {
+    certificate.split_whitespace().next().is_some_and(|key_type| key_type.ends_with(CERT_SUFFIX))
+}
+
+// the fields before the serial depend on the key type, the ones after are the same for all of them
+fn public_fields(key_type: &str) -> TunnelResult<usize> {
+    match key_type.strip_suffix(CERT_SUFFIX) {
+        Some("ssh-rsa") => Ok(2),
+        Some("ssh-dss") => Ok(4),
+        Some("ecdsa-sha2-nistp256") | Some("ecdsa-sha2-nistp384") | Some("ecdsa-sha2-nistp521") => Ok(2),
+        Some("ssh-ed25519") => Ok(1),
+        _ => Err(invalid("UNSUPPORTED CERTIFICATE TYPE!!!").with_context("key_type", key_type)),
+    }
+}
+
+fn ca_fingerprint(key: &[u8]) -> Option<String> {
+    openssl::hash::hash(openssl::hash::MessageDigest::sha256(), key).ok()
+        .map(|hash| super::known_hosts::fingerprint(&hash))
+}
+
+fn millis(secs: u64) -> i64 {
+    secs.saturating_mul(1000).min(i64::MAX as u64) as i64
+}
+
+fn parse(line: &str) -> TunnelResult<CertificateInfo> {
+    let mut fields = line.split_whitespace();
+    let key_type = fields.next().unwrap_or_default();
+    if !key_type.ends_with(CERT_SUFFIX) {
+        return Err(invalid("NOT AN OPENSSH CERTIFICATE!!!"));
+    }
+    let blob = base64::decode(fields.next().unwrap_or_default())
+        .map_err(|e| invalid("MALFORMED CERTIFICATE!!!").with_source(e))?;
+    let mut reader = Reader::new(&blob).on_truncated("TRUNCATED CERTIFICATE!!!");
+    if reader.string()? != key_type.as_bytes() {
+        return Err(invalid("MALFORMED CERTIFICATE!!!"));
+    }
+    reader.string()?;
+    for _ in 0..public_fields(key_type)? {
+        reader.string()?;
+    }
+    let serial = reader.u64()?;
+    if reader.u32()? != SSH_CERT_TYPE_USER {
+        return Err(invalid("HOST CERTIFICATES CANNOT LOG IN!!!"));
+    }
+    let key_id = String::from_utf8_lossy(reader.string()?).into_owned();
+    let mut principals = Vec::new();
+    let mut packed = Reader::new(reader.string()?);
+    while let Ok(principal) = packed.string() {
+        principals.push(String::from_utf8_lossy(principal).into_owned());
+    }
+    let valid_after = millis(reader.u64()?);
+    let valid_before = Some(reader.u64()?).filter(|secs| *secs != u64::MAX).map(millis);
+    reader.string()?;
+    reader.string()?;
+    reader.string()?;
+    let signing_ca = ca_fingerprint(reader.string()?);
+
+    let info = CertificateInfo {
+        key_type: String::from(key_type),
+        key_id,
+        serial,
+        principals,
+        valid_after,
+        valid_before,
+        signing_ca,
+        expiring: false,
+        expired: false,
+    };
+    Ok(info.at(chrono::Utc::now().timestamp_millis()))
+}
+
+impl CertificateInfo {
+    // `expiring` and `expired` as of `now`, the rest does not change for as long as the certificate is the same
+    pub fn at(mut self, now: i64) -> CertificateInfo {
+        let warning = CERTIFICATE_EXPIRY_WARNING.as_millis() as i64;
+        self.expiring = self.valid_before.is_some_and(|before| before > now && before - now < warning);
+        self.expired = self.valid_before.is_some_and(|before| before <= now);
+        self
+    }
+}
+
+// the one configured, pasted or a path, otherwise `<key>-cert.pub` next to a key file as ssh(1) picks it up
+fn locate(t: &Tunnel) -> Option<String> {
+    if let Some(certificate) = t.certificate.as_ref().filter(|certificate| !certificate.trim().is_empty()) {
+        return Some(certificate.clone());
+    }
+    let key = t.private_key.as_deref().filter(|key| !key.trim().is_empty() && !keys::is_inline(key))?;
+    let path = format!("{}{}", key.strip_suffix(".ppk").unwrap_or(key), CERT_FILE_SUFFIX);
+    Some(path).filter(|path| Path::new(path).is_file())
+}
+
+pub fn load(t: &Tunnel) -> TunnelResult<Option<Certificate>> {
+    let certificate = match locate(t) {
+        Some(certificate) => certificate,
+        None => return Ok(None),
+    };
+    let (path, line) = if is_inline(&certificate) {
+        (None, certificate.trim().to_string())
+    } else {
+        let line = fs::read_to_string(&certificate)
+            .map_err(|e| invalid("CERTIFICATE FILE NOT READABLE!!!").with_context("path", certificate.as_str()).with_source(e))?;
+        (Some(certificate), line.trim().to_string())
+    };
+    let info = parse(&line)?;
+    Ok(Some(Certificate { path, line, info }))
+}
+
+// for the tunnel's row, nothing when there is none or it can not be read, the login tells why.
+// it reads the certificate file, so it runs when the definition changes or the tunnel starts, not per view
+pub fn inspect(t: &Tunnel) -> Option<CertificateInfo> {
+    load(t).ok().flatten().map(|certificate| certificate.info)
+}
+
+// a certificate outside its validity is refused before the server gets to, that one would only say no
+pub fn check(certificate: &Certificate, username: &str) -> TunnelResult<()> {
+    let info = &certificate.info;
+    let now = chrono::Utc::now().timestamp_millis();
+    let at = |millis: i64| chrono::NaiveDateTime::from_timestamp(millis / 1000, 0).to_string();
+    if info.valid_after > now {
+        return Err(PluginError::build(ErrorKind::Auth, "CERTIFICATE NOT YET VALID!!!")
+            .with_context("key_id", info.key_id.as_str())
+            .with_context("valid_after", at(info.valid_after).as_str())
+            .with_retryable(false));
+    }
+    if let Some(valid_before) = info.valid_before.filter(|_| info.expired) {
+        return Err(PluginError::build(ErrorKind::Auth, "CERTIFICATE EXPIRED!!!")
+            .with_context("key_id", info.key_id.as_str())
+            .with_context("valid_before", at(valid_before).as_str())
+            .with_retryable(false));
+    }
+    if info.expiring {
+        log::warn!("CERTIFICATE {} EXPIRES AT {} UTC", info.key_id, at(info.valid_before.unwrap_or_default()));
+    }
+    // the server may map a principal to another account, so this only warns
+    if !info.principals.is_empty() && !info.principals.iter().any(|principal| principal == username) {
+        log::warn!("USER {} IS NOT A PRINCIPAL OF CERTIFICATE {}: {:?}", username, info.key_id, info.principals);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::interactive::Prompter;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use crate::plugins::ssh_tunnel::TunnelWrapper;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tokio::sync::mpsc::channel;
+
+    // tests/fixtures/certs: ssh-keygen certificates signed by the key in ca.pub, user_ed25519 has its
+    // certificate next to it the way ssh(1) finds one
+    const CA: &str = "SHA256:zr/r94GtjF6iTsJ7ifp3nqsjpX44fuKUPRNWdWqR+z0";
+    const JAN_1_2020: i64 = 1577836800000;
+    const JAN_2_2020: i64 = 1577923200000;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/certs").join(name)
+    }
+
+    fn line(name: &str) -> String {
+        fs::read_to_string(fixture(name)).unwrap()
+    }
+
+    fn message(rs: TunnelResult<CertificateInfo>) -> String {
+        match rs {
+            Ok(info) => panic!("parsed {}", info.key_id),
+            Err(e) => e.message,
+        }
+    }
+
+    // the blob of a certificate line with `key_type` in front of whatever follows it
+    fn blob(key_type: &str, rest: &[u8]) -> String {
+        let mut blob = (key_type.len() as u32).to_be_bytes().to_vec();
+        blob.extend_from_slice(key_type.as_bytes());
+        blob.extend_from_slice(rest);
+        format!("{} {}", key_type, base64::encode(blob))
+    }
+
+    #[test]
+    fn parses_user_certificates() {
+        let cases = vec![
+            ("user_ed25519-cert.pub", "ssh-ed25519-cert-v01@openssh.com", "alice@leviathan", 42, vec!["alice", "deploy"], JAN_1_2020, Some(4102358400000)),
+            ("user_rsa-cert.pub", "ssh-rsa-cert-v01@openssh.com", "rsa@leviathan", 7, vec!["alice"], 0, None),
+            ("expired_ecdsa-cert.pub", "ecdsa-sha2-nistp256-cert-v01@openssh.com", "expired@leviathan", 3, vec!["alice"], JAN_1_2020, Some(JAN_2_2020)),
+            ("future_ecdsa-cert.pub", "ecdsa-sha2-nistp256-cert-v01@openssh.com", "anyone@leviathan", 4, vec![], 4039372800000, Some(4102358400000)),
+        ];
+        for (name, key_type, key_id, serial, principals, valid_after, valid_before) in cases {
+            let info = parse(&line(name)).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(info.key_type, key_type, "{}", name);
+            assert_eq!(info.key_id, key_id, "{}", name);
+            assert_eq!(info.serial, serial, "{}", name);
+            assert_eq!(info.principals, principals, "{}", name);
+            assert_eq!((info.valid_after, info.valid_before), (valid_after, valid_before), "{}", name);
+            assert_eq!(info.signing_ca.as_deref(), Some(CA), "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_what_is_not_a_user_certificate() {
+        let ed25519 = line("user_ed25519-cert.pub");
+        let (_, body) = ed25519.split_once(' ').unwrap();
+        let body = body.split_whitespace().next().unwrap();
+        let decoded = base64::decode(body).unwrap();
+        let truncated = format!("ssh-ed25519-cert-v01@openssh.com {}", base64::encode(&decoded[..decoded.len() / 2]));
+
+        let cases = vec![
+            ("host certificate", line("host_ed25519-cert.pub"), "HOST CERTIFICATES CANNOT LOG IN!!!"),
+            ("public key", line("ca.pub"), "NOT AN OPENSSH CERTIFICATE!!!"),
+            ("another type inside", format!("ssh-rsa-cert-v01@openssh.com {}", body), "MALFORMED CERTIFICATE!!!"),
+            ("not base64", String::from("ssh-ed25519-cert-v01@openssh.com ***"), "MALFORMED CERTIFICATE!!!"),
+            ("truncated", truncated, "TRUNCATED CERTIFICATE!!!"),
+            ("security key", blob("sk-ssh-ed25519-cert-v01@openssh.com", &[0, 0, 0, 0]), "UNSUPPORTED CERTIFICATE TYPE!!!"),
+        ];
+        for (name, line, expected) in cases {
+            assert_eq!(message(parse(&line)), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn expiry_as_of_now() {
+        let info = parse(&line("expired_ecdsa-cert.pub")).unwrap();
+        assert!(info.expired && !info.expiring);
+        let cases = vec![
+            ("before", JAN_1_2020, false, false),
+            ("an hour and more left", JAN_2_2020 - 2 * 60 * 60 * 1000, false, false),
+            ("under an hour left", JAN_2_2020 - 60 * 1000, true, false),
+            ("at valid_before", JAN_2_2020, false, true),
+            ("after", JAN_2_2020 + 1, false, true),
+        ];
+        for (name, now, expiring, expired) in cases {
+            let info = info.clone().at(now);
+            assert_eq!((info.expiring, info.expired), (expiring, expired), "{}", name);
+        }
+        // one that never expires never warns
+        let forever = parse(&line("user_rsa-cert.pub")).unwrap().at(i64::MAX);
+        assert!(!forever.expiring && !forever.expired);
+    }
+
+    #[test]
+    fn checks_validity_before_login() {
+        let check_file = |name: &str| {
+            let t = tunnel(json!({ "certificate": fixture(name) }));
+            check(&load(&t).unwrap().unwrap(), "alice").err().map(|e| e.message)
+        };
+        assert_eq!(check_file("user_ed25519-cert.pub"), None);
+        assert_eq!(check_file("user_rsa-cert.pub"), None);
+        assert_eq!(check_file("expired_ecdsa-cert.pub").as_deref(), Some("CERTIFICATE EXPIRED!!!"));
+        assert_eq!(check_file("future_ecdsa-cert.pub").as_deref(), Some("CERTIFICATE NOT YET VALID!!!"));
+    }
+
+    #[test]
+    fn finds_the_certificate_of_a_tunnel() {
+        let key = fixture("user_ed25519");
+        let cert = fixture("user_ed25519-cert.pub");
+        let pasted = line("user_rsa-cert.pub");
+
+        // next to the key file
+        let found = load(&tunnel(json!({ "private_key": key }))).unwrap().unwrap();
+        assert_eq!(found.path.as_deref(), cert.to_str());
+        assert_eq!(found.info.key_id, "alice@leviathan");
+
+        // configured, as a path or pasted, wins over the one next to the key
+        let found = load(&tunnel(json!({ "private_key": key, "certificate": pasted }))).unwrap().unwrap();
+        assert_eq!((found.path, found.info.key_id.as_str()), (None, "rsa@leviathan"));
+        let found = load(&tunnel(json!({ "certificate": fixture("user_rsa-cert.pub") }))).unwrap().unwrap();
+        assert_eq!(found.info.key_id, "rsa@leviathan");
+
+        // none for a pasted key, a key without one or nothing configured
+        assert!(load(&tunnel(json!({ "private_key": line("user_ed25519") }))).unwrap().is_none());
+        assert!(load(&tunnel(json!({ "private_key": fixture("ca.pub") }))).unwrap().is_none());
+        assert!(load(&tunnel(json!({ "certificate": " " }))).unwrap().is_none());
+
+        // a configured file that is not there fails the login, the row just shows none
+        let missing = tunnel(json!({ "certificate": fixture("missing-cert.pub") }));
+        assert_eq!(load(&missing).err().unwrap().message, "CERTIFICATE FILE NOT READABLE!!!");
+        assert!(inspect(&missing).is_none());
+    }
+
+    #[test]
+    fn wrapper_reads_the_certificate_when_the_definition_changes() {
+        let dir = std::env::temp_dir().join(format!("leviathan-cert-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let copy = dir.join("id-cert.pub");
+        fs::copy(fixture("user_ed25519-cert.pub"), &copy).unwrap();
+
+        let (events, _rx) = channel(1);
+        let t = tunnel(json!({ "certificate": copy }));
+        let mut wrapper = TunnelWrapper::from_tunnel(t.clone(), Prompter::new(), events);
+        let key_id = |wrapper: &TunnelWrapper| wrapper.view().certificate_info.map(|info| info.key_id);
+        assert_eq!(key_id(&wrapper).as_deref(), Some("alice@leviathan"));
+
+        // views go by what was read, only a new definition reads again
+        fs::copy(fixture("user_rsa-cert.pub"), &copy).unwrap();
+        assert_eq!(key_id(&wrapper).as_deref(), Some("alice@leviathan"));
+        wrapper.redefine(t.clone());
+        assert_eq!(key_id(&wrapper).as_deref(), Some("rsa@leviathan"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(key_id(&wrapper).as_deref(), Some("rsa@leviathan"));
+        wrapper.redefine(t);
+        assert_eq!(key_id(&wrapper), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,201 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+use super::Tunnel;
+
+// one forwarded client connection as the forwarder driving it sees it
+#[derive(Debug)]
+pub struct ClientStats {
+    pub id: u64,
+    pub peer: String,
+    started: Instant,
+    started_at: i64, //ms
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    // ms after `started`
+    active_at: AtomicU64,
+    killed: AtomicBool,
+    wake: Arc<Notify>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelClient {
+    pub id: u64,
+    pub peer: String,
+    pub started: i64, //ms
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub idle: u64, //ms
+}
+
+// which connection of which tunnel to drop, all of them without an id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelClientKill {
+    pub tunnel: Tunnel,
+    pub id: Option<u64>,
+}
+
+impl ClientStats {
+    pub fn sent(&self, size: usize) {
+        self.bytes_in.fetch_add(size as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn received(&self, size: usize) {
+        self.bytes_out.fetch_add(size as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn lasted(&self) -> std::time::Duration {
+        self.started.elapsed()
+    }
+
+    pub fn killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    fn touch(&self) {
+        self.active_at.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn view(&self) -> TunnelClient {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        TunnelClient {
+            id: self.id,
+            peer: self.peer.clone(),
+            started: self.started_at,
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            idle: elapsed.saturating_sub(self.active_at.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// the live client connections of one tunnel, across every forwarder and reconnect it goes through
+#[derive(Debug, Default)]
+pub struct TunnelClients {
+    next_id: AtomicU64,
+    clients: StdMutex<HashMap<u64, Arc<ClientStats>>>,
+}
+
+impl TunnelClients {
+    pub fn register(&self, peer: String, wake: Arc<Notify>) -> Arc<ClientStats> {
+        let stats = Arc::new(ClientStats {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            peer,
+            started: Instant::now(),
+            started_at: chrono::Utc::now().timestamp_millis(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            active_at: AtomicU64::new(0),
+            killed: AtomicBool::new(false),
+            wake,
+        });
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.insert(stats.id, stats.clone());
+        }
+        stats
+    }
+
+    pub fn remove(&self, id: u64) {
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.remove(&id);
+        }
+    }
+
+    // the forwarder closes the channel and the client socket on its next turn, false when nothing matched
+    pub fn kill(&self, id: Option<u64>) -> bool {
+        let clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(_) => return false,
+        };
+        let mut found = false;
+        for stats in clients.values().filter(|stats| id.is_none() || id == Some(stats.id)) {
+            stats.killed.store(true, Ordering::Relaxed);
+            stats.wake.notify_one();
+            found = true;
+        }
+        found || id.is_none()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.clients.lock() {
+            Ok(clients) => clients.is_empty(),
+            Err(_) => true,
+        }
+    }
+
+    // the ones already told to go are left out
+    pub fn list(&self) -> Vec<TunnelClient> {
+        let mut clients: Vec<TunnelClient> = match self.clients.lock() {
+            Ok(clients) => clients.values().filter(|stats| !stats.killed()).map(|stats| stats.view()).collect(),
+            Err(_) => Vec::new(),
+        };
+        clients.sort_by_key(|client| client.id);
+        clients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    // id, peer, bytes_in, bytes_out
+    fn listed(clients: &TunnelClients) -> Vec<(u64, String, u64, u64)> {
+        clients.list().into_iter().map(|client| (client.id, client.peer, client.bytes_in, client.bytes_out)).collect()
+    }
+
+    fn peer(port: u16) -> String {
+        format!("127.0.0.1:{}", port)
+    }
+
+    #[tokio::test]
+    async fn lists_and_kills_clients() {
+        let clients = TunnelClients::default();
+        let (first_wake, second_wake) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let first = clients.register(peer(50001), first_wake.clone());
+        let second = clients.register(peer(50002), second_wake.clone());
+        first.sent(10);
+        first.received(200);
+        second.sent(3);
+        assert_eq!(listed(&clients), vec![(1, peer(50001), 10, 200), (2, peer(50002), 3, 0)]);
+
+        // the forwarder of the killed one is woken up to close it, and it is not listed any more
+        assert!(clients.kill(Some(second.id)));
+        assert!(second.killed() && !first.killed());
+        assert!(time::timeout(Duration::from_secs(1), second_wake.notified()).await.is_ok());
+        assert_eq!(listed(&clients), vec![(1, peer(50001), 10, 200)]);
+        assert!(!clients.kill(Some(99)), "nothing to kill");
+
+        // until its forwarder removes it, a killed one still counts as live
+        clients.remove(second.id);
+        assert!(!clients.is_empty());
+        assert!(clients.kill(None));
+        assert!(first.killed());
+        assert!(time::timeout(Duration::from_secs(1), first_wake.notified()).await.is_ok());
+        assert!(clients.list().is_empty());
+        clients.remove(first.id);
+        assert!(clients.is_empty());
+        assert!(clients.kill(None), "killing all of none is no error");
+
+        // ids keep counting, a new client is not mistaken for a gone one
+        let third = clients.register(peer(50003), Arc::new(Notify::new()));
+        assert_eq!(third.id, 3);
+    }
+
+    #[test]
+    fn idle_since_the_last_traffic() {
+        let clients = TunnelClients::default();
+        let client = clients.register(peer(50001), Arc::new(Notify::new()));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(clients.list()[0].idle >= 50);
+        client.received(1);
+        assert!(clients.list()[0].idle < 50);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,738 @@
+use ssh2::{BlockDirections, Channel as SSHChannel, ErrorCode as SSH2ErrorCode, Listener, Session};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error as IOError, ErrorKind as IOErrorKind, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio::sync::mpsc::{channel, error::TryRecvError, error::TrySendError, Receiver, Sender};
+use tokio::sync::{Notify, OwnedSemaphorePermit};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use super::clients::ClientStats;
+use super::server::{report, tunnel_error};
+use super::{PluginError, Tunnel, TunnelEvent, TunnelEventSender, TunnelResult, LIBSSH2_ERROR_EAGAIN};
+use crate::plugins::error::ErrorKind;
+
+const FORWARD_BUFFER_SIZE: usize = 16 * 1024;
+// buffers per direction and connection, once they are all in flight the reading side stops
+const FORWARD_BUFFERS: usize = 4;
+const FORWARD_TICK: Duration = Duration::from_secs(1);
+const FORWARD_CLOSE_RETRY: usize = 64;
+const FORWARD_CLOSE_BACKOFF: Duration = Duration::from_millis(5);
+const FORWARD_ACCEPT_QUEUE: usize = 64;
+const KEEPALIVE_DEFAULT_INTERVAL: u32 = 30;
+const KEEPALIVE_DEFAULT_COUNT_MAX: u32 = 3;
+
+type Buffer = Vec<u8>;
+
+// libssh2 keeps a packet that only went out partly and wants the very same call to finish it,
+// anything else sent on the session in between fails with LIBSSH2_ERROR_BAD_USE
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Owed {
+    Write(u64),
+    Read(u64),
+    Close,
+}
+
+enum Command {
+    Attach(Connection),
+    Data(u64, Buffer, usize),
+    Eof(u64),
+    Listen(Listener, Sender<SSHChannel>),
+    Discard(SSHChannel),
+    Shutdown,
+}
+
+struct Connection {
+    id: u64,
+    channel: SSHChannel,
+    // the peer of a client connection, jump hops carry none and are neither announced nor counted.
+    // it is registered with the tunnel once the driver takes the connection over
+    peer: Option<String>,
+    client: Option<Arc<ClientStats>>,
+    // upstream: client -> ssh
+    pending: Option<(Buffer, usize, usize)>,
+    queued: VecDeque<(Buffer, usize)>,
+    up_pool: Sender<Buffer>,
+    client_eof: bool,
+    eof_sent: bool,
+    // downstream: ssh -> client
+    down_pool: Receiver<Buffer>,
+    down: Sender<(Buffer, usize)>,
+    spare: Option<Buffer>,
+    remote_eof: bool,
+    // a client's place under the tunnel's max_channels, a jump hop's channel carries the session and takes none
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+// drives every channel of one ssh session from a single task, woken by the session socket
+#[derive(Clone)]
+pub struct Forwarder {
+    commands: Sender<Command>,
+    wake: Arc<Notify>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct Driver {
+    tunnel: Tunnel,
+    session: Session,
+    conns: HashMap<u64, Connection>,
+    closing: Vec<(SSHChannel, usize)>,
+    events: TunnelEventSender,
+    dead: Option<PluginError>,
+    owed: Option<Owed>,
+    // remote forwards, every channel the server opens on them goes to the paired receiver
+    listeners: Vec<(Listener, Sender<SSHChannel>)>,
+    keepalive: Option<Keepalive>,
+    // something came in from the server since the last keepalive
+    heard: bool,
+    wake: Arc<Notify>,
+}
+
+// ServerAliveInterval and ServerAliveCountMax of ssh(1): the server answers every keepalive,
+// a session that stays silent for `count_max` intervals in a row is dead
+struct Keepalive {
+    interval: Duration,
+    count_max: u32,
+    missed: u32,
+    next: Instant,
+}
+
+impl Keepalive {
+    fn from_tunnel(t: &Tunnel) -> Option<Keepalive> {
+        let interval = t.keepalive_interval.unwrap_or(KEEPALIVE_DEFAULT_INTERVAL);
+        if interval == 0 {
+            return None;
+        }
+        let interval = Duration::from_secs(u64::from(interval));
+        Some(Keepalive {
+            interval,
+            count_max: t.keepalive_count_max.unwrap_or(KEEPALIVE_DEFAULT_COUNT_MAX),
+            missed: 0,
+            next: Instant::now() + interval,
+        })
+    }
+
+    fn due(&self) -> bool {
+        Instant::now() >= self.next
+    }
+
+    // counts the interval that just ended, false once the server has been silent for too long
+    fn check(&mut self, heard: bool) -> bool {
+        self.missed = if heard { 0 } else { self.missed + 1 };
+        self.next = Instant::now() + self.interval;
+        heard || self.missed < self.count_max
+    }
+}
+
+fn sending(session: &Session) -> bool {
+    matches!(session.block_directions(), BlockDirections::Outbound | BlockDirections::Both)
+}
+
+fn would_block<T>(rs: &std::io::Result<T>) -> bool {
+    match rs {
+        Err(e) => e.kind() == IOErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+// libssh2 reads and writes the socket on its own, so a readiness flag is only cleared for the
+// direction libssh2 says it is blocked on: clearing the other one loses an edge, keeping a stale
+// one spins the driver. returns what to wait for when `f` got blocked
+fn with_readiness<F: FnMut() -> bool>(socket: &TcpStream, session: &Session, mut f: F) -> Option<Interest> {
+    let mut blocked: Option<Option<Interest>> = None;
+    let mut step = |interest: Option<Interest>| {
+        let waiting = *blocked.get_or_insert_with(|| match (f(), session.block_directions()) {
+            (false, _) => None,
+            (true, BlockDirections::Outbound) => Some(Interest::WRITABLE),
+            (true, BlockDirections::Both) => Some(Interest::READABLE | Interest::WRITABLE),
+            (true, _) => Some(Interest::READABLE),
+        });
+        match (waiting, interest) {
+            (Some(waiting), Some(interest)) if (waiting | interest) == waiting => Err(IOError::from(IOErrorKind::WouldBlock)),
+            _ => Ok(()),
+        }
+    };
+    let _ = socket.try_io(Interest::READABLE, || {
+        let _ = socket.try_io(Interest::WRITABLE, || step(Some(Interest::WRITABLE)));
+        step(Some(Interest::READABLE))
+    });
+    let _ = socket.try_io(Interest::WRITABLE, || step(Some(Interest::WRITABLE)));
+    let _ = step(None);
+    blocked.flatten()
+}
+
+impl Forwarder {
+    pub fn spawn(tunnel: Tunnel, session: Session, socket: TcpStream, events: TunnelEventSender) -> (Forwarder, JoinHandle<()>) {
+        let (sx, rx) = channel::<Command>(1024);
+        let wake = Arc::new(Notify::new());
+        let keepalive = Keepalive::from_tunnel(&tunnel);
+        if keepalive.is_some() {
+            // the driver paces the keepalives itself, libssh2 only has to send whenever asked
+            session.set_keepalive(true, 1);
+        }
+        let driver = Driver {
+            tunnel,
+            session,
+            conns: HashMap::new(),
+            closing: Vec::new(),
+            events,
+            dead: None,
+            owed: None,
+            listeners: Vec::new(),
+            keepalive,
+            heard: true,
+            wake: wake.clone(),
+        };
+        let handle = tokio::spawn(driver.run(socket, rx));
+        (Forwarder { commands: sx, wake, next_id: Arc::new(AtomicU64::new(1)) }, handle)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    pub async fn shutdown(&self) {
+        let _ = self.commands.send(Command::Shutdown).await;
+    }
+
+    pub async fn attach(&self, c: SSHChannel, stream: TcpStream, peer: Option<String>, permit: Option<OwnedSemaphorePermit>) -> TunnelResult<()> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (up_pool_sx, up_pool_rx) = channel::<Buffer>(FORWARD_BUFFERS);
+        let (down_pool_sx, down_pool_rx) = channel::<Buffer>(FORWARD_BUFFERS);
+        let (down_sx, down_rx) = channel::<(Buffer, usize)>(FORWARD_BUFFERS + 1);
+        for _ in 0..FORWARD_BUFFERS {
+            let _ = up_pool_sx.try_send(vec![0; FORWARD_BUFFER_SIZE]);
+            let _ = down_pool_sx.try_send(vec![0; FORWARD_BUFFER_SIZE]);
+        }
+
+        let conn = Connection {
+            id,
+            channel: c,
+            peer,
+            client: None,
+            pending: None,
+            queued: VecDeque::new(),
+            up_pool: up_pool_sx,
+            client_eof: false,
+            eof_sent: false,
+            down_pool: down_pool_rx,
+            down: down_sx,
+            spare: None,
+            remote_eof: false,
+            _permit: permit,
+        };
+        if self.commands.send(Command::Attach(conn)).await.is_err() {
+            return Err(PluginError::build(ErrorKind::Connection, "SSH SESSION CLOSED!!!"));
+        }
+        tokio::spawn(pump_client(id, stream, up_pool_rx, down_rx, down_pool_sx, self.commands.clone(), self.wake.clone()));
+        Ok(())
+    }
+
+    // hands a remote forward to the driver, which accepts its channels as the session socket wakes it
+    pub async fn listen(&self, listener: Listener) -> TunnelResult<Receiver<SSHChannel>> {
+        let (sx, rx) = channel::<SSHChannel>(FORWARD_ACCEPT_QUEUE);
+        if self.commands.send(Command::Listen(listener, sx)).await.is_err() {
+            return Err(PluginError::build(ErrorKind::Connection, "SSH SESSION CLOSED!!!"));
+        }
+        Ok(rx)
+    }
+
+    // closes a channel that never got a client
+    pub async fn discard(&self, c: SSHChannel) {
+        let _ = self.commands.send(Command::Discard(c)).await;
+    }
+}
+
+async fn read_client(reader: &mut tokio::net::tcp::OwnedReadHalf, spare: &mut Option<Buffer>) -> std::io::Result<usize> {
+    match spare.as_mut() {
+        Some(buf) => reader.read(buf).await,
+        None => Ok(0),
+    }
+}
+
+// the client side of one forwarded connection, buffers travel to the driver and come back
+async fn pump_client(id: u64, stream: TcpStream, mut up_pool: Receiver<Buffer>, mut down: Receiver<(Buffer, usize)>, down_pool: Sender<Buffer>, commands: Sender<Command>, wake: Arc<Notify>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut reading = true;
+    let mut spare: Option<Buffer> = None;
+
+    loop {
+        select! {
+            buf = up_pool.recv(), if reading && spare.is_none() => {
+                match buf {
+                    Some(buf) => spare = Some(buf),
+                    None => reading = false,
+                }
+            },
+            size = read_client(&mut reader, &mut spare), if reading && spare.is_some() => {
+                let command = match size {
+                    Ok(size) if size > 0 => Command::Data(id, spare.take().unwrap(), size),
+                    _ => {
+                        reading = false;
+                        Command::Eof(id)
+                    },
+                };
+                if commands.send(command).await.is_err() {
+                    break;
+                }
+            },
+            payload = down.recv() => {
+                let (buf, size) = match payload {
+                    None => break,
+                    Some(payload) => payload,
+                };
+                let rs = if size == 0 {
+                    writer.shutdown().await
+                } else {
+                    writer.write_all(&buf[..size]).await
+                };
+                let _ = down_pool.try_send(buf);
+                wake.notify_one();
+                if let Err(e) = rs {
+                    log::warn!("CLIENT WRITE: {:?}", e);
+                    break;
+                }
+            },
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+impl Driver {
+    async fn run(mut self, socket: TcpStream, mut commands: Receiver<Command>) {
+        let mut tick = time::interval(FORWARD_TICK);
+        let mut running = true;
+        let session = self.session.clone();
+        let wake = self.wake.clone();
+
+        while running {
+            loop {
+                match commands.try_recv() {
+                    Ok(command) => running &= self.handle(command),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        running = false;
+                        break;
+                    },
+                }
+            }
+            if !running {
+                break;
+            }
+
+            self.kill();
+            let blocked_write = with_readiness(&socket, &session, || self.flush());
+            let blocked_read = with_readiness(&socket, &session, || self.fill());
+            let blocked_accept = with_readiness(&socket, &session, || self.accept());
+            self.reap();
+            if blocked_write.is_none() {
+                self.keepalive();
+            }
+            if self.dead.is_some() {
+                break;
+            }
+
+            // with keepalives on, every answer has to be seen, even when no channel waits for data
+            let listening = self.keepalive.as_ref().map(|_| Interest::READABLE);
+            let interest = [blocked_read, blocked_write, blocked_accept, listening].iter()
+                .flatten()
+                .fold(None, |all: Option<Interest>, &interest| Some(all.map_or(interest, |all| all | interest)));
+
+            select! {
+                command = commands.recv() => {
+                    match command {
+                        Some(command) => running &= self.handle(command),
+                        None => running = false,
+                    }
+                },
+                _ = wake.notified() => {},
+                ready = socket.ready(interest.unwrap_or(Interest::READABLE)), if interest.is_some() => {
+                    match ready {
+                        Err(e) => {
+                            self.dead = Some(tunnel_error(e, &self.tunnel, "forward"));
+                            break;
+                        },
+                        // nothing may read what came in, the flag is dropped so the next packet wakes us again.
+                        // the channels are driven on every turn anyway, no data is left behind by that
+                        Ok(ready) if ready.is_readable() => {
+                            self.heard = true;
+                            let _ = socket.try_io(Interest::READABLE, || Err::<(), _>(IOError::from(IOErrorKind::WouldBlock)));
+                        },
+                        Ok(_) => (),
+                    }
+                },
+                _ = tick.tick() => {},
+            }
+        }
+
+        if let Some(e) = self.dead.take() {
+            report(&self.events, e);
+        }
+        let ids: Vec<u64> = self.conns.keys().cloned().collect();
+        for id in ids {
+            self.close(id);
+        }
+        for _ in 0..FORWARD_CLOSE_RETRY {
+            if self.closing.is_empty() {
+                break;
+            }
+            self.reap();
+            time::sleep(FORWARD_CLOSE_BACKOFF).await;
+        }
+        log::info!("FORWARDER CLOSE: {}", self.tunnel.local_port);
+    }
+
+    // sends the next keepalive when it is due, never in the middle of a packet libssh2 still owes
+    fn keepalive(&mut self) {
+        if self.owed.is_some() {
+            return;
+        }
+        let keepalive = match self.keepalive.as_mut() {
+            Some(keepalive) if keepalive.due() => keepalive,
+            _ => return,
+        };
+        if !keepalive.check(self.heard) {
+            self.dead = Some(PluginError::build(ErrorKind::Timeout, "SSH KEEPALIVE TIMEOUT!!!")
+                .with_context("stage", "keepalive")
+                .with_context("local_port", self.tunnel.local_port.to_string().as_str())
+                .with_context("ssh_host", format!("{}:{}", self.tunnel.ssh_host, self.tunnel.ssh_port).as_str())
+                .with_context("missed", keepalive.missed.to_string().as_str()));
+            return;
+        }
+        self.heard = false;
+        match self.session.keepalive_send() {
+            Err(e) if e.code() != SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                let e = tunnel_error(e, &self.tunnel, "keepalive");
+                if e.kind == ErrorKind::Connection || e.kind == ErrorKind::Timeout {
+                    self.dead = Some(e);
+                } else {
+                    report(&self.events, e);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Attach(mut conn) => {
+                if let Some(peer) = conn.peer.take() {
+                    self.events.metrics().opened();
+                    self.announce(TunnelEvent::Connected(peer.clone()));
+                    conn.client = Some(self.events.clients().register(peer, self.wake.clone()));
+                }
+                self.conns.insert(conn.id, conn);
+            },
+            Command::Data(id, buf, size) => {
+                if let Some(conn) = self.conns.get_mut(&id) {
+                    conn.queued.push_back((buf, size));
+                }
+            },
+            Command::Eof(id) => {
+                if let Some(conn) = self.conns.get_mut(&id) {
+                    conn.client_eof = true;
+                }
+            },
+            Command::Listen(listener, sx) => {
+                self.listeners.push((listener, sx));
+            },
+            Command::Discard(c) => {
+                self.closing.push((c, 0));
+            },
+            Command::Shutdown => return false,
+        }
+        true
+    }
+
+    // channels the server opened on our remote forwards, a listener nobody takes channels from is cancelled
+    fn accept(&mut self) -> bool {
+        if self.owed.is_some() {
+            return false;
+        }
+        let mut blocked = false;
+        let mut failed = None;
+        let mut closed = Vec::new();
+
+        for (i, (listener, sx)) in self.listeners.iter_mut().enumerate() {
+            if sx.is_closed() {
+                closed.push(i);
+                continue;
+            }
+            loop {
+                match listener.accept() {
+                    Ok(c) => {
+                        self.heard = true;
+                        if let Err(e) = sx.try_send(c) {
+                            let c = match e {
+                                TrySendError::Full(c) | TrySendError::Closed(c) => c,
+                            };
+                            self.closing.push((c, 0));
+                        }
+                    },
+                    Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                        blocked = true;
+                        break;
+                    },
+                    Err(e) => {
+                        failed = Some(tunnel_error(e, &self.tunnel, "accept"));
+                        closed.push(i);
+                        break;
+                    },
+                }
+            }
+        }
+
+        for i in closed.into_iter().rev() {
+            self.listeners.remove(i);
+        }
+        if let Some(e) = failed {
+            if e.kind == ErrorKind::Connection || e.kind == ErrorKind::Timeout {
+                self.dead = Some(e);
+            } else {
+                report(&self.events, e);
+            }
+        }
+        blocked
+    }
+
+    // client -> ssh, returns whether some connection is waiting on the session
+    fn flush(&mut self) -> bool {
+        let mut blocked = false;
+        let mut failed = Vec::new();
+
+        for (id, conn) in self.conns.iter_mut() {
+            if matches!(self.owed, Some(owed) if owed != Owed::Write(*id)) {
+                continue;
+            }
+            let mut stalled = false;
+            loop {
+                if conn.pending.is_none() {
+                    match conn.queued.pop_front() {
+                        Some((buf, size)) => conn.pending = Some((buf, size, 0)),
+                        None => break,
+                    }
+                }
+                let (buf, size, offset) = conn.pending.as_mut().unwrap();
+                let rs = conn.channel.write(&buf[*offset..*size]);
+                if would_block(&rs) {
+                    stalled = true;
+                    break;
+                }
+                match rs {
+                    Ok(written) => {
+                        if let Some(client) = conn.client.as_ref() {
+                            self.events.metrics().sent(written);
+                            client.sent(written);
+                        }
+                        *offset += written;
+                        if offset == size {
+                            let (buf, _, _) = conn.pending.take().unwrap();
+                            let _ = conn.up_pool.try_send(buf);
+                        }
+                    },
+                    Err(e) => {
+                        failed.push((*id, tunnel_error(e, &self.tunnel, "forward")));
+                        break;
+                    },
+                }
+            }
+
+            if !stalled && conn.client_eof && !conn.eof_sent && conn.pending.is_none() && conn.queued.is_empty() {
+                match conn.channel.send_eof() {
+                    Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => stalled = true,
+                    _ => conn.eof_sent = true,
+                }
+            }
+            blocked |= stalled;
+            self.owed = if stalled && sending(&self.session) { Some(Owed::Write(*id)) } else { None };
+        }
+
+        for (id, e) in failed {
+            self.fail(id, e);
+        }
+        blocked
+    }
+
+    // ssh -> client, a connection without a free buffer is left alone until its client catches up
+    fn fill(&mut self) -> bool {
+        let mut blocked = false;
+        let mut failed = Vec::new();
+
+        for (id, conn) in self.conns.iter_mut() {
+            if matches!(self.owed, Some(owed) if owed != Owed::Read(*id)) {
+                continue;
+            }
+            let mut stalled = false;
+            while !conn.remote_eof {
+                let mut buf = match conn.spare.take() {
+                    Some(buf) => buf,
+                    None => match conn.down_pool.try_recv() {
+                        Ok(buf) => buf,
+                        Err(_) => break,
+                    },
+                };
+                let rs = conn.channel.read(&mut buf);
+                if would_block(&rs) {
+                    conn.spare = Some(buf);
+                    stalled = true;
+                    break;
+                }
+                match rs {
+                    Ok(size) => {
+                        self.heard = true;
+                        if let Some(client) = conn.client.as_ref() {
+                            self.events.metrics().received(size);
+                            client.received(size);
+                        }
+                        if size == 0 {
+                            conn.remote_eof = true;
+                        }
+                        if conn.down.try_send((buf, size)).is_err() {
+                            conn.remote_eof = true;
+                            conn.client_eof = true;
+                        }
+                    },
+                    Err(e) => {
+                        failed.push((*id, tunnel_error(e, &self.tunnel, "forward")));
+                        break;
+                    },
+                }
+            }
+            blocked |= stalled;
+            self.owed = if stalled && sending(&self.session) { Some(Owed::Read(*id)) } else { None };
+        }
+
+        for (id, e) in failed {
+            self.fail(id, e);
+        }
+        blocked
+    }
+
+    fn fail(&mut self, id: u64, e: PluginError) {
+        if e.kind == ErrorKind::Connection || e.kind == ErrorKind::Timeout {
+            self.dead = Some(e);
+        } else {
+            report(&self.events, e);
+        }
+        self.close(id);
+    }
+
+    fn close(&mut self, id: u64) {
+        if self.owed == Some(Owed::Write(id)) || self.owed == Some(Owed::Read(id)) {
+            self.owed = None;
+        }
+        if let Some(conn) = self.conns.remove(&id) {
+            if let Some(client) = conn.client {
+                self.events.metrics().closed(client.lasted());
+                self.events.clients().remove(client.id);
+                self.announce(TunnelEvent::Closed(client.peer.clone()));
+            }
+            self.closing.push((conn.channel, 0));
+        }
+    }
+
+    // connections the ui asked to drop, closing the channel also ends the client side
+    fn kill(&mut self) {
+        let killed: Vec<u64> = self.conns.values()
+            .filter(|conn| matches!(conn.client.as_ref(), Some(client) if client.killed()))
+            .map(|conn| conn.id)
+            .collect();
+        for id in killed {
+            log::info!("CLIENT KILLED: {} {}", self.tunnel.local_port, id);
+            self.close(id);
+        }
+    }
+
+    fn announce(&self, event: TunnelEvent) {
+        if let Err(e) = self.events.try_send(event) {
+            log::warn!("TUNNEL EVENT DROPPED: {}", e);
+        }
+    }
+
+    fn reap(&mut self) {
+        let done: Vec<u64> = self.conns.values()
+            .filter(|conn| conn.remote_eof && conn.eof_sent)
+            .map(|conn| conn.id)
+            .collect();
+        for id in done {
+            self.close(id);
+        }
+
+        if matches!(self.owed, Some(owed) if owed != Owed::Close) {
+            return;
+        }
+        // a close that went out partly stays in front so it is the first one retried
+        self.owed = None;
+        let mut closing = Vec::new();
+        for (mut channel, retry) in self.closing.drain(..) {
+            if self.owed.is_some() {
+                closing.push((channel, retry));
+                continue;
+            }
+            match channel.close() {
+                Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) && sending(&self.session) => {
+                    self.owed = Some(Owed::Close);
+                    closing.insert(0, (channel, retry));
+                },
+                Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) && retry < FORWARD_CLOSE_RETRY => {
+                    closing.push((channel, retry + 1));
+                },
+                _ => (),
+            }
+        }
+        self.closing = closing;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+
+    fn keepalive(interval: Option<u32>, count_max: Option<u32>) -> Option<Keepalive> {
+        Keepalive::from_tunnel(&tunnel(json!({ "keepalive_interval": interval, "keepalive_count_max": count_max })))
+    }
+
+    #[test]
+    fn keepalive_settings() {
+        let defaults = keepalive(None, None).unwrap();
+        assert_eq!(defaults.interval, Duration::from_secs(u64::from(KEEPALIVE_DEFAULT_INTERVAL)));
+        assert_eq!(defaults.count_max, KEEPALIVE_DEFAULT_COUNT_MAX);
+        assert!(!defaults.due());
+        assert!(keepalive(Some(0), Some(3)).is_none());
+        assert_eq!(keepalive(Some(15), Some(5)).unwrap().count_max, 5);
+    }
+
+    #[test]
+    fn keepalive_counts_silent_intervals() {
+        // count_max, what each interval heard, whether the session is still alive after it
+        let cases: &[(u32, &[bool], &[bool])] = &[
+            (3, &[false, false, false], &[true, true, false]),
+            (3, &[false, false, true, false, false, false], &[true, true, true, true, true, false]),
+            (3, &[true, true, true, true], &[true, true, true, true]),
+            (1, &[true, false], &[true, false]),
+            (0, &[true, false], &[true, false]),
+        ];
+        for (count_max, heard, alive) in cases {
+            let mut keepalive = keepalive(Some(15), Some(*count_max)).unwrap();
+            let checked: Vec<bool> = heard.iter().map(|heard| keepalive.check(*heard)).collect();
+            assert_eq!(checked, alive.to_vec(), "count_max {} heard {:?}", count_max, heard);
+        }
+    }
+
+    #[test]
+    fn keepalive_check_schedules_the_next_one() {
+        let mut keepalive = keepalive(Some(15), None).unwrap();
+        keepalive.next = Instant::now();
+        assert!(keepalive.due());
+        keepalive.check(false);
+        assert!(!keepalive.due());
+        assert!(keepalive.next > Instant::now() + Duration::from_secs(14));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+
+use super::{PluginError, Tunnel, TunnelState, TunnelResult};
+use crate::plugins::error::ErrorKind;
+
+// what one whole group is asked for, RUNNING or STOP as for a single tunnel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelGroupControl {
+    pub name: String,
+    pub status: TunnelState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelGroupState {
+    Running,
+    // some members are up, or on their way or giving up
+    Partial,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TunnelGroup {
+    pub name: String,
+    pub status: TunnelGroupState,
+    pub total: usize,
+    pub running: usize,
+    pub failed: usize,
+    pub members: Vec<String>,
+}
+
+fn members<'a>(name: &str, tunnels: &'a [Tunnel]) -> Vec<&'a Tunnel> {
+    let mut members: Vec<&Tunnel> = tunnels.iter().filter(|tunnel| tunnel.group.as_deref() == Some(name)).collect();
+    members.sort_by(|a, b| a.local_port.cmp(&b.local_port).then_with(|| a.id.cmp(&b.id)));
+    members
+}
+
+// the groups the tunnels name, with how far each of them is up
+pub fn aggregate(tunnels: &[Tunnel]) -> Vec<TunnelGroup> {
+    let mut names: Vec<&str> = tunnels.iter()
+        .filter_map(|tunnel| tunnel.group.as_deref())
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names.into_iter().map(|name| {
+        let members = members(name, tunnels);
+        let running = members.iter().filter(|tunnel| tunnel.status == TunnelState::RUNNING).count();
+        let stopped = members.iter().filter(|tunnel| tunnel.status.is_stopped()).count();
+        let status = if running == members.len() {
+            TunnelGroupState::Running
+        } else if stopped == members.len() {
+            TunnelGroupState::Stopped
+        } else {
+            TunnelGroupState::Partial
+        };
+        TunnelGroup {
+            name: String::from(name),
+            status,
+            total: members.len(),
+            running,
+            failed: members.iter().filter(|tunnel| tunnel.status == TunnelState::FAILED).count(),
+            members: members.iter().map(|tunnel| tunnel.id.clone()).collect(),
+        }
+    }).collect()
+}
+
+// a cycle or a dependency that is gone can not be started, stopping gets past them with what there is
+fn visit(id: &str, index: &HashMap<&str, &Tunnel>, strict: bool, visiting: &mut HashSet<String>, order: &mut Vec<String>) -> TunnelResult<()> {
+    if order.iter().any(|done| done == id) {
+        return Ok(());
+    }
+    if !visiting.insert(String::from(id)) {
+        if !strict {
+            return Ok(());
+        }
+        return Err(PluginError::build(ErrorKind::Validation, "DEPENDENCY CYCLE!!!")
+            .with_context("tunnel", id));
+    }
+    let tunnel = match index.get(id) {
+        Some(tunnel) => tunnel,
+        None if strict => return Err(PluginError::build(ErrorKind::Validation, "DEPENDENCY DOES NOT EXIST!!!")
+            .with_context("dependency", id)),
+        None => return Ok(()),
+    };
+    for dependency in tunnel.depends_on.iter() {
+        visit(dependency, index, strict, visiting, order)?;
+    }
+    visiting.remove(id);
+    order.push(String::from(id));
+    Ok(())
+}
+
+fn order(name: &str, tunnels: &[Tunnel], strict: bool) -> TunnelResult<Vec<String>> {
+    let members = members(name, tunnels);
+    if members.is_empty() {
+        return Err(PluginError::build(ErrorKind::Validation, "NO SUCH GROUP!!!")
+            .with_context("group", name));
+    }
+    let index: HashMap<&str, &Tunnel> = tunnels.iter().map(|tunnel| (tunnel.id.as_str(), tunnel)).collect();
+    let mut visiting = HashSet::new();
+    let mut order = Vec::new();
+    for member in members {
+        visit(member.id.as_str(), &index, strict, &mut visiting, &mut order)?;
+    }
+    Ok(order)
+}
+
+// every tunnel the group needs, each after the ones it depends on, whether those belong to the group or not
+pub fn start_order(name: &str, tunnels: &[Tunnel]) -> TunnelResult<Vec<String>> {
+    order(name, tunnels, true)
+}
+
+// the members only, the other way round, dependencies outside the group stay up for whoever else uses them
+pub fn stop_order(name: &str, tunnels: &[Tunnel]) -> TunnelResult<Vec<String>> {
+    let members: HashSet<String> = members(name, tunnels).iter().map(|tunnel| tunnel.id.clone()).collect();
+    let mut order = order(name, tunnels, false)?;
+    order.retain(|id| members.contains(id));
+    order.reverse();
+    Ok(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+
+    // id, group, depends_on, status; the local port keeps the order they are listed in
+    fn tunnels(specs: &[(&str, Option<&str>, &[&str], &str)]) -> Vec<Tunnel> {
+        specs.iter().enumerate().map(|(i, (id, group, depends_on, status))| tunnel(json!({
+            "id": id,
+            "local_port": 17000 + i,
+            "group": group,
+            "depends_on": depends_on,
+            "status": status,
+        }))).collect()
+    }
+
+    fn stopped(specs: &[(&'static str, Option<&'static str>, &'static [&'static str])]) -> Vec<Tunnel> {
+        let specs: Vec<_> = specs.iter().map(|(id, group, depends_on)| (*id, *group, *depends_on, "stop")).collect();
+        tunnels(&specs)
+    }
+
+    type Order = Result<&'static [&'static str], &'static str>;
+
+    #[test]
+    fn start_and_stop_order() {
+        let g = Some("g");
+        // tunnels, start order, stop order
+        let cases: Vec<(&str, Vec<Tunnel>, Order, Order)> = vec![
+            ("independent, by local port", stopped(&[("c", g, &[]), ("a", g, &[]), ("x", None, &[]), ("b", g, &[])]),
+                Ok(&["c", "a", "b"]), Ok(&["b", "a", "c"])),
+            ("chain", stopped(&[("app", g, &["db"]), ("db", g, &["jump"]), ("jump", g, &[])]),
+                Ok(&["jump", "db", "app"]), Ok(&["app", "db", "jump"])),
+            ("dependency outside the group", stopped(&[("app", g, &["jump"]), ("jump", None, &[])]),
+                Ok(&["jump", "app"]), Ok(&["app"])),
+            ("dependency of another group", stopped(&[("app", g, &["jump"]), ("jump", Some("h"), &[])]),
+                Ok(&["jump", "app"]), Ok(&["app"])),
+            ("diamond", stopped(&[("top", g, &["left", "right"]), ("left", g, &["base"]), ("right", g, &["base"]), ("base", g, &[])]),
+                Ok(&["base", "left", "right", "top"]), Ok(&["top", "right", "left", "base"])),
+            ("cycle", stopped(&[("a", g, &["b"]), ("b", g, &["a"])]),
+                Err("DEPENDENCY CYCLE!!!"), Ok(&["a", "b"])),
+            ("cycle outside the group", stopped(&[("a", g, &["b"]), ("b", None, &["c"]), ("c", None, &["b"])]),
+                Err("DEPENDENCY CYCLE!!!"), Ok(&["a"])),
+            ("self", stopped(&[("a", g, &["a"])]),
+                Err("DEPENDENCY CYCLE!!!"), Ok(&["a"])),
+            ("missing", stopped(&[("a", g, &["gone"]), ("b", g, &[])]),
+                Err("DEPENDENCY DOES NOT EXIST!!!"), Ok(&["b", "a"])),
+            ("no members", stopped(&[("a", Some("h"), &[]), ("b", None, &[])]),
+                Err("NO SUCH GROUP!!!"), Err("NO SUCH GROUP!!!")),
+        ];
+        for (name, tunnels, start, stop) in cases {
+            for (which, rs, expected) in [("start", start_order("g", &tunnels), start), ("stop", stop_order("g", &tunnels), stop)] {
+                match (rs, expected) {
+                    (Ok(order), Ok(expected)) => assert_eq!(order, expected.to_vec(), "{} {}", which, name),
+                    (Err(e), Err(expected)) => assert_eq!(e.message, expected, "{} {}", which, name),
+                    (rs, _) => panic!("{} {}: {:?}", which, name, rs),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn errors_name_the_culprit() {
+        let cycle = start_order("g", &stopped(&[("a", Some("g"), &["b"]), ("b", None, &["a"])])).unwrap_err();
+        assert_eq!(cycle.context, vec![(String::from("tunnel"), String::from("a"))]);
+        let missing = start_order("g", &stopped(&[("a", Some("g"), &["gone"])])).unwrap_err();
+        assert_eq!(missing.context, vec![(String::from("dependency"), String::from("gone"))]);
+    }
+
+    #[test]
+    fn aggregate_groups() {
+        let all = tunnels(&[
+            ("a", Some("g"), &[], "run"),
+            ("b", Some("g"), &[], "failed"),
+            ("c", Some("h"), &[], "run"),
+            ("d", Some("i"), &[], "stop"),
+            ("e", Some("i"), &[], "failed"),
+            ("f", Some(""), &[], "run"),
+            ("x", None, &[], "run"),
+        ]);
+        let groups: Vec<(String, TunnelGroupState, usize, usize, usize, Vec<String>)> = aggregate(&all).into_iter()
+            .map(|group| (group.name, group.status, group.total, group.running, group.failed, group.members))
+            .collect();
+        let expected = |name: &str, status, total, running, failed, members: &[&str]| {
+            (String::from(name), status, total, running, failed, members.iter().map(|id| String::from(*id)).collect())
+        };
+        assert_eq!(groups, vec![
+            expected("g", TunnelGroupState::Partial, 2, 1, 1, &["a", "b"]),
+            expected("h", TunnelGroupState::Running, 1, 1, 0, &["c"]),
+            expected("i", TunnelGroupState::Stopped, 2, 0, 1, &["d", "e"]),
+        ]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,176 @@
+use serde::{Serialize, Deserialize};
+use ssh2::{KeyboardInteractivePrompt, Prompt, Session};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task;
+use tokio::time;
+
+use super::{PluginError, Tunnel, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+const AUTH_ANSWER_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthPromptField {
+    pub text: String,
+    pub echo: bool,
+}
+
+// one round of server challenges, the ui answers it with the same id. `tunnel` is the id of the tunnel
+// logging in, tunnels may share a local port
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthPrompt {
+    pub id: u64,
+    pub tunnel: String,
+    pub ssh_host: String,
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<AuthPromptField>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthAnswer {
+    pub id: u64,
+    #[serde(default)]
+    pub answers: Vec<String>,
+    #[serde(default)]
+    pub cancel: bool,
+}
+
+type PendingAnswers = Arc<StdMutex<HashMap<u64, oneshot::Sender<AuthAnswer>>>>;
+
+// hands keyboard-interactive challenges to the ui and waits for its answers
+#[derive(Clone)]
+pub struct Prompter {
+    sx: Sender<AuthPrompt>,
+    rx: Arc<Mutex<Receiver<AuthPrompt>>>,
+    pending: PendingAnswers,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Prompter {
+    pub fn new() -> Prompter {
+        let (sx, rx) = channel::<AuthPrompt>(64);
+        Prompter {
+            sx,
+            rx: Arc::new(Mutex::new(rx)),
+            pending: Arc::new(StdMutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    pub async fn next(&self) -> Option<AuthPrompt> {
+        self.rx.lock().await.recv().await
+    }
+
+    pub fn answer(&self, answer: AuthAnswer) -> TunnelResult<()> {
+        let waiting = match self.pending.lock() {
+            Ok(mut pending) => pending.remove(&answer.id),
+            Err(_) => None,
+        };
+        match waiting {
+            Some(waiting) => waiting.send(answer).map_err(|_| PluginError::build(ErrorKind::Timeout, "AUTH PROMPT EXPIRED!!!")),
+            None => Err(PluginError::build(ErrorKind::Validation, "NO SUCH AUTH PROMPT!!!")
+                .with_context("id", answer.id.to_string().as_str())),
+        }
+    }
+
+    fn ask(&self, handle: &Handle, prompt: AuthPrompt) -> TunnelResult<AuthAnswer> {
+        let id = prompt.id;
+        let (sx, rx) = oneshot::channel::<AuthAnswer>();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(id, sx);
+        }
+        if self.sx.try_send(prompt).is_err() {
+            self.forget(id);
+            return Err(PluginError::build(ErrorKind::Internal, "AUTH PROMPT NOT DELIVERED!!!"));
+        }
+        let rs = handle.block_on(time::timeout(AUTH_ANSWER_TIMEOUT, rx));
+        self.forget(id);
+        match rs {
+            Ok(Ok(answer)) => Ok(answer),
+            Ok(Err(_)) => Err(PluginError::build(ErrorKind::Internal, "AUTH PROMPT DROPPED!!!")),
+            Err(_) => Err(PluginError::build(ErrorKind::Timeout, "AUTH PROMPT TIMEOUT!!!")),
+        }
+    }
+
+    fn forget(&self, id: u64) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&id);
+        }
+    }
+}
+
+impl Default for Prompter {
+    fn default() -> Prompter {
+        Prompter::new()
+    }
+}
+
+struct Asker<'a> {
+    prompter: &'a Prompter,
+    tunnel: &'a Tunnel,
+    handle: Handle,
+    failure: Option<PluginError>,
+}
+
+impl<'a> KeyboardInteractivePrompt for Asker<'a> {
+    fn prompt<'b>(&mut self, name: &str, instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        if prompts.is_empty() || self.failure.is_some() {
+            return prompts.iter().map(|_| String::new()).collect();
+        }
+
+        // a stored password answers the password challenge, the ui only sees what is left
+        let password = self.tunnel.password.as_ref().filter(|password| !password.is_empty());
+        let known = |p: &Prompt| match password {
+            Some(password) if !p.echo && p.text.to_lowercase().contains("password") => Some(password.clone()),
+            _ => None,
+        };
+        let mut answers: Vec<Option<String>> = prompts.iter().map(known).collect();
+        if answers.iter().all(|a| a.is_some()) {
+            return answers.into_iter().flatten().collect();
+        }
+
+        let prompt = AuthPrompt {
+            id: self.prompter.next_id.fetch_add(1, Ordering::Relaxed),
+            tunnel: self.tunnel.id.clone(),
+            ssh_host: format!("{}:{}", self.tunnel.ssh_host, self.tunnel.ssh_port),
+            name: String::from(name),
+            instructions: String::from(instructions),
+            prompts: prompts.iter().zip(answers.iter())
+                .filter(|(_, known)| known.is_none())
+                .map(|(p, _)| AuthPromptField { text: p.text.to_string(), echo: p.echo })
+                .collect(),
+        };
+        let mut given = match self.prompter.ask(&self.handle, prompt) {
+            Ok(AuthAnswer { cancel: true, .. }) => {
+                self.failure = Some(PluginError::build(ErrorKind::Auth, "AUTH PROMPT CANCELED!!!").with_retryable(false));
+                Vec::new()
+            },
+            Ok(answer) => answer.answers,
+            Err(e) => {
+                self.failure = Some(e);
+                Vec::new()
+            },
+        }.into_iter();
+        for answer in answers.iter_mut().filter(|a| a.is_none()) {
+            *answer = Some(given.next().unwrap_or_default());
+        }
+        answers.into_iter().flatten().collect()
+    }
+}
+
+// runs in place on the calling worker, the prompt callback blocks until the ui answers
+pub fn authenticate(s: &Session, t: &Tunnel, prompter: &Prompter) -> TunnelResult<()> {
+    let mut asker = Asker { prompter, tunnel: t, handle: Handle::current(), failure: None };
+    let rs = task::block_in_place(|| s.userauth_keyboard_interactive(t.username.as_str(), &mut asker));
+    match (asker.failure, rs) {
+        (Some(e), _) => Err(e),
+        (None, rs) => rs.map_err(|e| e.convert()),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,227 @@
+use serde::{Serialize, Deserialize};
+use ssh2::{CheckResult, HashType, KnownHostFileKind, KnownHostKeyFormat, KnownHosts, Session};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use super::{to_hex, PluginError, Tunnel, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+const KNOWN_HOSTS_DIR: &str = ".leviathan";
+const KNOWN_HOSTS_FILE: &str = "known_hosts";
+const HOSTKEY_UNKNOWN: &str = "unknown";
+const HOSTKEY_MISMATCH: &str = "mismatch";
+
+// a key stored in the leviathan managed known_hosts file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostKeyEntry {
+    pub host: String,
+    pub key: String,
+}
+
+// what the ui shows before a key gets pinned, sent back as is to trust it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostKeyPrompt {
+    pub host: String,
+    pub port: u16,
+    pub state: String,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub key: String,
+}
+
+impl HostKeyPrompt {
+    // rebuilds the prompt from the context `verify` put on its error
+    pub fn from_error(e: &PluginError) -> Option<HostKeyPrompt> {
+        let get = |key: &str| e.context.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        Some(HostKeyPrompt {
+            host: get("host")?,
+            port: get("port")?.parse().ok()?,
+            state: get("hostkey")?,
+            key_type: get("key_type")?,
+            fingerprint: get("fingerprint")?,
+            key: get("key")?,
+        })
+    }
+}
+
+pub fn home() -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+fn managed_file() -> TunnelResult<PathBuf> {
+    match home() {
+        Some(home) => Ok(home.join(KNOWN_HOSTS_DIR).join(KNOWN_HOSTS_FILE)),
+        None => Err(PluginError::build(ErrorKind::Internal, "NO HOME DIRECTORY!!!")),
+    }
+}
+
+fn system_file() -> Option<PathBuf> {
+    home().map(|home| home.join(".ssh").join(KNOWN_HOSTS_FILE))
+}
+
+// known_hosts keeps non default ports as "[host]:port"
+fn host_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        String::from(host)
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+fn key_type(format: KnownHostKeyFormat) -> &'static str {
+    match format {
+        KnownHostKeyFormat::SshRsa => "ssh-rsa",
+        KnownHostKeyFormat::SshDss => "ssh-dss",
+        KnownHostKeyFormat::Ecdsa256 => "ecdsa-sha2-nistp256",
+        KnownHostKeyFormat::Ecdsa384 => "ecdsa-sha2-nistp384",
+        KnownHostKeyFormat::Ecdsa521 => "ecdsa-sha2-nistp521",
+        KnownHostKeyFormat::Ed25519 => "ssh-ed25519",
+        _ => "unknown",
+    }
+}
+
+fn key_format(key_type: &str) -> TunnelResult<KnownHostKeyFormat> {
+    match key_type {
+        "ssh-rsa" => Ok(KnownHostKeyFormat::SshRsa),
+        "ssh-dss" => Ok(KnownHostKeyFormat::SshDss),
+        "ecdsa-sha2-nistp256" => Ok(KnownHostKeyFormat::Ecdsa256),
+        "ecdsa-sha2-nistp384" => Ok(KnownHostKeyFormat::Ecdsa384),
+        "ecdsa-sha2-nistp521" => Ok(KnownHostKeyFormat::Ecdsa521),
+        "ssh-ed25519" => Ok(KnownHostKeyFormat::Ed25519),
+        _ => Err(PluginError::build(ErrorKind::Validation, "UNSUPPORTED HOST KEY TYPE!!!")
+            .with_context("key_type", key_type)),
+    }
+}
+
+fn from_hex(hex: &str) -> TunnelResult<Vec<u8>> {
+    let invalid = || PluginError::build(ErrorKind::Validation, "INVALID HOST KEY!!!");
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()).ok_or_else(invalid))
+        .collect()
+}
+
+// the way ssh-keygen -l shows a sha256 key hash
+pub fn fingerprint(hash: &[u8]) -> String {
+    format!("SHA256:{}", base64::encode_config(hash, base64::STANDARD_NO_PAD))
+}
+
+// the managed file only, this is what gets written back
+fn load(s: &Session) -> TunnelResult<KnownHosts> {
+    let mut hosts = s.known_hosts().map_err(|e| e.convert())?;
+    let path = managed_file()?;
+    if path.exists() {
+        hosts.read_file(&path, KnownHostFileKind::OpenSSH).map_err(|e| e.convert()
+            .with_context("known_hosts", path.to_string_lossy().as_ref()))?;
+    }
+    Ok(hosts)
+}
+
+fn save(hosts: &KnownHosts) -> TunnelResult<()> {
+    let path = managed_file()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.convert())?;
+    }
+    hosts.write_file(&path, KnownHostFileKind::OpenSSH).map_err(|e| e.convert()
+        .with_context("known_hosts", path.to_string_lossy().as_ref()))
+}
+
+fn entries(hosts: &KnownHosts) -> TunnelResult<Vec<HostKeyEntry>> {
+    Ok(hosts.hosts().map_err(|e| e.convert())?.iter()
+        .map(|h| HostKeyEntry { host: String::from(h.name().unwrap_or("")), key: String::from(h.key()) })
+        .collect())
+}
+
+// checks the server key of a handshaken session, unknown keys are never trusted silently:
+// the error carries everything the ui needs to ask the user and pin the key afterwards
+pub fn verify(s: &Session, t: &Tunnel) -> TunnelResult<String> {
+    let (key, kind) = match s.host_key() {
+        Some(key) => key,
+        None => return Err(PluginError::build(ErrorKind::Connection, "NO HOST KEY!!!")),
+    };
+    let fingerprint = match s.host_key_hash(HashType::Sha256) {
+        Some(hash) => fingerprint(hash),
+        None => return Err(PluginError::build(ErrorKind::Connection, "NO HOST KEY!!!")),
+    };
+
+    let mut hosts = load(s)?;
+    if t.system_known_hosts {
+        if let Some(path) = system_file().filter(|path| path.exists()) {
+            if let Err(e) = hosts.read_file(&path, KnownHostFileKind::OpenSSH) {
+                log::warn!("SYSTEM KNOWN HOSTS {:?}: {:?}", path, e);
+            }
+        }
+    }
+
+    let (state, message) = match hosts.check_port(t.ssh_host.as_str(), t.ssh_port, key) {
+        CheckResult::Match => return Ok(fingerprint),
+        CheckResult::Mismatch => (HOSTKEY_MISMATCH, "HOST KEY MISMATCH!!!"),
+        CheckResult::NotFound => (HOSTKEY_UNKNOWN, "UNKNOWN HOST KEY!!!"),
+        CheckResult::Failure => return Err(PluginError::build(ErrorKind::Internal, "HOST KEY CHECK FAILED!!!")),
+    };
+    Err(PluginError::build(ErrorKind::Auth, message)
+        .with_retryable(false)
+        .with_context("hostkey", state)
+        .with_context("host", t.ssh_host.as_str())
+        .with_context("port", t.ssh_port.to_string().as_str())
+        .with_context("key_type", key_type(KnownHostKeyFormat::from(kind)))
+        .with_context("fingerprint", fingerprint.as_str())
+        .with_context("key", to_hex(key).as_str()))
+}
+
+pub fn list() -> TunnelResult<Vec<HostKeyEntry>> {
+    entries(&load(&Session::new().map_err(|e| e.convert())?)?)
+}
+
+fn drop_host(hosts: &KnownHosts, name: &str) -> TunnelResult<()> {
+    for h in hosts.hosts().map_err(|e| e.convert())? {
+        if h.name() == Some(name) {
+            hosts.remove(&h).map_err(|e| e.convert())?;
+        }
+    }
+    Ok(())
+}
+
+// `host` is the stored name as listed, "[host]:port" for non default ports
+pub fn remove(host: &str) -> TunnelResult<Vec<HostKeyEntry>> {
+    let hosts = load(&Session::new().map_err(|e| e.convert())?)?;
+    drop_host(&hosts, host)?;
+    save(&hosts)?;
+    entries(&hosts)
+}
+
+// pins the prompted key, any key stored for the same host before is replaced
+pub fn trust(prompt: &HostKeyPrompt) -> TunnelResult<Vec<HostKeyEntry>> {
+    let format = key_format(prompt.key_type.as_str())?;
+    let key = from_hex(prompt.key.as_str())?;
+    let name = host_name(prompt.host.as_str(), prompt.port);
+
+    let mut hosts = load(&Session::new().map_err(|e| e.convert())?)?;
+    drop_host(&hosts, name.as_str())?;
+    hosts.add(name.as_str(), &key, "leviathan", format).map_err(|e| e.convert())?;
+    save(&hosts)?;
+    log::info!("HOST KEY PINNED: {} {}", name, prompt.fingerprint);
+    entries(&hosts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_matches_ssh_keygen() {
+        // sha256 of nothing
+        let hash = from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855").unwrap();
+        assert_eq!(fingerprint(&hash), "SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU");
+    }
+
+    #[test]
+    fn from_hex_rejects_odd_and_non_hex() {
+        assert_eq!(from_hex("00ff7a").unwrap(), vec![0x00, 0xff, 0x7a]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,219 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::sync::mpsc::Sender;
+
+use super::bind::bind_addr;
+use super::{request, PluginError, Tunnel, TunnelControl, TunnelKind, TunnelOperator, TunnelResult, TunnelState};
+use crate::plugins::error::ErrorKind;
+
+// a handle on the tunnel manager for the other plugins, they talk to it the way the ui does
+#[derive(Clone)]
+pub struct TunnelLink {
+    sx: Sender<TunnelControl>,
+}
+
+fn link_error(message: &str, id: &str) -> PluginError {
+    PluginError::build(ErrorKind::Tunnel, message)
+        .with_retryable(false)
+        .with_context("tunnel", id)
+}
+
+// why the start failed stays in the context and the causes, the kind tells the caller it was the tunnel
+fn start_error(e: PluginError, id: &str) -> PluginError {
+    let mut wrapped = link_error("TUNNEL FAILED TO START!!!", id)
+        .with_retryable(e.retryable)
+        .with_context("reason", e.to_string().as_str());
+    wrapped.context.extend(e.context.iter().cloned());
+    wrapped.with_source(e)
+}
+
+impl TunnelLink {
+    pub fn new(sx: Sender<TunnelControl>) -> TunnelLink {
+        TunnelLink { sx }
+    }
+
+    async fn request(&self, tunnel: Option<Tunnel>, operator: TunnelOperator) -> TunnelResult<Vec<Tunnel>> {
+        match request(&self.sx, tunnel, operator).await {
+            Some(rs) => rs,
+            None => Err(PluginError::build(ErrorKind::Internal, "TUNNEL MANAGER IS GONE!!!")),
+        }
+    }
+
+    pub async fn view(&self, id: &str) -> TunnelResult<Tunnel> {
+        let tunnels = self.request(None, TunnelOperator::VIEW).await?;
+        tunnels.into_iter().find(|tunnel| tunnel.id == id)
+            .ok_or_else(|| PluginError::build(ErrorKind::Validation, "TUNNEL DOES NOT EXIST!!!").with_context("tunnel", id))
+    }
+
+    // the tunnel as it runs, started first when it is down. one that is reconnecting is not waited for,
+    // whoever needs it learns right away that the way to the other side is gone for now
+    pub async fn ensure_running(&self, id: &str) -> TunnelResult<Tunnel> {
+        let tunnel = self.view(id).await?;
+        match tunnel.status {
+            TunnelState::RUNNING => return Ok(tunnel),
+            TunnelState::STOP | TunnelState::FAILED => {},
+            _ => return Err(link_error("TUNNEL IS NOT READY!!!", id)
+                .with_retryable(true)
+                .with_context("status", format!("{:?}", tunnel.status).as_str())),
+        }
+        log::info!("TUNNEL {} STARTING ON DEMAND", id);
+        let tunnels = self.request(Some(Tunnel { status: TunnelState::RUNNING, ..tunnel }), TunnelOperator::STATE).await
+            .map_err(|e| start_error(e, id))?;
+        match tunnels.into_iter().find(|tunnel| tunnel.id == id) {
+            Some(tunnel) if tunnel.status == TunnelState::RUNNING => Ok(tunnel),
+            Some(tunnel) => Err(link_error("TUNNEL DID NOT COME UP!!!", id)
+                .with_context("status", format!("{:?}", tunnel.status).as_str())),
+            None => Err(link_error("TUNNEL DOES NOT EXIST!!!", id)),
+        }
+    }
+
+    // where a client of a running local forward connects to, loopback for a tunnel that listens everywhere
+    pub async fn endpoint(&self, id: &str) -> TunnelResult<SocketAddr> {
+        let tunnel = self.ensure_running(id).await?;
+        if tunnel.kind != TunnelKind::Local {
+            return Err(link_error("TUNNEL IS NOT A LOCAL FORWARD!!!", id));
+        }
+        let addr = bind_addr(&tunnel)?;
+        let ip = match addr.ip() {
+            ip if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ip => ip,
+        };
+        match tunnel.bound_port {
+            Some(port) => Ok(SocketAddr::new(ip, port)),
+            None => Err(link_error("TUNNEL HAS NO LOCAL PORT!!!", id)),
+        }
+    }
+
+    // a loopback endpoint reaching `host:port` as the tunnel's ssh server sees it, over the running tunnel's session
+    pub async fn forward(&self, id: &str, host: &str, port: u16) -> TunnelResult<SocketAddr> {
+        let tunnel = self.view(id).await?;
+        let tunnels = self.request(Some(tunnel), TunnelOperator::FORWARD(String::from(host), port)).await?;
+        tunnels.into_iter()
+            .flat_map(|tunnel| tunnel.targets)
+            .find(|target| target.host == host && target.port == port)
+            .map(|target| SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), target.bound_port))
+            .ok_or_else(|| link_error("TUNNEL TARGET NOT OPENED!!!", id).with_context("destination", format!("{}:{}", host, port).as_str()))
+    }
+
+    // gives the loopback endpoint of `forward` back once nothing is to go through it any more
+    pub async fn unforward(&self, id: &str, host: &str, port: u16) -> TunnelResult<()> {
+        let tunnel = self.view(id).await?;
+        self.request(Some(tunnel), TunnelOperator::UNFORWARD(String::from(host), port)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::targets::TunnelTarget;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc::channel;
+
+    type Asked = Arc<Mutex<Vec<String>>>;
+
+    // a manager holding `t` alone, a start ends in `started` and a running tunnel has its local port bound.
+    // what it was asked for is kept, one line a request
+    fn manager(t: Tunnel, started: TunnelResult<TunnelState>) -> (TunnelLink, Asked) {
+        let asked: Asked = Arc::default();
+        let (sx, mut rx) = channel::<TunnelControl>(16);
+        let replica = asked.clone();
+        tokio::spawn(async move {
+            let mut t = t;
+            while let Some(control) = rx.recv().await {
+                let rs = match control.operator {
+                    TunnelOperator::VIEW => {
+                        replica.lock().unwrap().push(String::from("VIEW"));
+                        Ok(())
+                    },
+                    TunnelOperator::STATE => {
+                        let status = control.tunnel.map(|asked| asked.status).unwrap_or_default();
+                        replica.lock().unwrap().push(format!("STATE {:?}", status));
+                        started.clone().map(|status| t.status = status)
+                    },
+                    TunnelOperator::FORWARD(host, port) => {
+                        replica.lock().unwrap().push(format!("FORWARD {}:{}", host, port));
+                        t.targets.push(TunnelTarget { host, port, bound_port: 17100 });
+                        Ok(())
+                    },
+                    _ => Ok(()),
+                };
+                t.bound_port = Some(t.local_port).filter(|_| t.status == TunnelState::RUNNING);
+                let _ = control.reply.send(rs.map(|_| vec![t.clone()]));
+            }
+        });
+        (TunnelLink::new(sx), asked)
+    }
+
+    fn asked(asked: &Asked) -> Vec<String> {
+        asked.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn a_stopped_tunnel_is_started_on_demand() {
+        let (link, requests) = manager(tunnel(json!({ "id": "t", "local_port": 17000, "status": "stop" })), Ok(TunnelState::RUNNING));
+        let t = link.ensure_running("t").await.expect("started");
+        assert_eq!((t.status, t.bound_port), (TunnelState::RUNNING, Some(17000)));
+        assert_eq!(asked(&requests), vec!["VIEW", "STATE RUNNING"]);
+
+        // once it runs it is only looked at
+        link.ensure_running("t").await.expect("running");
+        assert_eq!(asked(&requests), vec!["VIEW", "STATE RUNNING", "VIEW"]);
+
+        let e = link.ensure_running("gone").await.expect_err("no such tunnel");
+        assert_eq!(e.message, "TUNNEL DOES NOT EXIST!!!");
+    }
+
+    #[tokio::test]
+    async fn a_start_that_fails_keeps_its_reason() {
+        let refused = PluginError::build(ErrorKind::Connection, "SSH CONNECTION REFUSED!!!").with_retryable(true);
+        let (link, _) = manager(tunnel(json!({ "id": "t", "local_port": 17000 })), Err(refused));
+        let e = link.ensure_running("t").await.expect_err("failed start");
+        assert_eq!((e.message.as_str(), e.retryable), ("TUNNEL FAILED TO START!!!", true));
+        assert!(e.context.contains(&(String::from("tunnel"), String::from("t"))), "{:?}", e.context);
+        assert_eq!(e.causes(), vec![String::from("[connection] SSH CONNECTION REFUSED!!!")]);
+
+        let (link, _) = manager(tunnel(json!({ "id": "t", "local_port": 17000 })), Ok(TunnelState::FAILED));
+        let e = link.ensure_running("t").await.expect_err("not up");
+        assert_eq!((e.message.as_str(), e.retryable), ("TUNNEL DID NOT COME UP!!!", false));
+    }
+
+    #[tokio::test]
+    async fn a_reconnecting_tunnel_is_not_waited_for() {
+        let t = Tunnel { status: TunnelState::RECONNECTING, ..tunnel(json!({ "id": "t", "local_port": 17000 })) };
+        let (link, requests) = manager(t, Ok(TunnelState::RUNNING));
+        let e = link.endpoint("t").await.expect_err("reconnecting");
+        assert_eq!((e.message.as_str(), e.retryable), ("TUNNEL IS NOT READY!!!", true));
+        assert!(e.context.contains(&(String::from("status"), String::from("RECONNECTING"))), "{:?}", e.context);
+        assert_eq!(asked(&requests), vec!["VIEW"], "no start is asked for");
+    }
+
+    #[tokio::test]
+    async fn endpoints_of_local_forwards_on_loopback() {
+        let cases = vec![
+            ("default bind", json!({ "local_port": 17000 }), Ok("127.0.0.1:17000")),
+            ("another loopback", json!({ "local_port": 17000, "bind_address": "127.0.0.2" }), Ok("127.0.0.2:17000")),
+            ("every interface", json!({ "local_port": 17000, "bind_address": "0.0.0.0", "bind_wildcard": true }), Ok("127.0.0.1:17000")),
+            ("every v6 interface", json!({ "local_port": 17000, "bind_address": "::", "bind_wildcard": true }), Ok("127.0.0.1:17000")),
+            ("dynamic", json!({ "local_port": 17000, "kind": "dynamic" }), Err("TUNNEL IS NOT A LOCAL FORWARD!!!")),
+            ("remote", json!({ "local_port": 17000, "kind": "remote" }), Err("TUNNEL IS NOT A LOCAL FORWARD!!!")),
+        ];
+        for (name, spec, expected) in cases {
+            let mut spec = spec;
+            spec["id"] = json!("t");
+            let (link, _) = manager(tunnel(spec), Ok(TunnelState::RUNNING));
+            let rs = link.endpoint("t").await.map(|addr| addr.to_string()).map_err(|e| e.message);
+            assert_eq!(rs, expected.map(String::from).map_err(String::from), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_forward_comes_back_as_a_loopback_endpoint() {
+        let t = Tunnel { status: TunnelState::RUNNING, ..tunnel(json!({ "id": "t", "local_port": 17000 })) };
+        let (link, requests) = manager(t, Ok(TunnelState::RUNNING));
+        let addr = link.forward("t", "10.0.0.5", 9042).await.expect("forward");
+        assert_eq!(addr.to_string(), "127.0.0.1:17100");
+        assert_eq!(asked(&requests), vec!["VIEW", "FORWARD 10.0.0.5:9042"]);
+    }
+}
\ No newline at end of file
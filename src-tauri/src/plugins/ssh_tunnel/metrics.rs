@@ -0,0 +1,149 @@
+use serde::{Serialize, Deserialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::probe::ProbeReport;
+
+// counters of one tunnel since it was registered, only client connections count, not jump hops
+#[derive(Debug, Default)]
+pub struct TunnelMetrics {
+    active: AtomicU64,
+    total: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    closed: AtomicU64,
+    duration_total: AtomicU64,
+    duration_max: AtomicU64,
+    connect: AtomicU64,
+    handshake: AtomicU64,
+    auth: AtomicU64,
+    logins: AtomicU64,
+}
+
+// bytes_in goes from the clients to the ssh server, bytes_out comes back.
+// durations are of closed connections, the setup times of the last login over the whole chain
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TunnelMetricsSnapshot {
+    pub active: u64,
+    pub total: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub closed: u64,
+    pub duration_total: u64, //ms
+    pub duration_max: u64, //ms
+    pub connect: u64, //ms
+    pub handshake: u64, //ms
+    pub auth: u64, //ms
+    pub logins: u64,
+    pub errors: u64,
+}
+
+impl TunnelMetrics {
+    pub fn opened(&self) {
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn closed(&self, lasted: Duration) {
+        let lasted = lasted.as_millis() as u64;
+        self.active.fetch_sub(1, Ordering::Relaxed);
+        self.closed.fetch_add(1, Ordering::Relaxed);
+        self.duration_total.fetch_add(lasted, Ordering::Relaxed);
+        self.duration_max.fetch_max(lasted, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, size: usize) {
+        self.bytes_in.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, size: usize) {
+        self.bytes_out.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn login(&self, report: &ProbeReport) {
+        let elapsed = |name: &str| report.stages.iter()
+            .filter(|stage| stage.stage == name)
+            .map(|stage| stage.elapsed as u64)
+            .sum::<u64>();
+        self.connect.store(elapsed("connect"), Ordering::Relaxed);
+        self.handshake.store(elapsed("handshake"), Ordering::Relaxed);
+        self.auth.store(elapsed("auth"), Ordering::Relaxed);
+        self.logins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, errors: u64) -> TunnelMetricsSnapshot {
+        TunnelMetricsSnapshot {
+            active: self.active.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            closed: self.closed.load(Ordering::Relaxed),
+            duration_total: self.duration_total.load(Ordering::Relaxed),
+            duration_max: self.duration_max.load(Ordering::Relaxed),
+            connect: self.connect.load(Ordering::Relaxed),
+            handshake: self.handshake.load(Ordering::Relaxed),
+            auth: self.auth.load(Ordering::Relaxed),
+            logins: self.logins.load(Ordering::Relaxed),
+            errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::probe::ProbeStage;
+
+    // stage, host, elapsed ms
+    fn report(stages: &[(&str, &str, u128)]) -> ProbeReport {
+        ProbeReport {
+            local_port: 0,
+            success: true,
+            stages: stages.iter().map(|(stage, host, elapsed)| ProbeStage {
+                stage: String::from(*stage),
+                host: String::from(*host),
+                success: true,
+                elapsed: *elapsed,
+                detail: None,
+                kind: None,
+                message: None,
+            }).collect(),
+            hostkey: None,
+        }
+    }
+
+    #[test]
+    fn snapshot_adds_up_connections_and_traffic() {
+        let metrics = TunnelMetrics::default();
+        for _ in 0..3 {
+            metrics.opened();
+        }
+        metrics.closed(Duration::from_millis(120));
+        metrics.closed(Duration::from_millis(30));
+        metrics.sent(100);
+        metrics.sent(20);
+        metrics.received(4096);
+
+        let snapshot = metrics.snapshot(2);
+        assert_eq!((snapshot.active, snapshot.total, snapshot.closed), (1, 3, 2));
+        assert_eq!((snapshot.bytes_in, snapshot.bytes_out), (120, 4096));
+        assert_eq!((snapshot.duration_total, snapshot.duration_max), (150, 120));
+        assert_eq!(snapshot.errors, 2);
+    }
+
+    #[test]
+    fn setup_times_are_of_the_last_login_over_every_hop() {
+        let metrics = TunnelMetrics::default();
+        metrics.login(&report(&[
+            ("connect", "jump", 10), ("handshake", "jump", 20), ("auth", "jump", 30),
+            ("connect", "db", 1), ("handshake", "db", 2), ("auth", "db", 3),
+        ]));
+        let snapshot = metrics.snapshot(0);
+        assert_eq!((snapshot.connect, snapshot.handshake, snapshot.auth, snapshot.logins), (11, 22, 33, 1));
+
+        // a reconnect replaces the times and counts another login
+        metrics.login(&report(&[("connect", "db", 5), ("handshake", "db", 6), ("auth", "db", 7), ("forward", "db", 100)]));
+        let snapshot = metrics.snapshot(0);
+        assert_eq!((snapshot.connect, snapshot.handshake, snapshot.auth, snapshot.logins), (5, 6, 7, 2));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,151 @@
+use serde::Serialize;
+use ssh2::Session;
+use std::time::Instant;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::channel;
+use tokio::task;
+
+use super::interactive::Prompter;
+use super::known_hosts::{verify, HostKeyPrompt};
+use super::server::{authenticate, connect, connect_via, handshake, open_channel, stage};
+use super::session::{close_jumps, Connected, Link};
+use super::{PluginError, Tunnel, TunnelEvent, TunnelEventSender, TunnelKind, TunnelResult};
+use crate::plugins::error::ErrorKind;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeStage {
+    pub stage: String,
+    pub host: String,
+    pub success: bool,
+    pub elapsed: u128,
+    pub detail: Option<String>,
+    pub kind: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeReport {
+    pub local_port: u16,
+    pub success: bool,
+    pub stages: Vec<ProbeStage>,
+    pub hostkey: Option<HostKeyPrompt>,
+}
+
+impl ProbeReport {
+    fn push<T>(&mut self, stage: &str, hop: &Tunnel, started: Instant, rs: &TunnelResult<T>, detail: Option<String>) {
+        let (kind, message) = match rs {
+            Ok(_) => (None, None),
+            Err(e) => (Some(String::from(e.kind.as_str())), Some(e.message.clone())),
+        };
+        self.success = rs.is_ok();
+        self.stages.push(ProbeStage {
+            stage: String::from(stage),
+            host: format!("{}:{}", hop.ssh_host, hop.ssh_port),
+            success: rs.is_ok(),
+            elapsed: started.elapsed().as_millis(),
+            detail,
+            kind,
+            message,
+        });
+    }
+}
+
+// handshake, host key and login of one hop, libssh2 blocks on them while the runtime has to keep pumping
+// the previous hop, or the tunnel of ours the first one connects through
+fn login(report: &mut ProbeReport, s: &mut Session, hop: &Tunnel, prompter: &Prompter) -> TunnelResult<()> {
+    let started = Instant::now();
+    let rs = handshake(s, hop);
+    report.push("handshake", hop, started, &rs, None);
+    rs?;
+
+    let started = Instant::now();
+    let rs = stage(verify(s, hop), hop, "hostkey");
+    let detail = match &rs {
+        Ok(fingerprint) => Some(fingerprint.clone()),
+        Err(e) => HostKeyPrompt::from_error(e).map(|prompt| prompt.fingerprint),
+    };
+    report.push("hostkey", hop, started, &rs, detail);
+    if let Err(e) = rs {
+        report.hostkey = HostKeyPrompt::from_error(&e);
+        return Err(e);
+    }
+
+    let started = Instant::now();
+    let rs = authenticate(s, hop, prompter);
+    report.push("auth", hop, started, &rs, None);
+    rs
+}
+
+async fn hop_connect(report: &mut ProbeReport, hop: &Tunnel, jump: Option<&Link>) -> TunnelResult<(Session, TcpStream)> {
+    let started = Instant::now();
+    let rs = match jump {
+        None => connect(hop).await,
+        Some(jump) => connect_via(jump, hop).await,
+    };
+    report.push("connect", hop, started, &rs, None);
+    rs
+}
+
+// runs every stage a client connection goes through, hop by hop, and stops at the first failure,
+// handing back the authenticated chain so the tunnel can keep using it
+pub async fn probe(t: &Tunnel, prompter: &Prompter, events: &TunnelEventSender) -> (ProbeReport, TunnelResult<Connected>) {
+    let mut report = ProbeReport { local_port: t.local_port, success: false, stages: Vec::new(), hostkey: None };
+    let hops = t.hops();
+    let mut jumps: Vec<Link> = Vec::new();
+
+    for (i, hop) in hops.iter().enumerate() {
+        let hop_name = format!("{}/{}", i + 1, hops.len());
+        let rs = match hop_connect(&mut report, hop, jumps.last()).await {
+            Ok((mut s, socket)) => {
+                task::block_in_place(|| login(&mut report, &mut s, hop, prompter)).map(|_| (s, socket))
+            },
+            Err(e) => Err(e),
+        };
+        let (s, socket) = match rs {
+            Ok(connected) => connected,
+            Err(e) => {
+                close_jumps(jumps).await;
+                let e = if hops.len() > 1 { e.with_context("hop", hop_name.as_str()) } else { e };
+                return (report, Err(e));
+            },
+        };
+
+        if i + 1 < hops.len() {
+            jumps.push(Link::spawn(hop.clone(), s, socket, events.clone()));
+            continue;
+        }
+
+        // a dynamic tunnel has no fixed remote, its clients pick one each, a remote one listens instead
+        if t.kind != TunnelKind::Local {
+            events.metrics().login(&report);
+            return (report, Ok(Connected { jumps, session: s, socket }));
+        }
+        let started = Instant::now();
+        let check = || open_channel(&s, t).map(|mut c| {
+            if let Err(e) = c.close() {
+                log::warn!("PROBE CHANNEL CLOSE: {:?}", e);
+            }
+        });
+        let rs = task::block_in_place(check);
+        let detail = Some(format!("{}:{}", t.remote_host, t.remote_port));
+        report.push("channel", hop, started, &rs, detail);
+        if let Err(e) = rs {
+            close_jumps(jumps).await;
+            return (report, Err(e));
+        }
+        events.metrics().login(&report);
+        return (report, Ok(Connected { jumps, session: s, socket }));
+    }
+    (report, Err(PluginError::build(ErrorKind::Validation, "NO SSH HOST!!!")))
+}
+
+// standalone connection test, nothing is kept afterwards
+pub async fn test(t: &Tunnel, prompter: &Prompter) -> ProbeReport {
+    let (events, _) = channel::<(String, TunnelEvent)>(16);
+    let events = TunnelEventSender::new(t.id.clone(), events);
+    let (report, rs) = probe(t, prompter, &events).await;
+    if let Ok(connected) = rs {
+        connected.close().await;
+    }
+    report
+}
\ No newline at end of file
@@ -0,0 +1,459 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream as StdTcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::server::loopback;
+use super::socks;
+use super::ssh_config::expand_tokens;
+use super::{PluginError, ProxyKind, SocksAuth, Tunnel, TunnelProxy, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+// a proxy that sends more than this before the blank line is not talking HTTP
+const HTTP_RESPONSE_LIMIT: usize = 8192;
+const PROXY_COMMAND_BUFFER_SIZE: usize = 16 * 1024;
+
+fn invalid(message: &str) -> PluginError {
+    PluginError::build(ErrorKind::Validation, message).with_retryable(false)
+}
+
+// `host:port`, an IPv6 address in brackets
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+fn credentials(proxy: &TunnelProxy) -> Option<SocksAuth> {
+    proxy.username.as_ref().filter(|username| !username.is_empty()).map(|username| SocksAuth {
+        username: username.clone(),
+        password: proxy.password.clone().unwrap_or_default(),
+    })
+}
+
+async fn dial(proxy: &TunnelProxy) -> TunnelResult<TcpStream> {
+    if proxy.host.is_empty() || proxy.port == 0 {
+        return Err(invalid("PROXY HOST AND PORT REQUIRED!!!"));
+    }
+    TcpStream::connect((proxy.host.as_str(), proxy.port)).await.map_err(|e| e.convert())
+}
+
+async fn http_connect(stream: &mut TcpStream, t: &Tunnel, proxy: &TunnelProxy) -> TunnelResult<()> {
+    let target = authority(t.ssh_host.as_str(), t.ssh_port);
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(auth) = credentials(proxy) {
+        let token = base64::encode(format!("{}:{}", auth.username, auth.password));
+        request.push_str(format!("Proxy-Authorization: Basic {}\r\n", token).as_str());
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.map_err(|e| e.convert())?;
+
+    // one byte at a time, what follows the blank line is already the server's banner and belongs to libssh2
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= HTTP_RESPONSE_LIMIT {
+            return Err(PluginError::build(ErrorKind::Connection, "PROXY RESPONSE TOO LONG!!!").with_retryable(false));
+        }
+        response.push(stream.read_u8().await.map_err(|e| e.convert())?);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1).and_then(|status| status.parse::<u16>().ok()) {
+        Some(200..=299) => Ok(()),
+        Some(407) => Err(PluginError::build(ErrorKind::Auth, "PROXY AUTHENTICATION REQUIRED!!!")
+            .with_retryable(false)
+            .with_context("status", status_line)),
+        Some(_) => Err(PluginError::build(ErrorKind::Connection, "PROXY REFUSED CONNECT!!!")
+            .with_context("status", status_line)),
+        None => Err(PluginError::build(ErrorKind::Connection, "MALFORMED PROXY RESPONSE!!!")
+            .with_retryable(false)
+            .with_context("status", status_line)),
+    }
+}
+
+async fn tunnel_through(t: &Tunnel, proxy: &TunnelProxy) -> TunnelResult<StdTcpStream> {
+    let mut stream = dial(proxy).await?;
+    match proxy.kind {
+        ProxyKind::Http => http_connect(&mut stream, t, proxy).await?,
+        _ => socks::connect(&mut stream, t.ssh_host.as_str(), t.ssh_port, credentials(proxy).as_ref()).await?,
+    }
+    stream.into_std().map_err(|e| e.convert())
+}
+
+// what has arrived so far is passed on right away, std::io::copy may splice and hold it back until a
+// whole buffer is full, which stalls the handshake
+fn pump(mut reader: impl Read, mut writer: impl Write) {
+    let mut buf = vec![0; PROXY_COMMAND_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => if writer.write_all(&buf[..n]).and_then(|_| writer.flush()).is_err() {
+                return;
+            },
+        }
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+// the command's stdio is pumped into a loopback socket so libssh2 gets a real socket to talk on, the command
+// is killed once the session lets go of its end and whatever it says on stderr goes to the log
+fn spawn(t: &Tunnel, command: &str) -> TunnelResult<StdTcpStream> {
+    if command.trim().is_empty() {
+        return Err(invalid("PROXY COMMAND REQUIRED!!!"));
+    }
+    let command = expand_tokens(command, t.ssh_host.as_str(), t.ssh_host.as_str(), t.ssh_port, t.username.as_str());
+    let mut child = shell(command.as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| invalid("PROXY COMMAND FAILED TO START!!!").with_context("command", command.as_str()).with_source(e))?;
+    let (stdin, stdout, stderr) = match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+        (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+        _ => return Err(PluginError::build(ErrorKind::Internal, "PROXY COMMAND HAS NO STDIO!!!")),
+    };
+    let (near, far) = loopback().map_err(|e| e.convert())?;
+    let upstream = far.try_clone().map_err(|e| e.convert())?;
+    let downstream = far;
+
+    thread::spawn(move || {
+        pump(stdout, &downstream);
+        let _ = downstream.shutdown(Shutdown::Write);
+    });
+    thread::spawn(move || {
+        pump(&upstream, stdin);
+        let _ = upstream.shutdown(Shutdown::Both);
+        if let Err(e) = child.kill() {
+            log::debug!("PROXY COMMAND ALREADY GONE: {}", e);
+        }
+        match child.wait() {
+            Ok(status) => log::info!("PROXY COMMAND EXITED: {}", status),
+            Err(e) => log::warn!("PROXY COMMAND WAIT: {}", e),
+        }
+    });
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+            log::warn!("PROXY COMMAND: {}", line);
+        }
+    });
+
+    near.set_nonblocking(true).map_err(|e| e.convert())?;
+    Ok(near)
+}
+
+// a non-blocking socket that reaches `t.ssh_host:t.ssh_port` through the proxy, ready for the ssh handshake
+pub async fn connect(t: &Tunnel, proxy: &TunnelProxy) -> TunnelResult<StdTcpStream> {
+    let rs = match proxy.kind {
+        ProxyKind::Command => spawn(t, proxy.command.as_deref().unwrap_or_default()),
+        _ => tunnel_through(t, proxy).await,
+    };
+    rs.map_err(|e| match proxy.kind {
+        ProxyKind::Command => e.with_context("proxy", "command"),
+        _ => e.with_context("proxy", authority(proxy.host.as_str(), proxy.port).as_str()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    const BANNER: &[u8] = b"SSH-2.0-stand-in\r\n";
+
+    // user and password, and what connecting ends in: the banner or the message of the error
+    type Credentials = Option<(&'static str, &'static str)>;
+    type Outcome = Result<(), &'static str>;
+    // the bytes a stand-in reads before each of its replies
+    type Steps = Vec<(usize, Vec<u8>)>;
+
+    fn proxy(kind: ProxyKind, port: u16, auth: Option<(&str, &str)>) -> TunnelProxy {
+        TunnelProxy {
+            kind,
+            host: String::from("127.0.0.1"),
+            port,
+            username: auth.map(|(username, _)| String::from(username)),
+            password: auth.map(|(_, password)| String::from(password)),
+            command: None,
+        }
+    }
+
+    // the socket comes back non-blocking for libssh2
+    fn blocking(stream: &StdTcpStream) {
+        stream.set_nonblocking(false).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    }
+
+    // everything up to the ssh server's close
+    fn drain(stream: StdTcpStream) -> Vec<u8> {
+        blocking(&stream);
+        let mut received = Vec::new();
+        (&stream).read_to_end(&mut received).unwrap();
+        received
+    }
+
+    // an HTTP proxy that answers the first request with `response` and hands back the request
+    async fn http_stand_in(response: Vec<u8>) -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            let _ = stream.write_all(&response).await;
+            let _ = stream.shutdown().await;
+            String::from_utf8(request).unwrap()
+        });
+        (port, served)
+    }
+
+    #[tokio::test]
+    async fn http_connect_cases() {
+        let long = [b"HTTP/1.1 200 OK\r\n".as_ref(), &[b'x'; HTTP_RESPONSE_LIMIT]].concat();
+        let cases: Vec<(&str, Credentials, Vec<u8>, Outcome)> = vec![
+            ("established", None, [b"HTTP/1.1 200 Connection established\r\n\r\n".as_ref(), BANNER].concat(), Ok(())),
+            ("with credentials", Some(("pu", "pp")), [b"HTTP/1.0 200 OK\r\nVia: stand-in\r\n\r\n".as_ref(), BANNER].concat(), Ok(())),
+            ("auth required", None, b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec(), Err("PROXY AUTHENTICATION REQUIRED!!!")),
+            ("wrong credentials", Some(("pu", "xx")), b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec(), Err("PROXY AUTHENTICATION REQUIRED!!!")),
+            ("forbidden", None, b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec(), Err("PROXY REFUSED CONNECT!!!")),
+            ("bad gateway", None, b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n".to_vec(), Err("PROXY REFUSED CONNECT!!!")),
+            ("not http", None, b"SSH-2.0-OpenSSH\r\n\r\n".to_vec(), Err("MALFORMED PROXY RESPONSE!!!")),
+            ("too long", None, long, Err("PROXY RESPONSE TOO LONG!!!")),
+        ];
+        for (name, auth, response, expected) in cases {
+            let (port, served) = http_stand_in(response).await;
+            let t = tunnel(json!({ "ssh_host": "db.internal", "ssh_port": 2222 }));
+            let rs = connect(&t, &proxy(ProxyKind::Http, port, auth)).await;
+
+            let request = served.await.unwrap();
+            assert!(request.starts_with("CONNECT db.internal:2222 HTTP/1.1\r\nHost: db.internal:2222\r\n"), "{}: {}", name, request);
+            let authorization = auth.map(|(username, password)| format!("Proxy-Authorization: Basic {}\r\n", base64::encode(format!("{}:{}", username, password))));
+            assert_eq!(request.contains("Proxy-Authorization"), authorization.is_some(), "{}", name);
+            if let Some(authorization) = authorization {
+                assert!(request.contains(&authorization), "{}: {}", name, request);
+            }
+
+            match (rs, expected) {
+                // what came after the blank line is left for the ssh handshake
+                (Ok(stream), Ok(())) => assert_eq!(drain(stream), BANNER, "{}", name),
+                (Err(e), Err(expected)) => {
+                    assert_eq!(e.message, expected, "{}", name);
+                    assert!(e.context.contains(&(String::from("proxy"), format!("127.0.0.1:{}", port))), "{}: {:?}", name, e.context);
+                },
+                (rs, _) => panic!("{}: {:?}", name, rs.map(drain)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn http_connect_brackets_ipv6() {
+        let (port, served) = http_stand_in([b"HTTP/1.1 200 OK\r\n\r\n".as_ref(), BANNER].concat()).await;
+        let t = tunnel(json!({ "ssh_host": "fd00::22", "ssh_port": 22 }));
+        connect(&t, &proxy(ProxyKind::Http, port, None)).await.unwrap();
+        assert!(served.await.unwrap().starts_with("CONNECT [fd00::22]:22 HTTP/1.1\r\nHost: [fd00::22]:22\r\n"));
+    }
+
+    // a SOCKS5 proxy reading as much as the client should send for each step before it answers,
+    // it hands back everything it read
+    async fn socks_stand_in(steps: Steps) -> (u16, JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let served = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            for (size, answer) in steps {
+                let mut step = vec![0; size];
+                if stream.read_exact(&mut step).await.is_err() {
+                    break;
+                }
+                received.extend_from_slice(&step);
+                if stream.write_all(&answer).await.is_err() {
+                    break;
+                }
+            }
+            let _ = stream.shutdown().await;
+            received
+        });
+        (port, served)
+    }
+
+    fn concat(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[tokio::test]
+    async fn socks5_cases() {
+        // CONNECT to db.internal:2222 by name, the proxy resolves it
+        let request = concat(&[&[5, 1, 0, 3, 11], b"db.internal", &[0x08, 0xae]]);
+        let bound_v4 = [1, 127, 0, 0, 1, 0x04, 0x38];
+        let succeeded = |bound: &[u8]| concat(&[&[5, 0, 0], bound, BANNER]);
+        let refused = |code: u8| concat(&[&[5, code, 0], &bound_v4]);
+        let login = concat(&[&[1, 2], b"su", &[2], b"sp"]);
+
+        let cases: Vec<(&str, Credentials, Steps, Vec<u8>, Outcome)> = vec![
+            ("no auth", None,
+                vec![(3, vec![5, 0]), (request.len(), succeeded(&bound_v4))],
+                concat(&[&[5, 1, 0], &request]), Ok(())),
+            ("bound by name", None,
+                vec![(3, vec![5, 0]), (request.len(), succeeded(&concat(&[&[3, 5], b"proxy", &[0, 80]])))],
+                concat(&[&[5, 1, 0], &request]), Ok(())),
+            ("bound to ipv6", None,
+                vec![(3, vec![5, 0]), (request.len(), succeeded(&concat(&[&[4], &[0; 16], &[0, 80]])))],
+                concat(&[&[5, 1, 0], &request]), Ok(())),
+            ("password", Some(("su", "sp")),
+                vec![(4, vec![5, 2]), (login.len(), vec![1, 0]), (request.len(), succeeded(&bound_v4))],
+                concat(&[&[5, 2, 0, 2], &login, &request]), Ok(())),
+            ("credentials offered, none needed", Some(("su", "sp")),
+                vec![(4, vec![5, 0]), (request.len(), succeeded(&bound_v4))],
+                concat(&[&[5, 2, 0, 2], &request]), Ok(())),
+            ("wrong password", Some(("su", "sp")),
+                vec![(4, vec![5, 2]), (login.len(), vec![1, 1])],
+                concat(&[&[5, 2, 0, 2], &login]), Err("SOCKS PROXY AUTH FAILED!!!")),
+            ("credentials wanted", None,
+                vec![(3, vec![5, 2])],
+                vec![5, 1, 0], Err("SOCKS PROXY WANTS CREDENTIALS!!!")),
+            ("no acceptable method", None,
+                vec![(3, vec![5, 0xff])],
+                vec![5, 1, 0], Err("NO ACCEPTABLE SOCKS METHOD!!!")),
+            ("not socks5", None,
+                vec![(3, vec![4, 0])],
+                vec![5, 1, 0], Err("UNSUPPORTED SOCKS VERSION!!!")),
+            ("general failure", None, vec![(3, vec![5, 0]), (request.len(), refused(1))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY FAILURE!!!")),
+            ("not allowed", None, vec![(3, vec![5, 0]), (request.len(), refused(2))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY NOT ALLOWED!!!")),
+            ("network unreachable", None, vec![(3, vec![5, 0]), (request.len(), refused(3))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY NETWORK UNREACHABLE!!!")),
+            ("host unreachable", None, vec![(3, vec![5, 0]), (request.len(), refused(4))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY HOST UNREACHABLE!!!")),
+            ("connection refused", None, vec![(3, vec![5, 0]), (request.len(), refused(5))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY CONNECTION REFUSED!!!")),
+            ("ttl expired", None, vec![(3, vec![5, 0]), (request.len(), refused(6))], concat(&[&[5, 1, 0], &request]), Err("SOCKS PROXY TTL EXPIRED!!!")),
+            ("unknown bound address", None,
+                vec![(3, vec![5, 0]), (request.len(), vec![5, 0, 0, 9])],
+                concat(&[&[5, 1, 0], &request]), Err("UNSUPPORTED SOCKS ADDRESS TYPE!!!")),
+        ];
+        for (name, auth, steps, sent, expected) in cases {
+            let (port, served) = socks_stand_in(steps).await;
+            let t = tunnel(json!({ "ssh_host": "db.internal", "ssh_port": 2222 }));
+            let rs = connect(&t, &proxy(ProxyKind::Socks5, port, auth)).await;
+            match (rs, expected) {
+                (Ok(stream), Ok(())) => assert_eq!(drain(stream), BANNER, "{}", name),
+                (Err(e), Err(expected)) => {
+                    assert_eq!(e.message, expected, "{}", name);
+                    assert!(e.context.contains(&(String::from("stage"), String::from("socks"))), "{}: {:?}", name, e.context);
+                },
+                (rs, _) => panic!("{}: {:?}", name, rs.map(drain)),
+            }
+            assert_eq!(served.await.unwrap(), sent, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_sends_addresses_as_they_are() {
+        let bound = [1, 127, 0, 0, 1, 0, 0];
+        for (host, address) in [("10.1.2.3", vec![1, 10, 1, 2, 3]), ("::1", concat(&[&[4], &std::net::Ipv6Addr::LOCALHOST.octets()]))] {
+            let request = concat(&[&[5, 1, 0], &address, &[0, 22]]);
+            let (port, served) = socks_stand_in(vec![(3, vec![5, 0]), (request.len(), concat(&[&[5, 0, 0], &bound]))]).await;
+            let t = tunnel(json!({ "ssh_host": host, "ssh_port": 22 }));
+            connect(&t, &proxy(ProxyKind::Socks5, port, None)).await.unwrap_or_else(|e| panic!("{}: {}", host, e));
+            assert_eq!(served.await.unwrap(), concat(&[&[5, 1, 0], &request]), "{}", host);
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_needs_an_address_that_answers() {
+        let t = tunnel(json!({}));
+        let e = connect(&t, &proxy(ProxyKind::Http, 0, None)).await.err().unwrap();
+        assert_eq!(e.message, "PROXY HOST AND PORT REQUIRED!!!");
+
+        // a port nobody listens on, taken from a listener that is gone again
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let e = connect(&t, &proxy(ProxyKind::Socks5, port, None)).await.err().unwrap();
+        assert!(matches!(e.kind, ErrorKind::Connection), "{:?}", e);
+        assert!(e.context.contains(&(String::from("proxy"), format!("127.0.0.1:{}", port))), "{:?}", e.context);
+    }
+
+    fn command(command: &str) -> TunnelProxy {
+        TunnelProxy { kind: ProxyKind::Command, host: String::new(), port: 0, username: None, password: None, command: Some(String::from(command)) }
+    }
+
+    // the way libssh2 talks on it: the socket stays open both ways until the session is over, letting go
+    // of it stops the command
+    fn echoed(stream: &StdTcpStream, sent: &[u8]) -> Vec<u8> {
+        blocking(stream);
+        let mut stream = stream;
+        stream.write_all(sent).unwrap();
+        let mut received = vec![0; sent.len()];
+        stream.read_exact(&mut received).unwrap();
+        received
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn proxy_command_carries_the_session() {
+        let t = tunnel(json!({ "ssh_host": "db.internal", "ssh_port": 2222, "username": "deploy" }));
+
+        let cat = connect(&t, &command("cat")).await.unwrap();
+        let sent: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(echoed(&cat, &sent), sent);
+        assert_eq!(echoed(&cat, b"SSH-2.0-leviathan\r\n"), b"SSH-2.0-leviathan\r\n");
+        drop(cat);
+
+        // the tokens of ssh(1), the command's stderr goes to the log and not into the session
+        let echo = connect(&t, &command("echo %r@%h:%p; echo noise >&2")).await.unwrap();
+        assert_eq!(drain(echo), b"deploy@db.internal:2222\n");
+
+        // a command that is gone closes the session
+        let gone = connect(&t, &command("exit 3")).await.unwrap();
+        assert_eq!(drain(gone), b"");
+
+        let e = connect(&t, &command("  ")).await.err().unwrap();
+        assert_eq!(e.message, "PROXY COMMAND REQUIRED!!!");
+        assert!(e.context.contains(&(String::from("proxy"), String::from("command"))), "{:?}", e.context);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn proxy_command_through_nc() {
+        if Command::new("nc").arg("-h").stdout(Stdio::null()).stderr(Stdio::null()).status().is_err() {
+            eprintln!("no nc to run the ProxyCommand with");
+            return;
+        }
+        // an ssh server stand-in that greets and echoes what follows, on a thread of its own as the test
+        // blocks on the socket
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || -> std::io::Result<()> {
+            let (stream, _) = listener.accept()?;
+            (&stream).write_all(BANNER)?;
+            std::io::copy(&mut &stream, &mut &stream)?;
+            stream.shutdown(Shutdown::Write)
+        });
+
+        let t = tunnel(json!({ "ssh_host": "127.0.0.1", "ssh_port": port }));
+        let stream = connect(&t, &command("nc %h %p")).await.unwrap();
+        blocking(&stream);
+        let mut banner = vec![0; BANNER.len()];
+        (&stream).read_exact(&mut banner).unwrap();
+        assert_eq!(banner, BANNER);
+        assert_eq!(echoed(&stream, b"SSH-2.0-leviathan\r\n"), b"SSH-2.0-leviathan\r\n");
+    }
+}
\ No newline at end of file
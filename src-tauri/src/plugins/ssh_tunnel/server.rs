@@ -1,44 +1,262 @@
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::future::Future;
+use tokio::io::AsyncWriteExt;
 use tokio::select;
+use tokio::time;
+use std::time::Duration;
 use tokio::net::TcpStream;
-use crate::plugins::error::ConvertToPluginError;
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
 
-use super::{TunnelWrapper, TunnelState, Tunnel};
+use super::agent;
+use super::cert;
+use super::bind::{bind_addr, Allowlist};
+use super::interactive::{self, Prompter};
+use super::keys;
+use super::probe::probe;
+use super::proxy;
+use super::forward::Forwarder;
+use super::session::{Link, SharedSession};
+use super::socks;
+use super::{PluginError, TunnelWrapper, TunnelState, Tunnel, TunnelEvent, TunnelEventSender, TunnelKind, TunnelResult, LIBSSH2_ERROR_EAGAIN};
 use tokio::net::TcpListener;
-use std::net::{SocketAddrV4, Ipv4Addr};
-use tokio::sync::mpsc::{Receiver, Sender, channel};
-use ssh2::{Session, Channel as SSHChannel};
-use std::path::Path;
-use std::io::{Write, Read};
-use ssh2::Stream as SSHStream;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use ssh2::{Session, Channel as SSHChannel, ErrorCode as SSH2ErrorCode};
+use std::net::{TcpListener as StdTcpListener, TcpStream as StdTcpStream};
 
-#[derive(Debug)]
-struct Payload {
-    size: usize,
-    content: Vec<u8>,
-}
+const SSH_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const SSH_EAGAIN_BACKOFF: Duration = Duration::from_millis(5);
+const REMOTE_DEFAULT_TARGET: &str = "127.0.0.1";
+const SESSION_WATCH: Duration = Duration::from_secs(1);
+const RECONNECT_DEFAULT_ATTEMPTS: u32 = 8;
+const RECONNECT_DEFAULT_DELAY: u64 = 1000; //ms
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const DRAIN_CHECK: Duration = Duration::from_millis(100);
 
-pub async fn poll(wrapper: TunnelWrapper, mut rx: Receiver<TunnelState>) {
+pub async fn poll(mut wrapper: TunnelWrapper, mut rx: Receiver<TunnelState>) {
 
-    let listener = match TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(0,0,0,0), wrapper.tunnel.local_port)).await {
-        Ok(listener) => listener,
+    let listener = match wrapper.tunnel.kind {
+        TunnelKind::Remote => None,
+        _ => match listen_local(&wrapper.tunnel).await {
+            // a tunnel asking for any free port goes on with the one it got
+            Ok((listener, allowlist)) => {
+                if let Ok(addr) = listener.local_addr() {
+                    wrapper.tunnel.local_port = addr.port();
+                }
+                Some((listener, allowlist))
+            },
+            Err(e) => {
+                started(&wrapper, Err(e)).await;
+                return;
+            },
+        },
+    };
+    let (probe_report, rs) = probe(&wrapper.tunnel, &wrapper.prompter, &wrapper.event_sx).await;
+    log::info!("PROBE: {:?}", probe_report);
+    let shared = match rs {
+        Ok(connected) => SharedSession::new(wrapper.tunnel.clone(), Some(connected), wrapper.event_sx.clone(), wrapper.prompter.clone()),
         Err(e) => {
-            wrapper.sx.send(Err(e.convert())).await.unwrap();
+            started(&wrapper, Err(e)).await;
             return;
         },
     };
-    wrapper.sx.send(Ok(TunnelState::RUNNING)).await.unwrap();
+    let mut remote = match wrapper.tunnel.kind {
+        TunnelKind::Remote => match listen_remote(&wrapper, &shared).await {
+            Ok(remote) => Some(remote),
+            Err(e) => {
+                shared.disconnect().await;
+                started(&wrapper, Err(e)).await;
+                return;
+            },
+        },
+        _ => None,
+    };
+    if listener.is_some() {
+        wrapper.event_sx.bound(wrapper.tunnel.local_port);
+    }
+    if !started(&wrapper, Ok(())).await {
+        shared.disconnect().await;
+        return;
+    }
     log::info!("START CLIENT: {:?}", wrapper.tunnel);
 
+    let (cancel, cancelled) = watch::channel(false);
+    if wrapper.tunnel.kind == TunnelKind::Local {
+        wrapper.targets.attach(shared.clone(), wrapper.event_sx.clone(), cancelled.clone());
+    }
+    let state = loop {
+        let state = match (&listener, remote.take()) {
+            (Some((listener, allowlist)), _) => serve_local(listener, allowlist, &wrapper, &shared, &cancelled, &mut rx).await,
+            (_, Some((forwarder, accepted))) => serve_remote(forwarder, accepted, &wrapper, &shared, &cancelled, &mut rx).await,
+            _ => None,
+        };
+        if let Some(state) = state {
+            break state;
+        }
+        // the session broke down by itself, the listener stays bound while it is brought back
+        match recover(&wrapper, &shared, &mut rx).await {
+            Ok(recovered) => remote = recovered,
+            Err(state) => break state,
+        }
+    };
+
+    // no new clients from here on and the ones still on their way in are dropped,
+    // the forwarded ones get the drain time before the session goes down with them
+    drop(listener);
+    wrapper.targets.detach();
+    if cancel.send(true).is_err() {
+        log::debug!("NO CLIENT PENDING: {}", wrapper.tunnel.local_port);
+    }
+    if state == TunnelState::STOP {
+        drain(&wrapper).await;
+    }
+    shared.disconnect().await;
+    wrapper.event_sx.bound(0);
+    if wrapper.sx.send(Ok(state)).await.is_err() {
+        log::error!("TUNNEL MANAGER CLOSED: {:?}", wrapper.tunnel);
+    }
+    log::info!("STOP CLIENT: {:?}", wrapper.tunnel);
+}
+
+// the manager answers whoever asked for the start with it, false when the manager is gone meanwhile
+async fn started(wrapper: &TunnelWrapper, rs: TunnelResult<()>) -> bool {
+    if wrapper.event_sx.send(TunnelEvent::Started(rs)).await.is_err() {
+        log::error!("TUNNEL MANAGER CLOSED: {:?}", wrapper.tunnel);
+        return false;
+    }
+    true
+}
+
+async fn drain(wrapper: &TunnelWrapper) {
+    let timeout = match wrapper.tunnel.drain_timeout {
+        Some(timeout) if timeout > 0 => Duration::from_secs(u64::from(timeout)),
+        _ => return,
+    };
+    let clients = wrapper.event_sx.clients();
+    let deadline = time::Instant::now() + timeout;
+    while !clients.is_empty() && time::Instant::now() < deadline {
+        time::sleep(DRAIN_CHECK).await;
+    }
+    if !clients.is_empty() {
+        log::info!("DRAIN TIMEOUT: {} {} CLIENTS LEFT", wrapper.tunnel.local_port, clients.list().len());
+        clients.kill(None);
+    }
+}
+
+// a client on its way to the forwarder, given up once the tunnel shuts down
+pub async fn cancellable<F: Future<Output = ()>>(start: F, mut cancelled: watch::Receiver<bool>) {
+    let cancel = async move {
+        while !*cancelled.borrow() {
+            if cancelled.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+    select! {
+        _ = start => {},
+        _ = cancel => {},
+    }
+}
+
+fn notify(events: &TunnelEventSender, state: TunnelState) {
+    if let Err(e) = events.try_send(TunnelEvent::State(state)) {
+        log::warn!("TUNNEL EVENT DROPPED: {}", e);
+    }
+}
+
+// logs in again with exponential backoff until it works, the attempts run out or a state change is requested.
+// a remote tunnel gets its forward back as well, the error side carries the state the tunnel ends in
+async fn recover(wrapper: &TunnelWrapper, shared: &SharedSession, rx: &mut Receiver<TunnelState>) -> Result<Option<(Forwarder, Receiver<SSHChannel>)>, TunnelState> {
+    let t = &wrapper.tunnel;
+    let attempts = t.reconnect_attempts.unwrap_or(RECONNECT_DEFAULT_ATTEMPTS);
+    let mut delay = Duration::from_millis(t.reconnect_delay.unwrap_or(RECONNECT_DEFAULT_DELAY));
+    notify(&wrapper.event_sx, TunnelState::RECONNECTING);
+
+    for attempt in 1..=attempts {
+        log::info!("RECONNECT {}/{}: {}:{}", attempt, attempts, t.ssh_host, t.ssh_port);
+        let attempt_rs = async {
+            shared.reconnect().await?;
+            match t.kind {
+                // the server may hold the port of the dead session a little longer, worth another try
+                TunnelKind::Remote => listen_remote(wrapper, shared).await.map(Some).map_err(|e| e.with_retryable(true)),
+                _ => Ok(None),
+            }
+        };
+        let rs = select! {
+            rs = attempt_rs => rs,
+            message = rx.recv() => match message {
+                Some(state) => return Err(state),
+                None => Err(PluginError::build(ErrorKind::Internal, "TUNNEL MANAGER CLOSED!!!").with_retryable(false)),
+            },
+        };
+        let e = match rs {
+            Ok(recovered) => {
+                notify(&wrapper.event_sx, TunnelState::RUNNING);
+                return Ok(recovered);
+            },
+            Err(e) => e.with_context("attempt", format!("{}/{}", attempt, attempts).as_str()),
+        };
+        let retryable = e.retryable;
+        report(&wrapper.event_sx, e);
+        if !retryable || attempt == attempts {
+            break;
+        }
+        select! {
+            _ = time::sleep(delay) => {},
+            message = rx.recv() => {
+                if let Some(state) = message {
+                    return Err(state);
+                }
+            },
+        }
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+    Err(TunnelState::FAILED)
+}
+
+// a remote port 0 lets the server pick one, it may be another one after every reconnect
+async fn listen_remote(wrapper: &TunnelWrapper, shared: &SharedSession) -> TunnelResult<(Forwarder, Receiver<SSHChannel>)> {
+    let t = &wrapper.tunnel;
+    let (forwarder, accepted, bound) = shared.listen(t.remote_host.as_str(), t.remote_port).await?;
+    log::info!("REMOTE FORWARD LISTENING: {}:{}", t.remote_host, bound);
+    wrapper.event_sx.bound(bound);
+    Ok((forwarder, accepted))
+}
+
+async fn listen_local(t: &Tunnel) -> TunnelResult<(TcpListener, Allowlist)> {
+    let allowlist = Allowlist::parse(t)?;
+    let addr = bind_addr(t)?;
+    let listener = TcpListener::bind(addr).await.map_err(|e| e.convert()
+        .with_context("stage", "bind")
+        .with_context("bind_address", addr.to_string().as_str()))?;
+    Ok((listener, allowlist))
+}
+
+// serves until a state change is requested, `None` when the tunnel broke down by itself
+async fn serve_local(listener: &TcpListener, allowlist: &Allowlist, wrapper: &TunnelWrapper, shared: &SharedSession, cancelled: &watch::Receiver<bool>, rx: &mut Receiver<TunnelState>) -> Option<TunnelState> {
+    let mut watch = time::interval(SESSION_WATCH);
     loop {
         select! {
             client = listener.accept() => {
-                let (stream, _) = match client {
-                    Err(_) => break,
+                let (stream, peer) = match client {
+                    Err(e) => {
+                        report(&wrapper.event_sx, e.convert().with_context("stage", "accept"));
+                        return None;
+                    },
                     Ok(client) => client,
                 };
-                let tunnel = wrapper.tunnel.clone();
-                tokio::spawn(start_ssh(stream, tunnel));
+                if !allowlist.allows(peer.ip()) {
+                    // dropping the stream is all the refused client gets to see
+                    report(&wrapper.event_sx, PluginError::build(ErrorKind::Auth, "CLIENT NOT ALLOWED!!!")
+                        .with_retryable(false)
+                        .with_context("stage", "accept")
+                        .with_context("local_port", wrapper.tunnel.local_port.to_string().as_str())
+                        .with_context("peer", peer.to_string().as_str()));
+                    continue;
+                }
+                match wrapper.tunnel.kind {
+                    TunnelKind::Dynamic => tokio::spawn(cancellable(start_socks(stream, peer.to_string(), shared.clone(), wrapper.tunnel.clone(), wrapper.event_sx.clone()), cancelled.clone())),
+                    _ => tokio::spawn(cancellable(start_ssh(stream, peer.to_string(), shared.clone(), wrapper.event_sx.clone()), cancelled.clone())),
+                };
             },
             message = rx.recv() => {
                 match message {
@@ -46,109 +264,233 @@ pub async fn poll(wrapper: TunnelWrapper, mut rx: Receiver<TunnelState>) {
                         // tokio::task::yield_now().await;
                         continue;
                     },
-                    Some(state) => {
-                        wrapper.sx.send(Ok(state)).await.unwrap();
-                        return;
+                    Some(state) => return Some(state),
+                }
+            },
+            _ = watch.tick() => {
+                if !shared.alive().await {
+                    return None;
+                }
+            },
+        }
+    }
+}
+
+async fn serve_remote(forwarder: Forwarder, mut accepted: Receiver<SSHChannel>, wrapper: &TunnelWrapper, shared: &SharedSession, cancelled: &watch::Receiver<bool>, rx: &mut Receiver<TunnelState>) -> Option<TunnelState> {
+    let target = format!("{}:{}", wrapper.tunnel.local_host.as_deref().filter(|host| !host.is_empty()).unwrap_or(REMOTE_DEFAULT_TARGET), wrapper.tunnel.local_port);
+    loop {
+        select! {
+            c = accepted.recv() => {
+                match c {
+                    // the forwarder is gone and has reported why
+                    None => return None,
+                    Some(c) => {
+                        tokio::spawn(cancellable(start_reverse(c, target.clone(), forwarder.clone(), shared.clone(), wrapper.tunnel.clone(), wrapper.event_sx.clone()), cancelled.clone()));
                     },
                 }
             },
+            message = rx.recv() => {
+                match message {
+                    None => continue,
+                    Some(state) => return Some(state),
+                }
+            },
         }
     }
-    wrapper.sx.send(Ok(TunnelState::STOP)).await.unwrap();
-    log::info!("STOP CLIENT: {:?}", wrapper.tunnel);
 }
 
-async fn start_ssh(stream: TcpStream, t: Tunnel) {
-    let mut s= Session::new().unwrap();
-    let addr = format!("{}:{}", t.ssh_host, t.ssh_port);
-    let ssh_stream = TcpStream::connect(addr.as_str()).await.unwrap();
-    s.set_tcp_stream(ssh_stream.into_std().unwrap());
-    s.handshake().unwrap();
+pub fn report(events: &TunnelEventSender, e: PluginError) {
+    log::error!("TUNNEL FAILURE: {}", e);
+    if let Err(e) = events.try_send(TunnelEvent::Error(e)) {
+        log::warn!("TUNNEL EVENT DROPPED: {}", e);
+    }
+}
 
-    let t_replica = t.clone();
-    match t_replica {
-        Tunnel{password: Some(password), private_key: None, username, ..} => {
-            s.userauth_password(&username, &password).unwrap();
-        },
-        Tunnel{password: Some(password), private_key: Some(privatekey), username, ..} if privatekey == "" && password != "" => {
-            s.userauth_password(&username, &password).unwrap();
-        },
-        Tunnel{password: None, private_key: Some(privatekey), username, ..} => {
-            s.userauth_pubkey_file(username.as_str(), None, Path::new(privatekey.as_str()), None).unwrap();
-        },
-        Tunnel{password: Some(password), private_key: Some(privatekey), username, ..} if password == "" && privatekey != "" => {
-            s.userauth_pubkey_file(username.as_str(), None, Path::new(privatekey.as_str()), None).unwrap();
+pub fn tunnel_error<E: ConvertToPluginError>(e: E, t: &Tunnel, stage: &str) -> PluginError {
+    e.convert()
+        .with_context("stage", stage)
+        .with_context("local_port", t.local_port.to_string().as_str())
+        .with_context("ssh_host", format!("{}:{}", t.ssh_host, t.ssh_port).as_str())
+}
+
+pub fn stage<T, E: ConvertToPluginError>(rs: Result<T, E>, t: &Tunnel, stage: &str) -> TunnelResult<T> {
+    rs.map_err(|e| tunnel_error(e, t, stage))
+}
+
+// the returned socket is a clone of the session's, only used to wait for readiness,
+// `t.proxy` is passed on the way when the tunnel has one
+pub async fn connect(t: &Tunnel) -> TunnelResult<(Session, TcpStream)> {
+    let mut s = stage(Session::new(), t, "connect")?;
+    let ssh_stream = match t.proxy.as_ref() {
+        Some(p) => match time::timeout(SSH_CONNECT_TIMEOUT, proxy::connect(t, p)).await {
+            Ok(ssh_stream) => stage(ssh_stream, t, "connect")?,
+            Err(_) => return stage(Err(PluginError::build(ErrorKind::Timeout, "PROXY CONNECT TIMEOUT!!!")), t, "connect"),
         },
-        Tunnel{password: Some(password), private_key: Some(privatekey), username, ..} => {
-            s.userauth_pubkey_file(username.as_str(), None, Path::new(privatekey.as_str()), Some(password.as_str())).unwrap();
+        None => {
+            let addr = format!("{}:{}", t.ssh_host, t.ssh_port);
+            let ssh_stream = match time::timeout(SSH_CONNECT_TIMEOUT, TcpStream::connect(addr.as_str())).await {
+                Ok(ssh_stream) => stage(ssh_stream, t, "connect")?,
+                Err(_) => return stage(Err(PluginError::build(ErrorKind::Timeout, "SSH CONNECT TIMEOUT!!!")), t, "connect"),
+            };
+            stage(ssh_stream.into_std(), t, "connect")?
         },
-        _ => (),
-    }
-    let c = s.channel_direct_tcpip(t.remote_host.as_str(), t.remote_port, None).unwrap();
+    };
+    let socket = stage(ssh_stream.try_clone().and_then(TcpStream::from_std), t, "connect")?;
+    s.set_tcp_stream(ssh_stream);
+    s.set_timeout(SSH_CONNECT_TIMEOUT.as_millis() as u32);
+    Ok((s, socket))
+}
 
-    let ssh_stream = c.stream(0);
-    let (sx, rx) = channel::<Payload>(1024);
-    s.set_blocking(false);
-    let t_replica = t.clone();
-    tokio::spawn(poll_client(t, stream, s, c, rx));
-    tokio::spawn(poll_ssh(t_replica, ssh_stream, sx));
+// a connected loopback pair, the far end is checked to really be ours
+pub fn loopback() -> std::io::Result<(StdTcpStream, StdTcpStream)> {
+    let listener = StdTcpListener::bind("127.0.0.1:0")?;
+    let near = StdTcpStream::connect(listener.local_addr()?)?;
+    let (far, peer) = listener.accept()?;
+    if peer != near.local_addr()? {
+        return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "unexpected loopback peer"));
+    }
+    Ok((near, far))
 }
 
-async fn poll_client(tunnel: Tunnel, mut stream: TcpStream, session: Session, c: SSHChannel, mut rx: Receiver<Payload>) {
+// reaches `t.ssh_host` through a direct-tcpip channel of the previous hop, the channel is pumped
+// into a loopback socket by the previous hop's forwarder so libssh2 gets a real socket to talk on
+pub async fn connect_via(jump: &Link, t: &Tunnel) -> TunnelResult<(Session, TcpStream)> {
+    let mut s = stage(Session::new(), t, "connect")?;
+    let c = direct_tcpip(&jump.session, t.ssh_host.as_str(), t.ssh_port, t, "connect").await?;
+    let (near, far) = stage(loopback(), t, "connect")?;
+    let far = stage(far.set_nonblocking(true).and_then(|_| TcpStream::from_std(far)), t, "connect")?;
+    jump.forwarder.attach(c, far, None, None).await.map_err(|e| tunnel_error(e, t, "connect"))?;
+    let socket = stage(near.try_clone().and_then(|socket| {
+        socket.set_nonblocking(true)?;
+        TcpStream::from_std(socket)
+    }), t, "connect")?;
+    s.set_tcp_stream(near);
+    s.set_timeout(SSH_CONNECT_TIMEOUT.as_millis() as u32);
+    Ok((s, socket))
+}
 
+// channel_direct_tcpip on a session that may already be non-blocking
+pub async fn direct_tcpip(s: &Session, host: &str, port: u16, t: &Tunnel, stage_name: &str) -> TunnelResult<SSHChannel> {
     loop {
+        match s.channel_direct_tcpip(host, port, None) {
+            Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                time::sleep(SSH_EAGAIN_BACKOFF).await;
+            },
+            rs => return stage(rs, t, stage_name),
+        }
+    }
+}
 
-        let mut request = vec![0; 16 * 1024];
-        let mut ssh_stream = c.stream(0);
+pub fn handshake(s: &mut Session, t: &Tunnel) -> TunnelResult<()> {
+    stage(s.handshake(), t, "handshake")
+}
 
-        select! {
-            size = stream.read(&mut request) => {
-                let size = match size {
-                    Ok(size) if size == 0 => break,
-                    Ok(size) => size,
-                    Err(_) => break,
-                };
+pub fn authenticate(s: &Session, t: &Tunnel, prompter: &Prompter) -> TunnelResult<()> {
+    if t.interactive {
+        // a key or the agent can be the first factor only, the prompts finish the login
+        let has_key = t.private_key.as_ref().filter(|key| !key.is_empty()).is_some();
+        if t.agent || has_key {
+            if let Err(e) = first_factor(s, t) {
+                log::info!("FIRST FACTOR FAILED, TRY KEYBOARD-INTERACTIVE: {}", e);
+            }
+        }
+        if !s.authenticated() {
+            stage(interactive::authenticate(s, t, prompter), t, "auth")?;
+        }
+        return authenticated(s, t);
+    }
+    first_factor(s, t)?;
+    authenticated(s, t)
+}
 
-                ssh_stream.write_all(&request[..size]).unwrap();
-            },
-            payload = rx.recv() => {
-                let payload = match payload {
-                    None => break,
-                    Some(payload) => payload,
-                };
+fn first_factor(s: &Session, t: &Tunnel) -> TunnelResult<()> {
+    if t.agent {
+        return stage(agent::authenticate(s, t), t, "auth");
+    }
+    let password = t.password.as_deref().filter(|password| !password.is_empty());
+    let private_key = t.private_key.as_deref().filter(|key| !key.trim().is_empty());
+    match (private_key, password) {
+        (Some(private_key), password) => {
+            // a password next to a key has always been its passphrase
+            let passphrase = t.passphrase.as_deref().filter(|passphrase| !passphrase.is_empty()).or(password);
+            let certificate = stage(cert::load(t), t, "auth")?;
+            if let Some(certificate) = certificate.as_ref() {
+                stage(cert::check(certificate, t.username.as_str()), t, "auth")?;
+            }
+            stage(keys::authenticate(s, t.username.as_str(), private_key, certificate.as_ref(), passphrase), t, "auth")
+        },
+        (None, Some(password)) => stage(s.userauth_password(t.username.as_str(), password), t, "auth"),
+        (None, None) => stage(Err(PluginError::build(ErrorKind::Validation, "NO CREDENTIALS!!!")), t, "auth"),
+    }
+}
 
-                stream.write_all(&payload.content[..payload.size]).await.unwrap();
-            },
-        }
+fn authenticated(s: &Session, t: &Tunnel) -> TunnelResult<()> {
+    if !s.authenticated() {
+        return stage(Err(PluginError::build(ErrorKind::Auth, "NOT AUTHENTICATED!!!")), t, "auth");
     }
-    
-    session.disconnect(None, "close", None).unwrap();
-    stream.shutdown().await.unwrap();
-    log::info!("CLIENT CLOSE: {:?}!!!", tunnel);
+    Ok(())
 }
 
-async fn poll_ssh(tunnel: Tunnel, mut stream: SSHStream, sx: Sender<Payload>) {
+pub fn open_channel(s: &Session, t: &Tunnel) -> TunnelResult<SSHChannel> {
+    stage(s.channel_direct_tcpip(t.remote_host.as_str(), t.remote_port, None), t, "channel")
+}
 
-    loop {
+async fn start_ssh(mut stream: TcpStream, peer: String, shared: SharedSession, events: TunnelEventSender) {
+    let rs = match shared.open().await {
+        Ok((forwarder, c, permit)) => forwarder.attach(c, stream, Some(peer), Some(permit)).await,
+        Err(e) => {
+            if let Err(e) = stream.shutdown().await {
+                log::warn!("CLIENT SHUTDOWN: {:?}", e);
+            }
+            Err(e)
+        },
+    };
+    if let Err(e) = rs {
+        report(&events, e);
+    }
+}
 
-        let mut response = vec![0; 16 * 1024];
-        let size = match stream.read(&mut response) {
-            Ok(size) if size == 0 => {
-                log::warn!("STREAM CLOSE!!!");
-                break
+// a channel the server opened on our remote forward, piped to the local target.
+// libssh2 does not tell who connected over there, the client goes by the remote listen address
+async fn start_reverse(c: SSHChannel, target: String, forwarder: Forwarder, shared: SharedSession, t: Tunnel, events: TunnelEventSender) {
+    let stream = match time::timeout(SSH_CONNECT_TIMEOUT, TcpStream::connect(target.as_str())).await {
+        Ok(stream) => stage(stream, &t, "reverse"),
+        Err(_) => stage(Err(PluginError::build(ErrorKind::Timeout, "LOCAL TARGET CONNECT TIMEOUT!!!")), &t, "reverse"),
+    };
+    let rs = match stream {
+        Ok(stream) => match shared.permit().await {
+            Ok(permit) => forwarder.attach(c, stream, Some(format!("{}:{}", t.remote_host, t.remote_port)), Some(permit)).await,
+            Err(e) => {
+                forwarder.discard(c).await;
+                Err(e)
             },
-            Ok(size) => size,
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                tokio::task::yield_now().await;
-                continue;
+        },
+        Err(e) => {
+            forwarder.discard(c).await;
+            Err(e.with_context("target", target.as_str()))
+        },
+    };
+    if let Err(e) = rs {
+        report(&events, e);
+    }
+}
+
+// a dynamic tunnel client first tells over SOCKS5 where it wants to go
+async fn start_socks(mut stream: TcpStream, peer: String, shared: SharedSession, t: Tunnel, events: TunnelEventSender) {
+    let rs = match socks::negotiate(&mut stream, &t).await {
+        Ok((host, port)) => match shared.open_to(host.as_str(), port).await {
+            Ok((forwarder, c, permit)) => match socks::succeeded(&mut stream).await {
+                Ok(_) => forwarder.attach(c, stream, Some(peer), Some(permit)).await,
+                Err(e) => Err(e),
             },
             Err(e) => {
-                log::error!("SSH INTERRUPED: {:?}!!!", e);
-                break
+                socks::failed(&mut stream, &e).await;
+                Err(e)
             },
-        };
-        sx.send(Payload{size, content: response}).await.unwrap();
+        },
+        Err(e) => Err(e),
+    };
+    if let Err(e) = rs {
+        report(&events, e.with_context("local_port", t.local_port.to_string().as_str()));
     }
-    sx.closed().await;
-    log::info!("SSH CLOSE: {:?}!!!", tunnel);
 }
\ No newline at end of file
@@ -0,0 +1,204 @@
+use ssh2::{Channel as SSHChannel, ErrorCode as SSH2ErrorCode, Session};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use super::forward::Forwarder;
+use super::interactive::Prompter;
+use super::probe::probe;
+use super::server::{direct_tcpip, stage, SSH_EAGAIN_BACKOFF};
+use super::{PluginError, Tunnel, TunnelEventSender, TunnelResult, LIBSSH2_ERROR_EAGAIN};
+use crate::plugins::error::ErrorKind;
+
+const SSH_DEFAULT_MAX_CHANNELS: u32 = 64;
+const SSH_CHANNEL_WAIT: Duration = Duration::from_secs(10);
+
+// a logged in session whose channels are driven by its own forwarder
+pub struct Link {
+    pub session: Session,
+    pub forwarder: Forwarder,
+    driver: JoinHandle<()>,
+}
+
+impl Link {
+    pub fn spawn(tunnel: Tunnel, session: Session, socket: TcpStream, events: TunnelEventSender) -> Link {
+        session.set_blocking(false);
+        let (forwarder, driver) = Forwarder::spawn(tunnel, session.clone(), socket, events);
+        Link { session, forwarder, driver }
+    }
+
+    pub async fn close(self) {
+        self.forwarder.shutdown().await;
+        if let Err(e) = self.driver.await {
+            log::warn!("FORWARDER JOIN: {:?}", e);
+        }
+        self.session.set_blocking(true);
+        if let Err(e) = self.session.disconnect(None, "close", None) {
+            log::warn!("SSH DISCONNECT: {:?}", e);
+        }
+    }
+}
+
+// the session to the tunnel's ssh_host together with the jump hosts carrying it
+pub struct Connected {
+    pub jumps: Vec<Link>,
+    pub session: Session,
+    pub socket: TcpStream,
+}
+
+impl Connected {
+    pub async fn close(self) {
+        if let Err(e) = self.session.disconnect(None, "close", None) {
+            log::warn!("SSH DISCONNECT: {:?}", e);
+        }
+        close_jumps(self.jumps).await;
+    }
+}
+
+pub async fn close_jumps(jumps: Vec<Link>) {
+    for jump in jumps.into_iter().rev() {
+        jump.close().await;
+    }
+}
+
+struct Chain {
+    jumps: Vec<Link>,
+    target: Link,
+}
+
+impl Chain {
+    async fn close(self) {
+        self.target.close().await;
+        close_jumps(self.jumps).await;
+    }
+
+    fn is_closed(&self) -> bool {
+        self.target.forwarder.is_closed() || self.jumps.iter().any(|jump| jump.forwarder.is_closed())
+    }
+}
+
+// one authenticated session per running tunnel, every client gets its own channel on it
+#[derive(Clone)]
+pub struct SharedSession {
+    tunnel: Tunnel,
+    link: Arc<Mutex<Option<Chain>>>,
+    permits: Arc<Semaphore>,
+    events: TunnelEventSender,
+    prompter: Prompter,
+}
+
+impl SharedSession {
+    pub fn new(tunnel: Tunnel, connected: Option<Connected>, events: TunnelEventSender, prompter: Prompter) -> SharedSession {
+        let max_channels = tunnel.max_channels.unwrap_or(SSH_DEFAULT_MAX_CHANNELS).max(1);
+        let shared = SharedSession {
+            tunnel,
+            link: Arc::new(Mutex::new(None)),
+            permits: Arc::new(Semaphore::new(max_channels as usize)),
+            events,
+            prompter,
+        };
+        if let Some(connected) = connected {
+            let chain = shared.spawn_chain(connected);
+            if let Ok(mut current) = shared.link.try_lock() {
+                *current = Some(chain);
+            }
+        }
+        shared
+    }
+
+    fn spawn_chain(&self, connected: Connected) -> Chain {
+        let target = Link::spawn(self.tunnel.clone(), connected.session, connected.socket, self.events.clone());
+        Chain { jumps: connected.jumps, target }
+    }
+
+    async fn login(&self) -> TunnelResult<Connected> {
+        let (_, rs) = probe(&self.tunnel, &self.prompter, &self.events).await;
+        let connected = rs?;
+        log::info!("SSH SESSION READY: {}:{}", self.tunnel.ssh_host, self.tunnel.ssh_port);
+        Ok(connected)
+    }
+
+    async fn try_channel(&self, s: &Session, host: &str, port: u16) -> TunnelResult<SSHChannel> {
+        direct_tcpip(s, host, port, &self.tunnel, "channel").await
+            .map_err(|e| e.with_context("destination", format!("{}:{}", host, port).as_str()))
+    }
+
+    // opens a channel to the tunnel's remote for the next client
+    pub async fn open(&self) -> TunnelResult<(Forwarder, SSHChannel, OwnedSemaphorePermit)> {
+        self.open_to(self.tunnel.remote_host.as_str(), self.tunnel.remote_port).await
+    }
+
+    pub async fn permit(&self) -> TunnelResult<OwnedSemaphorePermit> {
+        match time::timeout(SSH_CHANNEL_WAIT, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(PluginError::build(ErrorKind::Unavailable, "TOO MANY CHANNELS!!!")
+                .with_context("local_port", self.tunnel.local_port.to_string().as_str())),
+        }
+    }
+
+    // opens a channel for the next client. a session found dead is dropped here,
+    // logging in again is left to the tunnel's reconnect policy
+    pub async fn open_to(&self, host: &str, port: u16) -> TunnelResult<(Forwarder, SSHChannel, OwnedSemaphorePermit)> {
+        let permit = self.permit().await?;
+
+        let mut link = self.link.lock().await;
+        let current = match link.as_ref().filter(|current| !current.is_closed()) {
+            Some(current) => current,
+            None => return Err(PluginError::build(ErrorKind::Connection, "SSH SESSION CLOSED!!!")
+                .with_context("local_port", self.tunnel.local_port.to_string().as_str())),
+        };
+        match self.try_channel(&current.target.session, host, port).await {
+            Ok(c) => Ok((current.target.forwarder.clone(), c, permit)),
+            Err(e) if e.kind == ErrorKind::Connection || e.kind == ErrorKind::Timeout => {
+                log::warn!("SSH SESSION LOST: {}", e);
+                if let Some(dead) = link.take() {
+                    dead.close().await;
+                }
+                Err(e)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn alive(&self) -> bool {
+        matches!(self.link.lock().await.as_ref(), Some(current) if !current.is_closed())
+    }
+
+    // replaces whatever is left of the previous session by a freshly logged in one
+    pub async fn reconnect(&self) -> TunnelResult<()> {
+        let mut link = self.link.lock().await;
+        if let Some(dead) = link.take() {
+            dead.close().await;
+        }
+        *link = Some(self.spawn_chain(self.login().await?));
+        Ok(())
+    }
+
+    // asks the server to listen on `host:port` for us, its channels come in through the returned receiver.
+    // a remote forward lives and dies with its session, it has to be asked for again after a reconnect
+    pub async fn listen(&self, host: &str, port: u16) -> TunnelResult<(Forwarder, Receiver<SSHChannel>, u16)> {
+        let link = self.link.lock().await;
+        let current = match link.as_ref().filter(|current| !current.is_closed()) {
+            Some(current) => current,
+            None => return Err(PluginError::build(ErrorKind::Connection, "SSH SESSION CLOSED!!!")),
+        };
+        let (listener, bound) = loop {
+            match current.target.session.channel_forward_listen(port, Some(host), None) {
+                Err(e) if e.code() == SSH2ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => time::sleep(SSH_EAGAIN_BACKOFF).await,
+                rs => break stage(rs, &self.tunnel, "listen")?,
+            }
+        };
+        let accepted = current.target.forwarder.listen(listener).await?;
+        Ok((current.target.forwarder.clone(), accepted, bound))
+    }
+
+    pub async fn disconnect(&self) {
+        if let Some(current) = self.link.lock().await.take() {
+            current.close().await;
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,393 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time;
+
+use super::{PluginError, SocksAuth, Tunnel, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_AUTH_VERSION: u8 = 0x01;
+const SOCKS_NEGOTIATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+// reply codes of RFC 1928
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_NOT_ALLOWED: u8 = 0x02;
+const REPLY_NETWORK_UNREACHABLE: u8 = 0x03;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_CONNECTION_REFUSED: u8 = 0x05;
+const REPLY_TTL_EXPIRED: u8 = 0x06;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+fn socks_error(message: &str) -> PluginError {
+    PluginError::build(ErrorKind::Tunnel, message)
+        .with_retryable(false)
+        .with_context("stage", "socks")
+}
+
+async fn read_u8(stream: &mut TcpStream) -> TunnelResult<u8> {
+    stream.read_u8().await.map_err(|e| e.convert().with_context("stage", "socks"))
+}
+
+async fn read_bytes(stream: &mut TcpStream, len: usize) -> TunnelResult<Vec<u8>> {
+    let mut buf = vec![0; len];
+    stream.read_exact(&mut buf).await.map_err(|e| e.convert().with_context("stage", "socks"))?;
+    Ok(buf)
+}
+
+async fn write(stream: &mut TcpStream, bytes: &[u8]) -> TunnelResult<()> {
+    stream.write_all(bytes).await.map_err(|e| e.convert().with_context("stage", "socks"))
+}
+
+// RFC 1929 username/password sub-negotiation
+async fn login(stream: &mut TcpStream, auth: &SocksAuth) -> TunnelResult<()> {
+    if read_u8(stream).await? != SOCKS_AUTH_VERSION {
+        return Err(socks_error("UNSUPPORTED SOCKS AUTH VERSION!!!"));
+    }
+    let len = read_u8(stream).await? as usize;
+    let username = read_bytes(stream, len).await?;
+    let len = read_u8(stream).await? as usize;
+    let password = read_bytes(stream, len).await?;
+
+    if username == auth.username.as_bytes() && password == auth.password.as_bytes() {
+        write(stream, &[SOCKS_AUTH_VERSION, 0x00]).await
+    } else {
+        write(stream, &[SOCKS_AUTH_VERSION, 0x01]).await?;
+        Err(PluginError::build(ErrorKind::Auth, "SOCKS AUTH FAILED!!!")
+            .with_retryable(false)
+            .with_context("stage", "socks"))
+    }
+}
+
+async fn greet(stream: &mut TcpStream, auth: Option<&SocksAuth>) -> TunnelResult<()> {
+    if read_u8(stream).await? != SOCKS_VERSION {
+        return Err(socks_error("UNSUPPORTED SOCKS VERSION!!!"));
+    }
+    let len = read_u8(stream).await? as usize;
+    let methods = read_bytes(stream, len).await?;
+    let wanted = if auth.is_some() { METHOD_PASSWORD } else { METHOD_NONE };
+    if !methods.contains(&wanted) {
+        write(stream, &[SOCKS_VERSION, METHOD_UNACCEPTABLE]).await?;
+        return Err(socks_error("NO ACCEPTABLE SOCKS METHOD!!!"));
+    }
+    write(stream, &[SOCKS_VERSION, wanted]).await?;
+    match auth {
+        Some(auth) => login(stream, auth).await,
+        None => Ok(()),
+    }
+}
+
+async fn request(stream: &mut TcpStream) -> TunnelResult<(String, u16)> {
+    let head = read_bytes(stream, 4).await?;
+    if head[0] != SOCKS_VERSION {
+        return Err(socks_error("UNSUPPORTED SOCKS VERSION!!!"));
+    }
+    let host = match head[3] {
+        ATYP_IPV4 => {
+            let ip = read_bytes(stream, 4).await?;
+            Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).to_string()
+        },
+        ATYP_DOMAIN => {
+            let len = read_u8(stream).await? as usize;
+            String::from_utf8_lossy(&read_bytes(stream, len).await?).into_owned()
+        },
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&read_bytes(stream, 16).await?);
+            Ipv6Addr::from(ip).to_string()
+        },
+        _ => {
+            reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(socks_error("UNSUPPORTED SOCKS ADDRESS TYPE!!!"));
+        },
+    };
+    let port = read_bytes(stream, 2).await?;
+    let port = u16::from_be_bytes([port[0], port[1]]);
+
+    if head[1] != CMD_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(socks_error("ONLY SOCKS CONNECT IS SUPPORTED!!!")
+            .with_context("command", head[1].to_string().as_str()));
+    }
+    Ok((host, port))
+}
+
+// the bound address is not known on our side of the channel, clients ignore it for CONNECT anyway
+async fn reply(stream: &mut TcpStream, code: u8) -> TunnelResult<()> {
+    write(stream, &[SOCKS_VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+// runs the SOCKS5 handshake of a freshly accepted client and returns the destination it asked for,
+// the client waits for `succeeded` or `failed` before any data flows
+pub async fn negotiate(stream: &mut TcpStream, t: &Tunnel) -> TunnelResult<(String, u16)> {
+    let auth = t.socks_auth.as_ref().filter(|auth| !auth.username.is_empty());
+    let rs = time::timeout(SOCKS_NEGOTIATE_TIMEOUT, async {
+        greet(stream, auth).await?;
+        request(stream).await
+    }).await;
+    match rs {
+        Ok(rs) => rs,
+        Err(_) => Err(PluginError::build(ErrorKind::Timeout, "SOCKS NEGOTIATE TIMEOUT!!!").with_context("stage", "socks")),
+    }
+}
+
+pub async fn succeeded(stream: &mut TcpStream) -> TunnelResult<()> {
+    reply(stream, REPLY_SUCCEEDED).await
+}
+
+// tells the client why its destination could not be reached through the ssh session
+pub async fn failed(stream: &mut TcpStream, e: &PluginError) {
+    let code = match e.kind {
+        ErrorKind::Tunnel => REPLY_CONNECTION_REFUSED,
+        ErrorKind::Timeout => REPLY_TTL_EXPIRED,
+        ErrorKind::Connection => REPLY_HOST_UNREACHABLE,
+        _ => REPLY_GENERAL_FAILURE,
+    };
+    if let Err(e) = reply(stream, code).await {
+        log::warn!("SOCKS REPLY: {}", e);
+    }
+}
+
+// RFC 1929 from the client side, a proxy that refuses the credentials is not asked again
+async fn present(stream: &mut TcpStream, auth: &SocksAuth) -> TunnelResult<()> {
+    if auth.username.len() > 255 || auth.password.len() > 255 {
+        return Err(socks_error("SOCKS CREDENTIALS TOO LONG!!!"));
+    }
+    let mut request = vec![SOCKS_AUTH_VERSION, auth.username.len() as u8];
+    request.extend_from_slice(auth.username.as_bytes());
+    request.push(auth.password.len() as u8);
+    request.extend_from_slice(auth.password.as_bytes());
+    write(stream, &request).await?;
+    let status = read_bytes(stream, 2).await?;
+    if status[1] != 0x00 {
+        return Err(PluginError::build(ErrorKind::Auth, "SOCKS PROXY AUTH FAILED!!!")
+            .with_retryable(false)
+            .with_context("stage", "socks"));
+    }
+    Ok(())
+}
+
+fn refused(code: u8) -> PluginError {
+    let message = match code {
+        REPLY_NOT_ALLOWED => "SOCKS PROXY NOT ALLOWED!!!",
+        REPLY_NETWORK_UNREACHABLE => "SOCKS PROXY NETWORK UNREACHABLE!!!",
+        REPLY_HOST_UNREACHABLE => "SOCKS PROXY HOST UNREACHABLE!!!",
+        REPLY_CONNECTION_REFUSED => "SOCKS PROXY CONNECTION REFUSED!!!",
+        REPLY_TTL_EXPIRED => "SOCKS PROXY TTL EXPIRED!!!",
+        _ => "SOCKS PROXY FAILURE!!!",
+    };
+    PluginError::build(ErrorKind::Connection, message)
+        .with_context("stage", "socks")
+        .with_context("reply", code.to_string().as_str())
+}
+
+// asks an upstream SOCKS5 proxy for a connection to `host:port`, the stream carries it once this returns,
+// a host name is left to the proxy to resolve
+pub async fn connect(stream: &mut TcpStream, host: &str, port: u16, auth: Option<&SocksAuth>) -> TunnelResult<()> {
+    match auth {
+        Some(_) => write(stream, &[SOCKS_VERSION, 2, METHOD_NONE, METHOD_PASSWORD]).await?,
+        None => write(stream, &[SOCKS_VERSION, 1, METHOD_NONE]).await?,
+    }
+    let choice = read_bytes(stream, 2).await?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(socks_error("UNSUPPORTED SOCKS VERSION!!!"));
+    }
+    match (choice[1], auth) {
+        (METHOD_NONE, _) => {},
+        (METHOD_PASSWORD, Some(auth)) => present(stream, auth).await?,
+        (METHOD_PASSWORD, None) => return Err(PluginError::build(ErrorKind::Auth, "SOCKS PROXY WANTS CREDENTIALS!!!")
+            .with_retryable(false)
+            .with_context("stage", "socks")),
+        _ => return Err(socks_error("NO ACCEPTABLE SOCKS METHOD!!!")),
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_) if host.len() <= 255 => {
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+        Err(_) => return Err(socks_error("SOCKS HOST NAME TOO LONG!!!")),
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    write(stream, &request).await?;
+
+    let head = read_bytes(stream, 4).await?;
+    if head[0] != SOCKS_VERSION {
+        return Err(socks_error("UNSUPPORTED SOCKS VERSION!!!"));
+    }
+    if head[1] != REPLY_SUCCEEDED {
+        return Err(refused(head[1]));
+    }
+    // the address the proxy bound for us, nothing to do with it
+    let len = match head[3] {
+        ATYP_IPV4 => 4,
+        ATYP_DOMAIN => read_u8(stream).await? as usize,
+        ATYP_IPV6 => 16,
+        _ => return Err(socks_error("UNSUPPORTED SOCKS ADDRESS TYPE!!!")),
+    };
+    read_bytes(stream, len + 2).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    // what the client sends in one go, what negotiate makes of it and what the client gets back
+    struct Case {
+        name: &'static str,
+        auth: Option<(&'static str, &'static str)>,
+        sent: Vec<u8>,
+        expected: Result<(&'static str, u16), &'static str>,
+        replied: Vec<u8>,
+    }
+
+    fn bytes(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    const FAILED_BIND: [u8; 8] = [0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+
+    fn cases() -> Vec<Case> {
+        vec![
+            Case {
+                name: "ipv4",
+                auth: None,
+                sent: bytes(&[&[5, 1, METHOD_NONE], &[5, CMD_CONNECT, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x90]]),
+                expected: Ok(("10.0.0.1", 8080)),
+                replied: vec![5, METHOD_NONE],
+            },
+            Case {
+                name: "domain",
+                auth: None,
+                sent: bytes(&[&[5, 2, METHOD_PASSWORD, METHOD_NONE], &[5, CMD_CONNECT, 0, ATYP_DOMAIN, 9], b"localhost", &[0, 22]]),
+                expected: Ok(("localhost", 22)),
+                replied: vec![5, METHOD_NONE],
+            },
+            Case {
+                name: "ipv6",
+                auth: None,
+                sent: bytes(&[&[5, 1, METHOD_NONE, 5, CMD_CONNECT, 0, ATYP_IPV6], &Ipv6Addr::LOCALHOST.octets(), &[1, 0xbb]]),
+                expected: Ok(("::1", 443)),
+                replied: vec![5, METHOD_NONE],
+            },
+            Case {
+                name: "password",
+                auth: Some(("su", "sp")),
+                sent: bytes(&[&[5, 1, METHOD_PASSWORD, SOCKS_AUTH_VERSION, 2], b"su", &[2], b"sp", &[5, CMD_CONNECT, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80]]),
+                expected: Ok(("127.0.0.1", 80)),
+                replied: vec![5, METHOD_PASSWORD, SOCKS_AUTH_VERSION, 0],
+            },
+            Case {
+                name: "wrong password",
+                auth: Some(("su", "sp")),
+                sent: bytes(&[&[5, 1, METHOD_PASSWORD, SOCKS_AUTH_VERSION, 2], b"su", &[2], b"xx"]),
+                expected: Err("SOCKS AUTH FAILED!!!"),
+                replied: vec![5, METHOD_PASSWORD, SOCKS_AUTH_VERSION, 1],
+            },
+            Case {
+                name: "password required",
+                auth: Some(("su", "sp")),
+                sent: vec![5, 1, METHOD_NONE],
+                expected: Err("NO ACCEPTABLE SOCKS METHOD!!!"),
+                replied: vec![5, METHOD_UNACCEPTABLE],
+            },
+            Case {
+                name: "no password configured",
+                auth: None,
+                sent: vec![5, 1, METHOD_PASSWORD],
+                expected: Err("NO ACCEPTABLE SOCKS METHOD!!!"),
+                replied: vec![5, METHOD_UNACCEPTABLE],
+            },
+            Case {
+                name: "socks4",
+                auth: None,
+                sent: vec![4, CMD_CONNECT, 0, 80, 127, 0, 0, 1, 0],
+                expected: Err("UNSUPPORTED SOCKS VERSION!!!"),
+                replied: vec![],
+            },
+            Case {
+                name: "bind",
+                auth: None,
+                sent: bytes(&[&[5, 1, METHOD_NONE], &[5, 2, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80]]),
+                expected: Err("ONLY SOCKS CONNECT IS SUPPORTED!!!"),
+                replied: bytes(&[&[5, METHOD_NONE], &[5, REPLY_COMMAND_NOT_SUPPORTED], &FAILED_BIND]),
+            },
+            Case {
+                name: "unknown address type",
+                auth: None,
+                sent: bytes(&[&[5, 1, METHOD_NONE], &[5, CMD_CONNECT, 0, 9]]),
+                expected: Err("UNSUPPORTED SOCKS ADDRESS TYPE!!!"),
+                replied: bytes(&[&[5, METHOD_NONE], &[5, REPLY_ADDRESS_NOT_SUPPORTED], &FAILED_BIND]),
+            },
+        ]
+    }
+
+    async fn run(case: &Case) -> (TunnelResult<(String, u16)>, Vec<u8>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        let t = tunnel(json!({
+            "kind": "dynamic",
+            "socks_auth": case.auth.map(|(username, password)| json!({"username": username, "password": password})),
+        }));
+
+        client.write_all(&case.sent).await.unwrap();
+        client.shutdown().await.unwrap();
+        let rs = negotiate(&mut server, &t).await;
+        // whatever a failed handshake left unread would reset the connection under the replies
+        let mut unread = Vec::new();
+        server.read_to_end(&mut unread).await.unwrap();
+        drop(server);
+        let mut replied = Vec::new();
+        client.read_to_end(&mut replied).await.unwrap();
+        (rs, replied)
+    }
+
+    #[tokio::test]
+    async fn negotiate_cases() {
+        for case in cases() {
+            let (rs, replied) = run(&case).await;
+            match (&rs, case.expected) {
+                (Ok((host, port)), Ok(expected)) => assert_eq!((host.as_str(), *port), expected, "{}", case.name),
+                (Err(e), Err(expected)) => assert_eq!(e.message, expected, "{}", case.name),
+                _ => panic!("{}: {:?}", case.name, rs),
+            }
+            assert_eq!(replied, case.replied, "{}", case.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiate_fails_on_a_short_handshake() {
+        let case = Case { name: "short", auth: None, sent: vec![5, 1], expected: Err(""), replied: vec![] };
+        let (rs, replied) = run(&case).await;
+        assert!(rs.is_err());
+        assert!(replied.is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,809 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::known_hosts::home;
+use super::{JumpHost, PluginError, ProxyKind, Tunnel, TunnelKind, TunnelProxy, TunnelResult, TunnelState};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+const SSH_DIR: &str = ".ssh";
+const SSH_CONFIG_FILE: &str = "config";
+const SSH_DEFAULT_PORT: u16 = 22;
+// same nesting limit as ssh(1)
+const INCLUDE_MAX_DEPTH: usize = 16;
+
+// one LocalForward of a config host, registered through ADD once the user picked it
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportCandidate {
+    pub alias: String,
+    pub forward: String,
+    pub tunnel: Tunnel,
+    pub warnings: Vec<String>,
+}
+
+// what import found, `warnings` are about the LocalForward lines that did not make a candidate
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportPreview {
+    pub candidates: Vec<ImportCandidate>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Option(String, Vec<String>),
+    Include(Vec<Item>),
+}
+
+// values of one host as ssh(1) resolves them: the first one obtained wins,
+// IdentityFile and LocalForward add up instead
+#[derive(Debug, Default)]
+struct HostOptions {
+    values: HashMap<String, Vec<String>>,
+    identity_files: Vec<String>,
+    local_forwards: Vec<Vec<String>>,
+    warnings: Vec<String>,
+}
+
+impl HostOptions {
+    fn get(&self, keyword: &str) -> Option<&str> {
+        self.values.get(keyword).and_then(|args| args.first()).map(|arg| arg.as_str())
+    }
+
+    fn set(&mut self, keyword: &str, args: &[String]) {
+        match keyword {
+            "identityfile" => self.identity_files.extend(args.iter().cloned()),
+            "localforward" => self.local_forwards.push(args.to_vec()),
+            _ => {
+                self.values.entry(String::from(keyword)).or_insert_with(|| args.to_vec());
+            },
+        }
+    }
+}
+
+fn config_file() -> TunnelResult<PathBuf> {
+    match home() {
+        Some(home) => Ok(home.join(SSH_DIR).join(SSH_CONFIG_FILE)),
+        None => Err(PluginError::build(ErrorKind::Internal, "NO HOME DIRECTORY!!!")),
+    }
+}
+
+fn local_user() -> String {
+    env::var("USER").or_else(|_| env::var("USERNAME")).unwrap_or_default()
+}
+
+// the keyword ends at whitespace or '=', then double quotes group an argument,
+// ProxyCommand keeps the rest of the line as it is for the shell
+fn tokenize(line: &str) -> Vec<String> {
+    let line = line.trim_start();
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut tokens = vec![String::from(&line[..end])];
+    if line[..end].eq_ignore_ascii_case("proxycommand") {
+        tokens.push(String::from(rest.trim_end()));
+        return tokens;
+    }
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut started = false;
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            },
+            '#' if !quoted && !started => break,
+            c if !quoted && c.is_whitespace() => {
+                if started {
+                    tokens.push(std::mem::take(&mut token));
+                    started = false;
+                }
+            },
+            c => {
+                token.push(c);
+                started = true;
+            },
+        }
+    }
+    if started {
+        tokens.push(token);
+    }
+    tokens
+}
+
+// `*` and `?` wildcards, the way ssh(1) matches host patterns
+fn wildcard(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| wildcard(rest, &text[i..])),
+        Some(('?', rest)) => !text.is_empty() && wildcard(rest, &text[1..]),
+        Some((c, rest)) => matches!(text.first(), Some(t) if t.eq_ignore_ascii_case(c)) && wildcard(rest, &text[1..]),
+    }
+}
+
+fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    wildcard(&pattern, &text)
+}
+
+// any positive pattern has to match and no negated one may
+fn pattern_list<'a>(patterns: impl Iterator<Item = &'a str>, text: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if matches(negated, text) => return false,
+            Some(_) => {},
+            None => matched = matched || matches(pattern, text),
+        }
+    }
+    matched
+}
+
+fn is_alias(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '!'])
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+// Include takes globs, relative paths are looked up in ~/.ssh like ssh(1) does for the user config
+fn include_paths(arg: &str, dir: &Path) -> Vec<PathBuf> {
+    let path = expand_home(arg);
+    let path = if path.is_absolute() { path } else { dir.join(path) };
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    if is_alias(name.as_str()) {
+        return vec![path];
+    }
+    let parent = path.parent().map(PathBuf::from).unwrap_or_default();
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&parent) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok())
+            .filter(|entry| matches(name.as_str(), entry.file_name().to_string_lossy().as_ref()))
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+fn parse(path: &Path, dir: &Path, depth: usize) -> TunnelResult<Vec<Item>> {
+    if depth > INCLUDE_MAX_DEPTH {
+        return Err(PluginError::build(ErrorKind::Validation, "SSH CONFIG INCLUDE NESTED TOO DEEPLY!!!")
+            .with_context("path", path.to_string_lossy().as_ref()));
+    }
+    let content = fs::read_to_string(path).map_err(|e| e.convert()
+        .with_context("path", path.to_string_lossy().as_ref()))?;
+    let mut items = Vec::new();
+    for line in content.lines() {
+        let mut tokens = tokenize(line).into_iter();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.is_empty() && !keyword.starts_with('#') => keyword.to_lowercase(),
+            _ => continue,
+        };
+        let args: Vec<String> = tokens.collect();
+        if keyword != "include" {
+            items.push(Item::Option(keyword, args));
+            continue;
+        }
+        // a missing include is no error for ssh(1) either
+        for include in args.iter().flat_map(|arg| include_paths(arg, dir)).filter(|path| path.is_file()) {
+            items.push(Item::Include(parse(&include, dir, depth + 1)?));
+        }
+    }
+    Ok(items)
+}
+
+fn aliases(items: &[Item], found: &mut Vec<String>) {
+    for item in items {
+        match item {
+            Item::Option(keyword, args) if keyword == "host" => {
+                for alias in args.iter().filter(|arg| is_alias(arg)) {
+                    if !found.contains(alias) {
+                        found.push(alias.clone());
+                    }
+                }
+            },
+            Item::Include(items) => aliases(items, found),
+            _ => {},
+        }
+    }
+}
+
+// criteria that need a connection or a shell (exec, canonical, localnetwork, ...) never match here
+fn match_block(args: &[String], alias: &str, options: &mut HostOptions) -> bool {
+    let mut args = args.iter();
+    let mut matched = true;
+    while let Some(criterion) = args.next() {
+        let (negated, criterion) = match criterion.strip_prefix('!') {
+            Some(criterion) => (true, criterion.to_lowercase()),
+            None => (false, criterion.to_lowercase()),
+        };
+        let rs = match criterion.as_str() {
+            "all" => true,
+            "host" | "originalhost" | "user" | "localuser" => {
+                let patterns = args.next().map(|arg| arg.as_str()).unwrap_or("");
+                let text = match criterion.as_str() {
+                    "host" => options.get("hostname").map(|hostname| hostname.replace("%h", alias)).unwrap_or_else(|| String::from(alias)),
+                    "originalhost" => String::from(alias),
+                    "user" => options.get("user").map(String::from).unwrap_or_else(local_user),
+                    _ => local_user(),
+                };
+                pattern_list(patterns.split(','), text.as_str())
+            },
+            _ => {
+                if criterion != "canonical" && criterion != "final" {
+                    args.next();
+                }
+                // only worth a warning when the rest of the line would have matched
+                let warning = format!("Match {} is not supported, block skipped", criterion);
+                if matched && !options.warnings.contains(&warning) {
+                    options.warnings.push(warning);
+                }
+                return false;
+            },
+        };
+        matched = matched && rs != negated;
+    }
+    matched
+}
+
+// walks the config for one alias, an Include runs under the block it sits in and
+// leaves that block active afterwards
+fn resolve(items: &[Item], alias: &str, options: &mut HostOptions, active: &mut bool, never: bool) {
+    for item in items {
+        match item {
+            Item::Option(keyword, args) if keyword == "host" => {
+                *active = !never && pattern_list(args.iter().map(|arg| arg.as_str()), alias);
+            },
+            Item::Option(keyword, args) if keyword == "match" => {
+                *active = !never && match_block(args, alias, options);
+            },
+            Item::Option(keyword, args) => {
+                if *active {
+                    options.set(keyword, args);
+                }
+            },
+            Item::Include(items) => {
+                let outer = *active;
+                resolve(items, alias, options, active, never || !outer);
+                *active = outer;
+            },
+        }
+    }
+}
+
+fn host_options(items: &[Item], alias: &str) -> HostOptions {
+    let mut options = HostOptions::default();
+    let mut active = true;
+    resolve(items, alias, &mut options, &mut active, false);
+    options
+}
+
+// the %-tokens ssh(1) expands in IdentityFile, CertificateFile and ProxyCommand
+pub fn expand_tokens(value: &str, alias: &str, hostname: &str, port: u16, user: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some('d') => expanded.push_str(home().map(|home| home.to_string_lossy().into_owned()).unwrap_or_default().as_str()),
+            Some('h') => expanded.push_str(hostname),
+            Some('n') => expanded.push_str(alias),
+            Some('p') => expanded.push_str(port.to_string().as_str()),
+            Some('r') => expanded.push_str(user),
+            Some('u') => expanded.push_str(local_user().as_str()),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            },
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+// "[host]:port", "host:port" or "host/port", the bracketed form carries IPv6 addresses
+fn split_host_port(spec: &str) -> Option<(String, u16)> {
+    let (host, port) = match spec.strip_prefix('[') {
+        Some(rest) => {
+            let end = rest.find(']')?;
+            (&rest[..end], rest[end + 1..].trim_start_matches([':', '/']))
+        },
+        None => {
+            let at = spec.rfind([':', '/'])?;
+            (&spec[..at], &spec[at + 1..])
+        },
+    };
+    Some((String::from(host), port.parse().ok()?))
+}
+
+// `[bind_address:]port`, an empty address or `*` listens on every interface
+fn listen_spec(spec: &str) -> Option<(Option<String>, u16)> {
+    if let Ok(port) = spec.parse::<u16>() {
+        return Some((None, port));
+    }
+    let (host, port) = split_host_port(spec)?;
+    let host = match host.as_str() {
+        "" | "*" => String::from("0.0.0.0"),
+        "localhost" => String::from("127.0.0.1"),
+        _ => host,
+    };
+    Some((Some(host), port))
+}
+
+struct Login {
+    ssh_host: String,
+    ssh_port: u16,
+    username: String,
+    private_key: Option<String>,
+    certificate: Option<String>,
+    warnings: Vec<String>,
+}
+
+fn login(options: &HostOptions, alias: &str) -> Login {
+    let ssh_host = options.get("hostname").map(|hostname| hostname.replace("%h", alias)).unwrap_or_else(|| String::from(alias));
+    let username = options.get("user").map(String::from).unwrap_or_else(local_user);
+    let mut warnings = options.warnings.clone();
+    let ssh_port = match options.get("port").map(|port| port.parse::<u16>()) {
+        None => SSH_DEFAULT_PORT,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            warnings.push(format!("invalid Port {}, using {}", options.get("port").unwrap_or(""), SSH_DEFAULT_PORT));
+            SSH_DEFAULT_PORT
+        },
+    };
+    // the first key that exists is used, without any the agent has to log in
+    let keys: Vec<String> = options.identity_files.iter()
+        .map(|key| expand_tokens(key, alias, ssh_host.as_str(), ssh_port, username.as_str()))
+        .map(|key| expand_home(key.as_str()).to_string_lossy().into_owned())
+        .collect();
+    let private_key = keys.iter().find(|key| Path::new(key.as_str()).is_file()).cloned();
+    if private_key.is_none() && !keys.is_empty() {
+        warnings.push(format!("IdentityFile {} not found, using the ssh agent", keys.join(", ")));
+    }
+    let certificate = options.get("certificatefile")
+        .map(|certificate| expand_tokens(certificate, alias, ssh_host.as_str(), ssh_port, username.as_str()))
+        .map(|certificate| expand_home(certificate.as_str()).to_string_lossy().into_owned());
+    if let Some(certificate) = certificate.as_ref().filter(|certificate| !Path::new(certificate.as_str()).is_file()) {
+        warnings.push(format!("CertificateFile {} not found", certificate));
+    }
+    let certificate = certificate.filter(|certificate| Path::new(certificate.as_str()).is_file());
+    Login { ssh_host, ssh_port, username, private_key, certificate, warnings }
+}
+
+// ProxyJump hops are `[user@]host[:port]` or ssh:// URIs, a hop that names a config host
+// takes HostName, Port, User, IdentityFile and CertificateFile from there
+fn jump_hosts(items: &[Item], options: &HostOptions, warnings: &mut Vec<String>) -> Vec<JumpHost> {
+    let spec = match options.get("proxyjump") {
+        Some(spec) if !spec.eq_ignore_ascii_case("none") => spec,
+        _ => return Vec::new(),
+    };
+    spec.split(',').map(|hop| hop.trim()).filter(|hop| !hop.is_empty()).map(|hop| {
+        let hop = hop.strip_prefix("ssh://").unwrap_or(hop);
+        let (user, address) = match hop.rfind('@') {
+            Some(at) => (Some(&hop[..at]), &hop[at + 1..]),
+            None => (None, hop),
+        };
+        let (host, port) = match split_host_port(address).filter(|_| address.contains(':')) {
+            Some((host, port)) => (host, Some(port)),
+            None => (String::from(address), None),
+        };
+        let login = login(&host_options(items, host.as_str()), host.as_str());
+        let hop_warnings: Vec<String> = login.warnings.iter()
+            .filter(|warning| !warnings.contains(warning))
+            .map(|warning| format!("{}: {}", host, warning))
+            .collect();
+        warnings.extend(hop_warnings);
+        JumpHost {
+            ssh_host: login.ssh_host,
+            ssh_port: port.unwrap_or(login.ssh_port),
+            username: user.map(String::from).unwrap_or(login.username),
+            password: None,
+            agent: login.private_key.is_none(),
+            private_key: login.private_key,
+            passphrase: None,
+            certificate: login.certificate,
+            agent_identity: None,
+            interactive: false,
+        }
+    }).collect()
+}
+
+fn candidates(items: &[Item], alias: &str, skipped: &mut Vec<String>) -> Vec<ImportCandidate> {
+    let options = host_options(items, alias);
+    let login = login(&options, alias);
+    let mut warnings = login.warnings.clone();
+    let jumps = jump_hosts(items, &options, &mut warnings);
+    // the tokens are left for the connect, ssh(1) does not take ProxyCommand and ProxyJump together either
+    let command = options.get("proxycommand").filter(|command| !command.eq_ignore_ascii_case("none"));
+    let proxy = match command {
+        Some(_) if !jumps.is_empty() => {
+            warnings.push(String::from("ProxyCommand is ignored next to ProxyJump"));
+            None
+        },
+        Some(command) => Some(TunnelProxy {
+            kind: ProxyKind::Command,
+            host: String::new(),
+            port: 0,
+            username: None,
+            password: None,
+            command: Some(String::from(command)),
+        }),
+        None => None,
+    };
+    // ServerAliveInterval 0 switches keepalives off for ssh(1) as well
+    let keepalive_interval = options.get("serveraliveinterval").and_then(|interval| interval.parse::<u32>().ok());
+    let keepalive_count_max = options.get("serveralivecountmax").and_then(|count| count.parse::<u32>().ok());
+
+    let mut candidates = Vec::new();
+    for forward in options.local_forwards.iter() {
+        let spec = forward.join(" ");
+        let parsed = match forward.as_slice() {
+            [listen, target] => listen_spec(listen).zip(split_host_port(target)),
+            _ => None,
+        };
+        let ((bind_address, local_port), (remote_host, remote_port)) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                log::warn!("SSH CONFIG {}: UNSUPPORTED LocalForward {}", alias, spec);
+                skipped.push(format!("{}: LocalForward {} is not supported, skipped", alias, spec));
+                continue;
+            },
+        };
+        let bind_wildcard = bind_address.as_deref() == Some("0.0.0.0");
+        let mut warnings = warnings.clone();
+        if bind_wildcard {
+            warnings.push(String::from("listens on every interface"));
+        }
+        candidates.push(ImportCandidate {
+            alias: String::from(alias),
+            forward: spec,
+            warnings,
+            tunnel: Tunnel {
+                local_port,
+                remote_port,
+                remote_host,
+                ssh_port: login.ssh_port,
+                ssh_host: login.ssh_host.clone(),
+                username: login.username.clone(),
+                private_key: login.private_key.clone(),
+                certificate: login.certificate.clone(),
+                status: TunnelState::STOP,
+                kind: TunnelKind::Local,
+                bind_address,
+                bind_wildcard,
+                keepalive_interval,
+                keepalive_count_max,
+                system_known_hosts: true,
+                agent: login.private_key.is_none(),
+                jump_hosts: jumps.clone(),
+                proxy: proxy.clone(),
+                ..Default::default()
+            },
+        });
+    }
+    candidates
+}
+
+// every LocalForward of the user's OpenSSH client config (or `path`) as a tunnel candidate,
+// nothing is registered here
+pub fn import(path: Option<&str>) -> TunnelResult<ImportPreview> {
+    let path = match path.filter(|path| !path.is_empty()) {
+        Some(path) => expand_home(path),
+        None => config_file()?,
+    };
+    let dir = home().map(|home| home.join(SSH_DIR)).unwrap_or_default();
+    import_file(&path, &dir)
+}
+
+// `dir` is where relative includes are looked up
+fn import_file(path: &Path, dir: &Path) -> TunnelResult<ImportPreview> {
+    let items = parse(path, dir, 0)?;
+
+    let mut found = Vec::new();
+    aliases(&items, &mut found);
+    let mut warnings = Vec::new();
+    let candidates = found.iter().flat_map(|alias| candidates(&items, alias, &mut warnings)).collect();
+    Ok(ImportPreview { candidates, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // a throwaway ~/.ssh holding the config files of one test
+    struct ConfigDir(PathBuf);
+
+    impl ConfigDir {
+        fn new(name: &str, files: &[(&str, &str)]) -> ConfigDir {
+            let dir = ConfigDir(env::temp_dir().join(format!("leviathan-ssh-config-{}-{}", process::id(), name)));
+            for (file, content) in files {
+                let path = dir.0.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, content).unwrap();
+            }
+            dir
+        }
+
+        fn path(&self, file: &str) -> String {
+            self.0.join(file).to_string_lossy().into_owned()
+        }
+
+        fn import(&self) -> ImportPreview {
+            import_file(&self.0.join("config"), &self.0).unwrap()
+        }
+
+        fn options(&self, alias: &str) -> HostOptions {
+            host_options(&parse(&self.0.join("config"), &self.0, 0).unwrap(), alias)
+        }
+    }
+
+    impl Drop for ConfigDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn candidate<'a>(candidates: &'a [ImportCandidate], alias: &str, forward: &str) -> &'a ImportCandidate {
+        candidates.iter().find(|candidate| candidate.alias == alias && candidate.forward == forward)
+            .unwrap_or_else(|| panic!("no {} {} in {:?}", alias, forward, candidates))
+    }
+
+    #[test]
+    fn tokenize_lines() {
+        let cases: &[(&str, &[&str])] = &[
+            ("Host foo bar", &["Host", "foo", "bar"]),
+            ("  HostName=example.com", &["HostName", "example.com"]),
+            ("HostName = example.com  ", &["HostName", "example.com"]),
+            ("\tPort\t2222", &["Port", "2222"]),
+            ("IdentityFile \"/keys/my key\"", &["IdentityFile", "/keys/my key"]),
+            ("LocalForward 8080 \"db host:5432\"", &["LocalForward", "8080", "db host:5432"]),
+            ("User \"\"", &["User", ""]),
+            ("User bob # the admin", &["User", "bob"]),
+            ("User bob#1", &["User", "bob#1"]),
+            ("IdentityFile \"/keys/#1\"", &["IdentityFile", "/keys/#1"]),
+            ("ProxyCommand ssh -W \"%h:%p\" jump # kept", &["ProxyCommand", "ssh -W \"%h:%p\" jump # kept"]),
+            ("proxycommand=nc %h %p", &["proxycommand", "nc %h %p"]),
+            ("", &[""]),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(tokenize(line), expected.to_vec(), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn host_patterns() {
+        let cases: &[(&[&str], &str, bool)] = &[
+            (&["*"], "anything", true),
+            (&["db"], "DB", true),
+            (&["db?"], "db1", true),
+            (&["db?"], "db12", false),
+            (&["*.example.com"], "web.example.com", true),
+            (&["*.example.com"], "example.com", false),
+            (&["web", "db"], "db", true),
+            (&["*", "!bastion"], "bastion", false),
+            (&["*", "!bastion"], "web", true),
+            (&["!bastion", "*"], "bastion", false),
+            (&["!bastion"], "web", false),
+            (&["10.0.*.?"], "10.0.12.3", true),
+        ];
+        for (patterns, text, expected) in cases {
+            assert_eq!(pattern_list(patterns.iter().copied(), text), *expected, "{:?} {}", patterns, text);
+        }
+    }
+
+    #[test]
+    fn first_value_wins() {
+        let dir = ConfigDir::new("first", &[("config", "\
+Host db
+    HostName db.internal
+    Port 2200
+    IdentityFile /keys/db
+
+Host d*
+    HostName ignored.internal
+    User fallback
+    IdentityFile /keys/any
+
+Host *
+    User ignored
+    Port 22
+")]);
+        let options = dir.options("db");
+        assert_eq!(options.get("hostname"), Some("db.internal"));
+        assert_eq!(options.get("port"), Some("2200"));
+        assert_eq!(options.get("user"), Some("fallback"));
+        assert_eq!(options.identity_files, vec!["/keys/db", "/keys/any"]);
+
+        let options = dir.options("web");
+        assert_eq!(options.get("hostname"), None);
+        assert_eq!(options.get("user"), Some("ignored"));
+        assert_eq!(options.get("port"), Some("22"));
+    }
+
+    #[test]
+    fn include_and_match() {
+        let dir = ConfigDir::new("include", &[
+            ("config", "\
+Include conf.d/*.conf
+Include missing.conf
+
+Host other
+    Include never.conf
+
+Host web
+    LocalForward 8080 127.0.0.1:80
+
+Match originalhost web user deploy
+    ServerAliveInterval 15
+
+Match host !web.internal
+    Port 1
+
+Match exec \"true\"
+    Port 9
+
+Match all
+    ServerAliveCountMax 5
+"),
+            ("never.conf", "Host web\n    Port 1\n    User nobody\n"),
+            ("conf.d/10-web.conf", "Host web\n    HostName web.internal\n    User deploy\n"),
+            ("conf.d/20-web.conf", "Host web\n    HostName ignored.internal\n    Port 2022\n"),
+            ("conf.d/web.txt", "Host web\n    Port 3\n"),
+        ]);
+        let options = dir.options("web");
+        assert_eq!(options.get("hostname"), Some("web.internal"));
+        assert_eq!(options.get("user"), Some("deploy"));
+        assert_eq!(options.get("port"), Some("2022"));
+        assert_eq!(options.get("serveraliveinterval"), Some("15"));
+        assert_eq!(options.get("serveralivecountmax"), Some("5"));
+        assert_eq!(options.warnings, vec!["Match exec is not supported, block skipped"]);
+
+        let candidates = dir.import().candidates;
+        assert_eq!(candidates.len(), 1);
+        let tunnel = &candidates[0].tunnel;
+        assert_eq!((tunnel.ssh_host.as_str(), tunnel.ssh_port, tunnel.username.as_str()), ("web.internal", 2022, "deploy"));
+        assert_eq!((tunnel.keepalive_interval, tunnel.keepalive_count_max), (Some(15), Some(5)));
+    }
+
+    #[test]
+    fn include_nested_too_deeply() {
+        let dir = ConfigDir::new("loop", &[("config", "Include config\n")]);
+        let e = import_file(&dir.0.join("config"), &dir.0).unwrap_err();
+        assert_eq!(e.message, "SSH CONFIG INCLUDE NESTED TOO DEEPLY!!!");
+    }
+
+    #[test]
+    fn expands_tokens() {
+        let cases = [
+            ("/keys/%r@%h:%p", "/keys/bob@db.internal:2200"),
+            ("/keys/%n", "/keys/db"),
+            ("100%%", "100%"),
+            ("%x %", "%x %"),
+            ("ssh -W %h:%p jump", "ssh -W db.internal:2200 jump"),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(expand_tokens(value, "db", "db.internal", 2200, "bob"), *expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn identity_and_certificate_files() {
+        let dir = ConfigDir::new("keys", &[("bob-db.internal", "key"), ("bob-db.internal-cert.pub", "cert")]);
+        let config = format!("\
+Host db
+    HostName db.internal
+    User bob
+    IdentityFile {keys}/missing
+    IdentityFile {keys}/%r-%h
+    CertificateFile {keys}/%r-%h-cert.pub
+    LocalForward 5432 localhost:5432
+
+Host nokey
+    IdentityFile {keys}/missing
+    CertificateFile {keys}/missing-cert.pub
+    LocalForward 5433 localhost:5432
+", keys = dir.0.to_string_lossy());
+        fs::write(dir.0.join("config"), config).unwrap();
+        let candidates = dir.import().candidates;
+
+        let db = candidate(&candidates, "db", "5432 localhost:5432");
+        assert_eq!(db.tunnel.private_key, Some(dir.path("bob-db.internal")));
+        assert_eq!(db.tunnel.certificate, Some(dir.path("bob-db.internal-cert.pub")));
+        assert!(!db.tunnel.agent);
+        assert!(db.warnings.is_empty(), "{:?}", db.warnings);
+
+        let nokey = candidate(&candidates, "nokey", "5433 localhost:5432");
+        assert_eq!((nokey.tunnel.private_key.as_ref(), nokey.tunnel.certificate.as_ref()), (None, None));
+        assert!(nokey.tunnel.agent);
+        assert_eq!(nokey.warnings, vec![
+            format!("IdentityFile {} not found, using the ssh agent", dir.path("missing")),
+            format!("CertificateFile {} not found", dir.path("missing-cert.pub")),
+        ]);
+    }
+
+    #[test]
+    fn local_forwards_become_tunnels() {
+        let dir = ConfigDir::new("forwards", &[("config", "\
+Host app
+    HostName app.internal
+    Port 2022
+    User deploy
+    LocalForward 8080 localhost:80
+    LocalForward *:8443 10.0.0.5:443
+    LocalForward [::1]:9000 [2001:db8::1]:22
+    LocalForward localhost:5000 db/5432
+    LocalForward 127.0.0.2:6379 cache:6379
+    LocalForward 7000
+    LocalForward /tmp/app.sock localhost:80
+
+Host jumped
+    ProxyJump bob@jump.internal:2222,hop2
+    ProxyCommand nc %h %p
+    LocalForward 3306 db:3306
+
+Host proxied
+    ProxyCommand ssh -W %h:%p bastion
+    LocalForward 3307 db:3306
+
+Host hop2
+    HostName hop2.internal
+    User hopper
+    Port 2200
+")]);
+        let preview = dir.import();
+        // the two app forwards left are neither a port nor an address
+        assert_eq!(preview.warnings, vec![
+            "app: LocalForward 7000 is not supported, skipped",
+            "app: LocalForward /tmp/app.sock localhost:80 is not supported, skipped",
+        ]);
+        let candidates = preview.candidates;
+        assert_eq!(candidates.len(), 7);
+
+        // forward, bind_address, local_port, remote_host, remote_port
+        let cases: &[(&str, Option<&str>, u16, &str, u16)] = &[
+            ("8080 localhost:80", None, 8080, "localhost", 80),
+            ("*:8443 10.0.0.5:443", Some("0.0.0.0"), 8443, "10.0.0.5", 443),
+            ("[::1]:9000 [2001:db8::1]:22", Some("::1"), 9000, "2001:db8::1", 22),
+            ("localhost:5000 db/5432", Some("127.0.0.1"), 5000, "db", 5432),
+            ("127.0.0.2:6379 cache:6379", Some("127.0.0.2"), 6379, "cache", 6379),
+        ];
+        for (forward, bind_address, local_port, remote_host, remote_port) in cases {
+            let app = candidate(&candidates, "app", forward);
+            let tunnel = &app.tunnel;
+            assert_eq!(tunnel.bind_address.as_deref(), *bind_address, "{}", forward);
+            assert_eq!((tunnel.local_port, tunnel.remote_host.as_str(), tunnel.remote_port), (*local_port, *remote_host, *remote_port), "{}", forward);
+            assert_eq!((tunnel.ssh_host.as_str(), tunnel.ssh_port, tunnel.username.as_str()), ("app.internal", 2022, "deploy"), "{}", forward);
+            assert_eq!((tunnel.kind, tunnel.status.clone(), tunnel.id.as_str()), (TunnelKind::Local, TunnelState::STOP, ""), "{}", forward);
+            assert!(tunnel.system_known_hosts && tunnel.agent, "{}", forward);
+            assert_eq!(tunnel.bind_wildcard, *bind_address == Some("0.0.0.0"), "{}", forward);
+        }
+        assert_eq!(candidate(&candidates, "app", "*:8443 10.0.0.5:443").warnings, vec!["listens on every interface"]);
+
+        let jumped = candidate(&candidates, "jumped", "3306 db:3306");
+        let hops: Vec<(&str, u16, &str)> = jumped.tunnel.jump_hosts.iter()
+            .map(|hop| (hop.ssh_host.as_str(), hop.ssh_port, hop.username.as_str()))
+            .collect();
+        assert_eq!(hops, vec![("jump.internal", 2222, "bob"), ("hop2.internal", 2200, "hopper")]);
+        assert!(jumped.tunnel.proxy.is_none());
+        assert_eq!(jumped.warnings, vec!["ProxyCommand is ignored next to ProxyJump"]);
+
+        let proxied = candidate(&candidates, "proxied", "3307 db:3306");
+        let proxy = proxied.tunnel.proxy.as_ref().unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Command);
+        assert_eq!(proxy.command.as_deref(), Some("ssh -W %h:%p bastion"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,203 @@
+use serde::{Serialize, Deserialize};
+use std::net::{Ipv4Addr, TcpListener as StdTcpListener};
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use super::server::{cancellable, report};
+use super::session::SharedSession;
+use super::{PluginError, TunnelEventSender, TunnelResult};
+use crate::plugins::error::{ConvertToPluginError, ErrorKind};
+
+// a destination a running local tunnel forwards to besides its own remote, on a loopback port of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelTarget {
+    pub host: String,
+    pub port: u16,
+    pub bound_port: u16,
+}
+
+struct Listening {
+    target: TunnelTarget,
+    accept: JoinHandle<()>,
+}
+
+// what the listeners need of the tunnel while it runs
+#[derive(Clone)]
+struct Running {
+    shared: SharedSession,
+    events: TunnelEventSender,
+    cancelled: watch::Receiver<bool>,
+}
+
+#[derive(Default)]
+struct Targets {
+    running: Option<Running>,
+    listening: Vec<Listening>,
+}
+
+// the other hosts behind the ssh server a tunnel was asked to reach, each one over a direct-tcpip channel
+// of the tunnel's session. they come and go with the tunnel and keep their ports across its reconnects
+#[derive(Clone, Default)]
+pub struct TunnelTargets {
+    targets: Arc<StdMutex<Targets>>,
+}
+
+impl TunnelTargets {
+    pub fn attach(&self, shared: SharedSession, events: TunnelEventSender, cancelled: watch::Receiver<bool>) {
+        if let Ok(mut targets) = self.targets.lock() {
+            targets.running = Some(Running { shared, events, cancelled });
+        }
+    }
+
+    // no new clients, the ones already forwarded go down with the session
+    pub fn detach(&self) {
+        if let Ok(mut targets) = self.targets.lock() {
+            targets.running = None;
+            for listening in targets.listening.drain(..) {
+                listening.accept.abort();
+            }
+        }
+    }
+
+    pub fn list(&self) -> Vec<TunnelTarget> {
+        match self.targets.lock() {
+            Ok(targets) => targets.listening.iter().map(|listening| listening.target.clone()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // the loopback port forwarding to `host:port`, the same one again for a destination asked for before.
+    // whether the ssh server can reach it shows with the first client
+    pub fn open(&self, host: &str, port: u16) -> TunnelResult<TunnelTarget> {
+        let mut targets = self.targets.lock()
+            .map_err(|_| PluginError::build(ErrorKind::Internal, "TUNNEL TARGETS UNAVAILABLE!!!"))?;
+        if let Some(listening) = targets.listening.iter().find(|listening| listening.target.host == host && listening.target.port == port) {
+            return Ok(listening.target.clone());
+        }
+        let running = match targets.running.clone() {
+            Some(running) => running,
+            None => return Err(PluginError::build(ErrorKind::Tunnel, "TUNNEL IS NOT RUNNING!!!")),
+        };
+        let listener = StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            })
+            .map_err(|e| e.convert().with_context("stage", "bind"))?;
+        let bound_port = listener.local_addr().map_err(|e| e.convert())?.port();
+        let target = TunnelTarget { host: String::from(host), port, bound_port };
+        log::info!("TUNNEL TARGET {}:{} AT 127.0.0.1:{}", host, port, bound_port);
+
+        let accept = tokio::spawn(serve(listener, target.clone(), running));
+        targets.listening.push(Listening { target: target.clone(), accept });
+        Ok(target)
+    }
+
+    // stops listening for `host:port`, false when there was no such target
+    pub fn close(&self, host: &str, port: u16) -> bool {
+        let mut targets = match self.targets.lock() {
+            Ok(targets) => targets,
+            Err(_) => return false,
+        };
+        match targets.listening.iter().position(|listening| listening.target.host == host && listening.target.port == port) {
+            Some(index) => {
+                let listening = targets.listening.remove(index);
+                listening.accept.abort();
+                log::info!("TUNNEL TARGET {}:{} CLOSED AT 127.0.0.1:{}", host, port, listening.target.bound_port);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, target: TunnelTarget, running: Running) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(client) => client,
+            Err(e) => {
+                report(&running.events, e.convert()
+                    .with_context("stage", "accept")
+                    .with_context("destination", format!("{}:{}", target.host, target.port).as_str()));
+                return;
+            },
+        };
+        let peer = format!("{} -> {}:{}", peer, target.host, target.port);
+        tokio::spawn(cancellable(start_target(stream, peer, target.clone(), running.shared.clone(), running.events.clone()), running.cancelled.clone()));
+    }
+}
+
+async fn start_target(mut stream: TcpStream, peer: String, target: TunnelTarget, shared: SharedSession, events: TunnelEventSender) {
+    let rs = match shared.open_to(target.host.as_str(), target.port).await {
+        Ok((forwarder, c, permit)) => forwarder.attach(c, stream, Some(peer), Some(permit)).await,
+        Err(e) => {
+            if let Err(e) = stream.shutdown().await {
+                log::warn!("CLIENT SHUTDOWN: {:?}", e);
+            }
+            Err(e)
+        },
+    };
+    if let Err(e) = rs {
+        report(&events, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::ssh_tunnel::interactive::Prompter;
+    use crate::plugins::ssh_tunnel::testing::tunnel;
+    use serde_json::json;
+    use tokio::sync::mpsc::channel;
+
+    // a tunnel taken to run, its session is only logged into once a client comes along
+    fn attached() -> TunnelTargets {
+        let (sx, _) = channel(16);
+        let events = TunnelEventSender::new(String::from("t"), sx);
+        let shared = SharedSession::new(tunnel(json!({ "id": "t" })), None, events.clone(), Prompter::new());
+        let (_, cancelled) = watch::channel(false);
+        let targets = TunnelTargets::default();
+        targets.attach(shared, events, cancelled);
+        targets
+    }
+
+    fn destinations(targets: &TunnelTargets) -> Vec<String> {
+        targets.list().iter().map(|target| format!("{}:{}", target.host, target.port)).collect()
+    }
+
+    async fn listens(port: u16) -> bool {
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn targets_open_and_close() {
+        let targets = TunnelTargets::default();
+        assert_eq!(targets.open("10.0.0.2", 9042).map_err(|e| e.message).err(), Some(String::from("TUNNEL IS NOT RUNNING!!!")));
+
+        let targets = attached();
+        let first = targets.open("10.0.0.2", 9042).expect("open");
+        let second = targets.open("10.0.0.3", 9042).expect("open");
+        assert_eq!(targets.open("10.0.0.2", 9042).expect("open").bound_port, first.bound_port, "the same port again");
+        assert_ne!(first.bound_port, second.bound_port);
+        assert!(listens(first.bound_port).await);
+
+        assert!(targets.close("10.0.0.2", 9042));
+        assert_eq!(destinations(&targets), vec!["10.0.0.3:9042"]);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!listens(first.bound_port).await, "the port is given up");
+        assert!(listens(second.bound_port).await);
+        assert!(!targets.close("10.0.0.2", 9042), "closed already");
+        assert!(!targets.close("10.0.0.3", 9043));
+
+        let reopened = targets.open("10.0.0.2", 9042).expect("open");
+        assert_eq!(destinations(&targets), vec!["10.0.0.3:9042", "10.0.0.2:9042"]);
+
+        targets.detach();
+        assert!(destinations(&targets).is_empty());
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!listens(second.bound_port).await && !listens(reopened.bound_port).await);
+    }
+}
\ No newline at end of file
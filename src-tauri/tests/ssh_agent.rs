@@ -0,0 +1,109 @@
+// a throwaway ssh-agent holding two keys, the way the ui lists them and the way a tunnel offers them
+#![cfg(unix)]
+
+use leviathan::plugins::ssh_tunnel::agent::{self, AgentIdentity};
+use leviathan::plugins::ssh_tunnel::Tunnel;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+struct Lab {
+    dir: PathBuf,
+    agent: Child,
+}
+
+impl Drop for Lab {
+    fn drop(&mut self) {
+        let _ = self.agent.kill();
+        let _ = self.agent.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn run(command: &mut Command) {
+    let status = command.stdout(Stdio::null()).stderr(Stdio::null()).status().expect("spawn");
+    assert!(status.success(), "{:?}", command);
+}
+
+fn keygen(dir: &Path, name: &str) -> PathBuf {
+    let key = dir.join(name);
+    run(Command::new("ssh-keygen").args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"]).arg(&key));
+    key
+}
+
+// `None` where there is no openssh to run it with
+fn lab() -> Option<Lab> {
+    let dir = env::temp_dir().join(format!("leviathan-agent-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("lab dir");
+    let sock = dir.join("agent.sock");
+    let agent = match Command::new("ssh-agent").arg("-D").arg("-a").arg(&sock).stdout(Stdio::null()).spawn() {
+        Ok(agent) => agent,
+        Err(e) => {
+            eprintln!("no ssh-agent: {}", e);
+            let _ = fs::remove_dir_all(&dir);
+            return None;
+        },
+    };
+    let lab = Lab { dir, agent };
+    for _ in 0..50 {
+        if sock.exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    for name in ["first", "second"].iter() {
+        let key = keygen(&lab.dir, name);
+        run(Command::new("ssh-add").arg(&key).env("SSH_AUTH_SOCK", &sock));
+    }
+    env::set_var("SSH_AUTH_SOCK", &sock);
+    Some(lab)
+}
+
+fn tunnel(agent_identity: Option<&str>) -> Tunnel {
+    serde_json::from_value(serde_json::json!({
+        "local_port": 0,
+        "remote_port": 22,
+        "remote_host": "127.0.0.1",
+        "ssh_port": 22,
+        "ssh_host": "127.0.0.1",
+        "username": "u",
+        "password": null,
+        "private_key": null,
+        "status": "stop",
+        "agent": true,
+        "agent_identity": agent_identity,
+    })).expect("tunnel")
+}
+
+fn comments(identities: &[AgentIdentity]) -> Vec<&str> {
+    identities.iter().map(|identity| identity.comment.as_str()).collect()
+}
+
+#[test]
+fn agent_identities_and_fallback_order() {
+    let _lab = match lab() {
+        Some(lab) => lab,
+        None => return,
+    };
+
+    let identities = agent::identities().expect("identities");
+    assert_eq!(comments(&identities), vec!["first", "second"]);
+    assert!(identities.iter().all(|identity| identity.key_type == "ssh-ed25519"));
+
+    // nothing picked, the agent's own order
+    assert_eq!(comments(&agent::candidates(&tunnel(None)).unwrap()), vec!["first", "second"]);
+    assert_eq!(comments(&agent::candidates(&tunnel(Some(""))).unwrap()), vec!["first", "second"]);
+
+    // the picked one first, the others after it as fallbacks
+    let second = identities[1].id.as_str();
+    assert_eq!(comments(&agent::candidates(&tunnel(Some(second))).unwrap()), vec!["second", "first"]);
+    let first = identities[0].id.as_str();
+    assert_eq!(comments(&agent::candidates(&tunnel(Some(first))).unwrap()), vec!["first", "second"]);
+
+    let missing = agent::candidates(&tunnel(Some("00"))).unwrap_err();
+    assert_eq!(missing.message, "AGENT IDENTITY NOT FOUND!!!");
+}
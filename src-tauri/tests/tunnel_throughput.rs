@@ -0,0 +1,127 @@
+// throughput of a local forward against the same echo service reached directly.
+//
+// needs an sshd that can reach 127.0.0.1 and whose host key is in known_hosts, so it does not run by default:
+//   LEVIATHAN_SSH_PORT=22 LEVIATHAN_SSH_USER=me LEVIATHAN_SSH_PASSWORD=secret \
+//   cargo test --release --test tunnel_throughput -- --ignored --nocapture
+// LEVIATHAN_SSH_KEY authenticates with a key file instead, THROUGHPUT_MIB and THROUGHPUT_CLIENTS size the run.
+// both numbers come from the same clients over the same echo service, the tunnel's against the direct one.
+use leviathan::plugins::ssh_tunnel::api::run;
+use leviathan::plugins::ssh_tunnel::interactive::Prompter;
+use leviathan::plugins::ssh_tunnel::{request, Tunnel, TunnelControl, TunnelNotice, TunnelOperator, TunnelState};
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::thread;
+use std::time::Instant;
+use tokio::runtime::Builder;
+use tokio::sync::mpsc::channel;
+
+const CHUNK: usize = 64 * 1024;
+
+fn var(name: &str, default: &str) -> String {
+    env::var(name).unwrap_or_else(|_| String::from(default))
+}
+
+fn number(name: &str, default: usize) -> usize {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+// an echo service on a loopback port of its own, one thread per connection
+fn echo() -> u16 {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).expect("echo bind");
+    let port = listener.local_addr().expect("echo addr").port();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut reader = stream.try_clone()?;
+                let mut writer = stream;
+                io::copy(&mut reader, &mut writer)?;
+                writer.shutdown(Shutdown::Write)
+            });
+        }
+    });
+    port
+}
+
+fn client(port: u16, total: usize) -> io::Result<usize> {
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let mut writer = stream.try_clone()?;
+    let sender = thread::spawn(move || -> io::Result<()> {
+        let chunk = vec![0x5a_u8; CHUNK];
+        let mut sent = 0;
+        while sent < total {
+            let size = CHUNK.min(total - sent);
+            writer.write_all(&chunk[..size])?;
+            sent += size;
+        }
+        writer.shutdown(Shutdown::Write)
+    });
+
+    let mut reader = stream;
+    let mut buf = vec![0_u8; CHUNK];
+    let mut received = 0;
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            size => received += size,
+        }
+    }
+    sender.join().expect("sender thread")?;
+    Ok(received)
+}
+
+// MiB/s both directions together, every client has to get back all it sent
+fn measure(name: &str, port: u16, mib: usize, clients: usize) -> f64 {
+    let total = mib * 1024 * 1024;
+    let started = Instant::now();
+    let handles: Vec<_> = (0..clients).map(|_| thread::spawn(move || client(port, total))).collect();
+    let received: usize = handles.into_iter()
+        .map(|handle| handle.join().expect("client thread").expect("client"))
+        .sum();
+    let elapsed = started.elapsed().as_secs_f64();
+    assert_eq!(received, total * clients, "{}: short echo", name);
+
+    let rate = (received * 2) as f64 / (1024.0 * 1024.0) / elapsed;
+    println!("{:>6}: {} clients, {} MiB each in {:.2}s, {:.1} MiB/s (both directions)", name, clients, mib, elapsed, rate);
+    rate
+}
+
+fn tunnel(remote_port: u16) -> Tunnel {
+    serde_json::from_value(serde_json::json!({
+        "local_port": 0,
+        "remote_port": remote_port,
+        "remote_host": "127.0.0.1",
+        "ssh_port": number("LEVIATHAN_SSH_PORT", 22),
+        "ssh_host": var("LEVIATHAN_SSH_HOST", "127.0.0.1"),
+        "username": var("LEVIATHAN_SSH_USER", "root"),
+        "password": env::var("LEVIATHAN_SSH_PASSWORD").ok(),
+        "private_key": env::var("LEVIATHAN_SSH_KEY").ok(),
+        "status": "stop",
+    })).expect("tunnel")
+}
+
+#[test]
+#[ignore]
+fn tunnel_throughput() {
+    let mib = number("THROUGHPUT_MIB", 64);
+    let clients = number("THROUGHPUT_CLIENTS", 4);
+    let port = echo();
+
+    let runtime = Builder::new_multi_thread().enable_all().build().expect("runtime");
+    let bound_port = runtime.block_on(async {
+        let (sx, rx) = channel::<TunnelControl>(16);
+        let (sx_notice, mut rx_notice) = channel::<TunnelNotice>(1024);
+        tokio::spawn(async move { while rx_notice.recv().await.is_some() {} });
+        tokio::spawn(run(rx, Prompter::new(), sx_notice));
+
+        let added = request(&sx, Some(tunnel(port)), TunnelOperator::ADD).await.expect("manager").expect("add");
+        let tunnel = Tunnel { status: TunnelState::RUNNING, ..added[0].clone() };
+        let started = request(&sx, Some(tunnel), TunnelOperator::STATE).await.expect("manager")
+            .unwrap_or_else(|e| panic!("start: {} {:?}", e, e.context));
+        started[0].bound_port.expect("bound port")
+    });
+
+    let direct = measure("direct", port, mib, clients);
+    let tunneled = measure("tunnel", bound_port, mib, clients);
+    println!("tunnel at {:.0}% of direct", tunneled / direct * 100.0);
+}
@@ -2,8 +2,19 @@ export interface Request<T = string> {
     data: T,
 }
 
+export type EventErrorKind = "connection" | "auth" | "timeout" | "schema" | "query-syntax"
+    | "unavailable" | "tunnel" | "validation" | "internal"
+
+export interface EventError extends Error {
+    code: number,
+    kind: EventErrorKind,
+    retryable: boolean,
+    context: [string, string][],
+    causes: string[],
+}
+
 export interface Response<T = string> {
     status: string,
-    err?: Error,
+    err?: EventError,
     data?: T,
 }
\ No newline at end of file
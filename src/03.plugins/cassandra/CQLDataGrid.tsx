@@ -25,7 +25,7 @@ const PaperBox = styled(Paper)(({theme}) => ({
 
 export const CQLDataGrid = memo((props: CQLDataGridProps): JSX.Element => {
 
-    const { port, host, username, password, keyspace, table } = props
+    const { port, host, username, password, tunnel, keyspace, table } = props
 
     const [ filterItems, setFilterItems ] = useState<FilterItemProps[]>([])
     const [ columns, setColumns ] = useState<GridColumn[]>([])
@@ -57,6 +57,7 @@ export const CQLDataGrid = memo((props: CQLDataGridProps): JSX.Element => {
             host: cqlRef.current.host,
             username: cqlRef.current.username,
             password: cqlRef.current.password,
+            tunnel: cqlRef.current.tunnel,
             keyspace: cqlRef.current.keyspace,
             table: cqlRef.current.table,
             fields: cqlRef.current.fields,
@@ -86,9 +87,9 @@ export const CQLDataGrid = memo((props: CQLDataGridProps): JSX.Element => {
         setColumns([])
         setData({rows: [], loading: true})
 
-        const req = { port, host, username, password, keyspace, table } as CQLRequest
+        const req = { port, host, username, password, tunnel, keyspace, table } as CQLRequest
         reqTableInfo(req).then((tableInfo: CQLTableInfo) => {
-            cqlRef.current = {...cqlRef.current, port, host, username, password, keyspace, table, ...tableInfo} as CQLDataGridCache
+            cqlRef.current = {...cqlRef.current, port, host, username, password, tunnel, keyspace, table, ...tableInfo} as CQLDataGridCache
 
             let columns = cqlRef.current.fields.map(elem =>({
                 field: elem.name,
@@ -130,7 +131,7 @@ export const CQLDataGrid = memo((props: CQLDataGridProps): JSX.Element => {
             setData(s => ({...s, loading: false}))
             enqueueSnackbar(e.message, {variant: "error"})
         })
-    }, [port, host, username, password, keyspace, table, setColumns, setFilterItems, setData, enqueueSnackbar])
+    }, [port, host, username, password, tunnel, keyspace, table, setColumns, setFilterItems, setData, enqueueSnackbar])
 
     return (
         <PaperBox>
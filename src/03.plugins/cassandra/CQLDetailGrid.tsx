@@ -103,6 +103,7 @@ const GridToolbarLoadButton = (): JSX.Element => {
             host: cache.host,
             username: cache.username,
             password: cache.password,
+            tunnel: cache.tunnel,
             keyspace: cache.keyspace,
             table: cache.table,
             udt: detailCache.udt,
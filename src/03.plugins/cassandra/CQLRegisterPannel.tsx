@@ -1,10 +1,13 @@
-import { Modal, Button, Paper, Grid, TextField, AppBar, Typography, Box } from '@mui/material'
+import { useEffect, useState } from 'react'
+import { Modal, Button, Paper, Grid, TextField, AppBar, Typography, Box, MenuItem } from '@mui/material'
 import { styled } from '@mui/system'
 import { useSnackbar } from 'notistack'
 import { useForm } from 'react-hook-form'
 import { useTreeCache, rootNodeSelector } from '../../01.tree/hooks'
 import { addTreeNode } from '../../01.tree/api'
 import { CQLDataGridProps } from './types'
+import { TunnelInfo } from '../ssh_tunnel/types'
+import { reqViewTunnelInfo } from '../ssh_tunnel/event'
 
 interface CQLRegisterPannelProps {
     open: boolean,
@@ -31,16 +34,31 @@ export function CQLRegisterPannel(props: CQLRegisterPannelProps): JSX.Element {
 
     const { register, handleSubmit } = useForm<CQLDataGridProps>()
     const { enqueueSnackbar } = useSnackbar()
+    const [ tunnels, setTunnels ] = useState<TunnelInfo[]>([])
+    const [ tunnel, setTunnel ] = useState("none")
 
-    const onSubmit = handleSubmit(data => {
+    useEffect(() => {
+        open && reqViewTunnelInfo().then(tunnels => setTunnels(tunnels.filter(elem => (elem.kind || "local") === "local"))).catch(() => setTunnels([]))
+    }, [open])
+
+    const onSubmit = handleSubmit(form => {
         onClose()
+        // through a tunnel host and port name the cluster as the bastion sees it, the tunnel's target when left empty
+        const linked = tunnels.find(elem => elem.id === tunnel)
+        const data = linked ? {
+            ...form,
+            host: form.host || linked.remote_host || "",
+            port: form.port || linked.remote_port || 0,
+            tunnel: linked.id,
+        } : form
+        const name = linked ? `${data.host}:${data.port} via ${linked.ssh_host}` : `${data.host}:${data.port}`
         const rs = addTreeNode("cql", rootNode, node => {
-            const nodeID = `${node.nodeID}@@${data.host}:${data.port}`
-            const pos = rootNode.children.findIndex(elem => elem.nodeID === `${node.nodeID}@@${data.host}:${data.port}`)
+            const nodeID = `${node.nodeID}@@${name}`
+            const pos = rootNode.children.findIndex(elem => elem.nodeID === nodeID)
             if(pos !== -1) return
             return {
-                id: `${data.host}:${data.port}`,
-                name: `${data.host}:${data.port}`,
+                id: name,
+                name,
                 nodeID,
                 level: node.level + 1,
                 children: [],
@@ -69,6 +87,14 @@ export function CQLRegisterPannel(props: CQLRegisterPannelProps): JSX.Element {
                                     <TextField fullWidth variant="standard" size="small" InputLabelProps={{shrink: true}} label="HOST" {...register("host")}></TextField>
                                 </Grid>
                             </Grid>
+                            <Grid item container justifyContent="space-around" alignItems="center">
+                                <Grid item xs={11}>
+                                    <TextField select fullWidth variant="standard" size="small" InputLabelProps={{shrink: true}} label="SSH TUNNEL" value={tunnel} onChange={e => setTunnel(e.target.value)}>
+                                        <MenuItem value="none">direct</MenuItem>
+                                        {tunnels.map(elem => <MenuItem key={elem.id} value={elem.id}>{`${elem.remote_host}:${elem.remote_port} via ${elem.ssh_host} (${elem.status})`}</MenuItem>)}
+                                    </TextField>
+                                </Grid>
+                            </Grid>
                             <Grid item container justifyContent="space-around" alignItems="center">
                                 <Grid item xs={5}>
                                     <TextField fullWidth variant="standard" size="small" InputLabelProps={{shrink: true}} label="USERNAME" {...register("username")}></TextField>
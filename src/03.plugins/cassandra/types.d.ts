@@ -31,6 +31,8 @@ export interface CQLRequest {
     username: string,
     password?: string,
     ca?: string,
+    // id of the ssh tunnel the cluster is reached through, started when it is down
+    tunnel?: string,
     keyspace?: string,
     table?: string,
     fields?: CQLField[],
@@ -45,6 +47,8 @@ export interface CQLDataGridCache {
     username: string,
     password?: string,
     ca?: string,
+    // id of the ssh tunnel the cluster is reached through, started when it is down
+    tunnel?: string,
     keyspace: string,
     table: string,
     fields: CQLField[],
@@ -64,6 +68,8 @@ export interface CQLDataGridProps {
     username: string,
     password?: string,
     ca?: string,
+    // id of the ssh tunnel the cluster is reached through, started when it is down
+    tunnel?: string,
     keyspace: string,
     table: string,
 }
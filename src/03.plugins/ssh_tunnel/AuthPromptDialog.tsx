@@ -0,0 +1,49 @@
+import { useEffect, useState } from 'react'
+import { Button, Dialog, DialogActions, DialogContent, DialogContentText, DialogTitle, TextField } from '@mui/material'
+import { useSnackbar } from 'notistack'
+import { listenAuthPrompt, reqAnswerAuthInfo } from './event'
+import { AuthPrompt } from './types'
+import { useTunnelCache } from './hooks'
+
+export function AuthPromptDialog(): JSX.Element {
+
+    const [prompt, setPrompt] = useState<AuthPrompt>()
+    const [answers, setAnswers] = useState<string[]>([])
+    const { enqueueSnackbar } = useSnackbar()
+    const { tunnels } = useTunnelCache()
+    const tunnel = tunnels.find(tunnel => tunnel.id === prompt?.tunnel)
+
+    useEffect(() => {
+        const unlisten = listenAuthPrompt(p => {
+            setAnswers(p.prompts.map(() => ""))
+            setPrompt(p)
+        })
+        return () => { unlisten.then(f => f()) }
+    }, [])
+
+    const reply = (cancel: boolean) => {
+        if (prompt) {
+            reqAnswerAuthInfo({id: prompt.id, answers, cancel})
+                .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
+        }
+        setPrompt(undefined)
+    }
+
+    return (
+        <Dialog open={prompt !== undefined} onClose={() => reply(true)}>
+            <DialogTitle color="primary">{prompt?.name || "SSH LOGIN"}</DialogTitle>
+            <DialogContent>
+                <DialogContentText>{`${prompt?.ssh_host}${tunnel ? ` (local ${tunnel.local_port})` : ""} ${prompt?.instructions || ""}`}</DialogContentText>
+                {prompt?.prompts.map((field, index) => (
+                    <TextField key={index} label={field.text} type={field.echo ? "text" : "password"} variant="standard" size="small" fullWidth autoFocus={index === 0}
+                        value={answers[index] || ""} onChange={e => setAnswers(a => a.map((v, i) => i === index ? e.target.value : v))}
+                        onKeyDown={e => e.key === "Enter" && index === answers.length - 1 && reply(false)}></TextField>
+                ))}
+            </DialogContent>
+            <DialogActions>
+                <Button onClick={() => reply(true)}>Cancel</Button>
+                <Button onClick={() => reply(false)}>Submit</Button>
+            </DialogActions>
+        </Dialog>
+    )
+}
\ No newline at end of file
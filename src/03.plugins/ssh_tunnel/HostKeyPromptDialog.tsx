@@ -0,0 +1,44 @@
+import { useEffect, useState } from 'react'
+import { Button, Dialog, DialogActions, DialogContent, DialogContentText, DialogTitle, Typography } from '@mui/material'
+import { listenHostKeyPrompt, reqTrustHostKeyInfo } from './event'
+import { HostKeyPrompt } from './types'
+
+export function HostKeyPromptDialog(): JSX.Element {
+
+    const [prompt, setPrompt] = useState<HostKeyPrompt>()
+
+    useEffect(() => {
+        const unlisten = listenHostKeyPrompt(setPrompt)
+        return () => { unlisten.then(f => f()) }
+    }, [])
+
+    const close = () => setPrompt(undefined)
+    const trust = () => {
+        if (prompt) {
+            reqTrustHostKeyInfo(prompt).then(close).catch(e => console.log(e))
+        }
+    }
+
+    return (
+        <Dialog open={prompt !== undefined} onClose={close}>
+            <DialogTitle color={prompt?.state === "mismatch" ? "error" : "primary"}>
+                {prompt?.state === "mismatch" ? "HOST KEY CHANGED" : "UNKNOWN HOST KEY"}
+            </DialogTitle>
+            <DialogContent>
+                <DialogContentText>
+                    {prompt?.state === "mismatch" ?
+                        `The key of ${prompt?.host}:${prompt?.port} does not match the pinned one, someone may be intercepting the connection.` :
+                        `${prompt?.host}:${prompt?.port} has not been seen before, check the fingerprint before trusting it.`}
+                </DialogContentText>
+                <Typography variant="overline" display="block">{prompt?.key_type}</Typography>
+                <Typography variant="body2" sx={{wordBreak: "break-all", fontFamily: "monospace"}}>{prompt?.fingerprint}</Typography>
+            </DialogContent>
+            <DialogActions>
+                <Button onClick={close}>Cancel</Button>
+                <Button color={prompt?.state === "mismatch" ? "error" : "primary"} onClick={trust}>
+                    {prompt?.state === "mismatch" ? "Re-pin" : "Trust"}
+                </Button>
+            </DialogActions>
+        </Dialog>
+    )
+}
\ No newline at end of file
@@ -3,6 +3,8 @@ import { VisibilitySharp } from '@mui/icons-material'
 import { styled } from '@mui/system'
 import { useNaviCache } from '../../02.navi/hooks'
 import { TunnelGrid } from './TunnelGrid'
+import { HostKeyPromptDialog } from './HostKeyPromptDialog'
+import { AuthPromptDialog } from './AuthPromptDialog'
 
 const TunnelBox = styled(Box)(({theme}) =>({
     padding: theme.spacing(1),
@@ -14,7 +16,10 @@ export function SSHTunnelBoard(): JSX.Element {
     
     return (
         <TunnelBox>
-            <TunnelGrid height={750}></TunnelGrid>
+            <TunnelGrid height={750}>
+                <AuthPromptDialog></AuthPromptDialog>
+            </TunnelGrid>
+            <HostKeyPromptDialog></HostKeyPromptDialog>
            <SpeedDial      
                 ariaLabel="SpeedDial basic example" icon={<SpeedDialIcon />} 
                 sx={{position: "absolute", bottom: 16, right: 16}}>
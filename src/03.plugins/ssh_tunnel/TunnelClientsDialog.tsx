@@ -0,0 +1,89 @@
+import { useCallback, useEffect, useState } from 'react'
+import { Button, Dialog, DialogActions, DialogContent, DialogTitle, IconButton, Table, TableBody, TableCell, TableHead, TableRow, Typography } from '@mui/material'
+import { LinkOffSharp } from '@mui/icons-material'
+import { useSnackbar } from 'notistack'
+import { listenTunnelNotice, reqClientsTunnelInfo, reqKillTunnelClient } from './event'
+import { TunnelClient, TunnelInfo } from './types'
+
+const CLIENTS_REFRESH_INTERVAL = 2000 //ms
+
+export interface TunnelClientsDialogProps {
+    tunnel?: TunnelInfo,
+    onClose: () => void,
+}
+
+// the live client connections of a running tunnel, each can be dropped on its own
+export function TunnelClientsDialog({tunnel, onClose}: TunnelClientsDialogProps): JSX.Element {
+
+    const [ clients, setClients ] = useState<TunnelClient[]>([])
+    const { enqueueSnackbar } = useSnackbar()
+
+    const refresh = useCallback(() => {
+        if (tunnel) {
+            reqClientsTunnelInfo(tunnel).then(setClients).catch(() => setClients([]))
+        }
+    }, [tunnel])
+
+    useEffect(() => {
+        if (!tunnel) {
+            setClients([])
+            return
+        }
+        refresh()
+        const tid = setInterval(refresh, CLIENTS_REFRESH_INTERVAL)
+        const unlisten = listenTunnelNotice(notice => {
+            if (notice.id === tunnel.id && (notice.kind === "connected" || notice.kind === "closed")) {
+                refresh()
+            }
+        })
+        return () => {
+            clearInterval(tid)
+            unlisten.then(f => f())
+        }
+    }, [tunnel, refresh])
+
+    const kill = (id?: number) => {
+        if (tunnel) {
+            reqKillTunnelClient(tunnel, id)
+                .then(setClients)
+                .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
+        }
+    }
+
+    return (
+        <Dialog open={tunnel !== undefined} onClose={onClose} maxWidth="md" fullWidth>
+            <DialogTitle color="primary">{`CLIENTS OF ${tunnel?.bound_port || tunnel?.local_port}`}</DialogTitle>
+            <DialogContent>
+                <Table size="small">
+                    <TableHead>
+                        <TableRow>
+                            <TableCell>Peer</TableCell>
+                            <TableCell>Started</TableCell>
+                            <TableCell align="right">In</TableCell>
+                            <TableCell align="right">Out</TableCell>
+                            <TableCell align="right">Idle</TableCell>
+                            <TableCell></TableCell>
+                        </TableRow>
+                    </TableHead>
+                    <TableBody>
+                        {clients.length === 0 && <TableRow><TableCell colSpan={6}><Typography variant="caption">no client connected</Typography></TableCell></TableRow>}
+                        {clients.map(client => (
+                            <TableRow key={client.id}>
+                                <TableCell>{client.peer}</TableCell>
+                                <TableCell>{new Date(client.started).toLocaleTimeString()}</TableCell>
+                                <TableCell align="right">{client.bytes_in}</TableCell>
+                                <TableCell align="right">{client.bytes_out}</TableCell>
+                                <TableCell align="right">{`${Math.floor(client.idle / 1000)}s`}</TableCell>
+                                <TableCell><IconButton color="error" size="small" onClick={() => kill(client.id)}><LinkOffSharp /></IconButton></TableCell>
+                            </TableRow>
+                        ))}
+                    </TableBody>
+                </Table>
+            </DialogContent>
+            <DialogActions>
+                <Button color="error" disabled={clients.length === 0} onClick={() => kill()}>Kill All</Button>
+                <Button onClick={onClose}>Close</Button>
+            </DialogActions>
+        </Dialog>
+    )
+}
\ No newline at end of file
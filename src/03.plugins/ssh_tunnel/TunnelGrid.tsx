@@ -1,13 +1,15 @@
 import { useState, useMemo, useEffect } from 'react'
 import { IconButton, Grid, Chip } from '@mui/material'
-import { EditSharp, SaveSharp, CancelSharp, DeleteSharp } from '@mui/icons-material'
+import { EditSharp, SaveSharp, CancelSharp, DeleteSharp, PeopleSharp } from '@mui/icons-material'
 import { DataGrid, GridRenderCellParams, GridRowId } from '@mui/x-data-grid'
 import { useSnackbar } from 'notistack'
 import { styled } from '@mui/system'
-import { TunnelInfo } from './types'
+import { CertificateInfo, TunnelInfo, TunnelTarget } from './types'
 import { TunnelGridToolbar } from './TunnelGridToolbar'
 import { TunnelContext, useTunnelCache } from './hooks'
-import { reqViewTunnelInfo, reqSyncTunnelInfo, reqDeleteTunnelInfo, reqStateTunnelInfo } from './event'
+import { TunnelSparkline } from './TunnelSparkline'
+import { TunnelClientsDialog } from './TunnelClientsDialog'
+import { reqViewTunnelInfo, reqSyncTunnelInfo, reqDeleteTunnelInfo, reqStateTunnelInfo, listenTunnelNotice, listenTunnelMetrics } from './event'
 
 interface TunnelGridDivProps {
     height?: number | string,
@@ -17,14 +19,41 @@ const TunnelGridDiv = styled('div')<TunnelGridDivProps>(props=> ({
     height: props.height,
 }))
 
+const formatBytes = (bytes: number) => {
+    const units = ["B", "KB", "MB", "GB", "TB"]
+    const exp = Math.min(Math.floor(Math.log(Math.max(bytes, 1)) / Math.log(1024)), units.length - 1)
+    return `${(bytes / Math.pow(1024, exp)).toFixed(exp === 0 ? 0 : 1)}${units[exp]}`
+}
+
+const formatRemaining = (millis: number) => {
+    const minutes = Math.floor(Math.abs(millis) / 60000)
+    const text = minutes >= 1440 ? `${Math.floor(minutes / 1440)}d` : minutes >= 60 ? `${Math.floor(minutes / 60)}h ${minutes % 60}m` : `${minutes}m`
+    return millis < 0 ? `expired ${text} ago` : `${text} left`
+}
+
+const certificateTitle = (info: CertificateInfo) => [
+    `${info.key_type} #${info.serial}`,
+    `principals: ${info.principals.join(", ") || "any"}`,
+    `valid: ${new Date(info.valid_after).toLocaleString()} - ${info.valid_before ? new Date(info.valid_before).toLocaleString() : "forever"}`,
+    info.signing_ca ? `CA: ${info.signing_ca}` : "",
+].filter(line => line).join("\n")
+
 export function TunnelPannel(props: any): JSX.Element {
 
     const {tunnels, update} = useTunnelCache()
+    const [ clientsOf, setClientsOf ] = useState<TunnelInfo>()
 
     const { enqueueSnackbar } = useSnackbar()
 
     const columns = useMemo(() => [{
-        headerName: "Local Port", field: "local_port", width: 120, type: 'number',
+        headerName: "Local Port", field: "local_port", width: 120,
+        valueGetter: ({row}: {row: TunnelInfo}) => row.kind !== "remote" && row.local_port === 0 ? `any${row.bound_port ? ` (${row.bound_port})` : ""}` : row.local_port,
+    }, {
+        headerName: "Bind Address", field: "bind_address", width: 130,
+        valueGetter: ({row}: {row: TunnelInfo}) => row.kind === "remote" ? "" : row.bind_address || "127.0.0.1",
+    }, {
+        headerName: "Kind", field: "kind", width: 100,
+        valueFormatter: ({value}: {value: any}) => value === "dynamic" ? "SOCKS5" : value === "remote" ? "REMOTE" : "LOCAL",
     }, {
         headerName: "Remote Port", field: "remote_port", width: 120, editable: true, type: 'number',
     }, {
@@ -33,6 +62,12 @@ export function TunnelPannel(props: any): JSX.Element {
         headerName: "SSH Port", field: "ssh_port", width: 120, editable: true, type: 'number',
     }, {
         headerName: "SSH Host", field: "ssh_host", width: 150, editable: true,
+    }, {
+        headerName: "Group", field: "group", width: 120,
+    }, {
+        headerName: "Jump Hosts", field: "jump_hosts", width: 200,
+        valueFormatter: ({value}: {value: any}) => (value as TunnelInfo["jump_hosts"] || [])
+            .map(elem => `${elem.username}@${elem.ssh_host}:${elem.ssh_port}`).join(" > "),
     }, {
         headerName: "Status", field: "status", width: 100,
         renderCell: (params: GridRenderCellParams) => {
@@ -40,28 +75,38 @@ export function TunnelPannel(props: any): JSX.Element {
             const {id, row, api} = params;
 
             const handleClick = (id: GridRowId, row: TunnelInfo) => () => {
-                const {id: _id, ...tunnel} = row
+                const tunnel = {...row}
                 tunnel.status = "run"
                 reqStateTunnelInfo(tunnel)
-                    .then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+                    .then(tunnels => update && update(tunnels))
                     .catch(e => enqueueSnackbar(e.message, {variant: "warning"}))
             }
 
             const handleClick2 = (id: GridRowId, row: TunnelInfo) => () => {
-                const {id: _id, ...tunnel} = row
+                const tunnel = {...row}
                 tunnel.status = "stop"
                 reqStateTunnelInfo(tunnel)
-                    .then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+                    .then(tunnels => update && update(tunnels))
                     .catch(e => enqueueSnackbar(e.message, {variant: "warning"}))
             }
 
             return (
                 <Grid container direction="column" alignItems="flex-start" justifyContent="center">
                     {
-                        params.value && params.value === "run"?
+                        params.value === "run"?
                         <Chip disabled={api.getRowMode(params.id) === "edit"} color="success" size="small" label="RUNNING" onClick={
                             handleClick2(id, row)
                         }></Chip>
+                        : params.value === "reconnecting"?
+                        <Chip color="warning" size="small" label="RECONNECTING" onClick={
+                            handleClick2(id, row)
+                        }></Chip>
+                        : params.value === "connecting"?
+                        <Chip disabled color="primary" size="small" label="CONNECTING"></Chip>
+                        : params.value === "failed"?
+                        <Chip disabled={api.getRowMode(params.id) === "edit"} color="error" size="small" label="FAILED" title={row.last_error} onClick={
+                            handleClick(id, row)
+                        }></Chip>
                         :
                         <Chip disabled={api.getRowMode(params.id) === "edit"} color="info" size="small" label="STOP" onClick={
                             handleClick(id, row)
@@ -70,14 +115,50 @@ export function TunnelPannel(props: any): JSX.Element {
                 </Grid>
             )
         }
+    }, {
+        headerName: "Clients", field: "clients", width: 100,
+        valueGetter: ({row}: {row: TunnelInfo}) => row.metrics ? `${row.metrics.active} / ${row.metrics.total}` : "",
+    }, {
+        headerName: "Targets", field: "targets", width: 90, type: 'number',
+        description: "other destinations forwarded over the same session",
+        renderCell: ({row}: GridRenderCellParams) => {
+            const targets = (row.targets || []) as TunnelTarget[]
+            return targets.length === 0 ? "" : <span title={targets.map(elem => `${elem.host}:${elem.port} at ${elem.bound_port}`).join("\n")}>{targets.length}</span>
+        }
+    }, {
+        headerName: "Traffic", field: "traffic", width: 220,
+        renderCell: ({row}: GridRenderCellParams) => (
+            <Grid container alignItems="center" wrap="nowrap" columnSpacing={1}>
+                <Grid item><TunnelSparkline tunnel_id={row.id}></TunnelSparkline></Grid>
+                <Grid item>{row.metrics ? `${formatBytes(row.metrics.bytes_in)} / ${formatBytes(row.metrics.bytes_out)}` : ""}</Grid>
+            </Grid>
+        ),
+    }, {
+        headerName: "Setup", field: "setup", width: 100,
+        description: "handshake and auth of the last login",
+        valueGetter: ({row}: {row: TunnelInfo}) => row.metrics && row.metrics.logins > 0 ? `${row.metrics.handshake + row.metrics.auth} ms` : "",
+    }, {
+        headerName: "Errors", field: "failures", width: 80, type: 'number',
     }, {
         headerName: "Username", field: "username", width: 200, editable: true,
     }, {
         headerName: "Password", field: "password", width: 200, editable: true,
     }, {
         headerName: "Private Key", field: "private_key", width: 200,
+        valueGetter: ({row}: {row: TunnelInfo}) => row.private_key?.trim().match(/^(-----BEGIN |PuTTY-User-Key-File-)/) ? "(pasted key)" : row.private_key,
     }, {
-        headerName: "Operation", field: "operation", width: 100,
+        headerName: "Certificate", field: "certificate_info", width: 200,
+        renderCell: ({row}: GridRenderCellParams) => {
+            const info = row.certificate_info as CertificateInfo | undefined
+            if (!info) {
+                return ""
+            }
+            const remaining = info.valid_before ? ` (${formatRemaining(info.valid_before - Date.now())})` : ""
+            return <Chip size="small" variant="outlined" color={info.expired ? "error" : info.expiring ? "warning" : "default"}
+                label={`${info.key_id}${remaining}`} title={certificateTitle(info)}></Chip>
+        }
+    }, {
+        headerName: "Operation", field: "operation", width: 130,
         renderCell: (params: GridRenderCellParams) => {
             console.info("render: ", params)
             const {id, row, api} = params;
@@ -93,7 +174,7 @@ export function TunnelPannel(props: any): JSX.Element {
                 } = api.getEditRowsModel()[id]
                 api.setRowMode(id, "view")
 
-                const { id: _id, ...tunnel } = {
+                const tunnel = {
                     ...row,
                     remote_port, remote_host, ssh_port, ssh_host, username, password,
                 } as TunnelInfo
@@ -101,7 +182,7 @@ export function TunnelPannel(props: any): JSX.Element {
                 console.info("sync: ", tunnel)
 
                 reqSyncTunnelInfo(tunnel)
-                    .then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+                    .then(tunnels => update && update(tunnels))
                     .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
             }
 
@@ -110,9 +191,8 @@ export function TunnelPannel(props: any): JSX.Element {
             }
 
             const onDeleteClick = (id: GridRowId) => () => {
-                const { id: _id, ...tunnel } = row
-                reqDeleteTunnelInfo(tunnel)
-                    .then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+                reqDeleteTunnelInfo(row)
+                    .then(tunnels => update && update(tunnels))
                     .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
             }
 
@@ -124,24 +204,53 @@ export function TunnelPannel(props: any): JSX.Element {
                     </Grid>
                     :
                     <Grid alignItems="center" container>
-                        <IconButton disabled={row.status === "run"} size="small" onClick={() => {api.setRowMode(id, "edit")}}><EditSharp /></IconButton>
-                        <IconButton color="error" disabled={row.status === "run"} size="small" onClick={onDeleteClick(id)}><DeleteSharp /></IconButton>
+                        <IconButton disabled={row.status !== "stop" && row.status !== "failed"} size="small" onClick={() => {api.setRowMode(id, "edit")}}><EditSharp /></IconButton>
+                        <IconButton color="error" disabled={row.status !== "stop" && row.status !== "failed"} size="small" onClick={onDeleteClick(id)}><DeleteSharp /></IconButton>
+                        <IconButton disabled={row.status === "stop" || row.status === "failed"} size="small" onClick={() => setClientsOf(row)}><PeopleSharp /></IconButton>
                     </Grid>
             )
         }
-    },], [update, enqueueSnackbar])
+    },], [update, enqueueSnackbar, setClientsOf])
+
+    useEffect(() => {
+        reqViewTunnelInfo().then(tunnels => {
+            update && update(tunnels)
+            // certificates are short lived, a login with one about to run out is refused next time
+            tunnels.filter(elem => elem.certificate_info && (elem.certificate_info.expiring || elem.certificate_info.expired))
+                .forEach(elem => enqueueSnackbar(`${elem.ssh_host}: certificate ${elem.certificate_info?.key_id} ${elem.certificate_info?.expired ? "has expired" : "expires soon"}`, {variant: "warning"}))
+        })
+    }, [update, enqueueSnackbar])
+
+    // the manager pushes every change, only the touched row is patched
+    useEffect(() => {
+        const unlisten = listenTunnelNotice(notice => {
+            update && update(tunnels => tunnels.map(elem => elem.id !== notice.id ? elem : {
+                ...elem, status: notice.status, bound_port: notice.bound_port, failures: notice.failures, last_error: notice.message || elem.last_error,
+            }))
+            if (notice.kind === "error" || (notice.kind === "stopped" && notice.message)) {
+                enqueueSnackbar(`${notice.bound_port || notice.local_port}: ${notice.message}`, {variant: "warning"})
+            }
+        })
+        return () => { unlisten.then(f => f()) }
+    }, [update, enqueueSnackbar])
 
     useEffect(() => {
-        reqViewTunnelInfo().then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+        const unlisten = listenTunnelMetrics(notice => {
+            update && update(tunnels => tunnels.map(elem => elem.id !== notice.id ? elem : {...elem, metrics: notice.metrics}))
+        })
+        return () => { unlisten.then(f => f()) }
     }, [update])
     
     return (
+        <>
         <DataGrid editMode="row" density="compact" columns={columns} rows={tunnels} components={{
                 Toolbar: TunnelGridToolbar,
             }} checkboxSelection disableSelectionOnClick
             onRowEditStart={(_, e: any) => e.defaultMuiPrevented = true}
             onRowEditStop={(_, e: any) => e.defaultMuiPrevented = true}
             onCellFocusOut={(_, e: any) => e.defaultMuiPrevented = true}></DataGrid>
+        <TunnelClientsDialog tunnel={clientsOf} onClose={() => setClientsOf(undefined)}></TunnelClientsDialog>
+        </>
     )
 }
 
@@ -1,8 +1,10 @@
 import { useState } from 'react'
 import { GridToolbarContainer, GridToolbarColumnsButton, GridToolbarFilterButton, GridToolbarDensitySelector, GridToolbarExport } from '@mui/x-data-grid'
-import { AppRegistrationOutlined, ReplaySharp } from '@mui/icons-material'
+import { AppRegistrationOutlined, FileDownloadOutlined, ReplaySharp } from '@mui/icons-material'
 import { Button, Grid, Modal } from '@mui/material'
 import { TunnelRegisterPannel } from './TunnelRegisterPannel'
+import { TunnelImportPannel } from './TunnelImportPannel'
+import { TunnelGroupBar } from './TunnelGroupBar'
 import { useTunnelCache } from './hooks'
 import { reqViewTunnelInfo } from './event'
 
@@ -11,10 +13,12 @@ export function TunnelGridToolbar(): JSX.Element {
         <GridToolbarContainer>
             <ViewItem></ViewItem>
             <RegisterItem></RegisterItem>
+            <ImportItem></ImportItem>
             <GridToolbarColumnsButton></GridToolbarColumnsButton>
             <GridToolbarFilterButton></GridToolbarFilterButton>
             <GridToolbarDensitySelector></GridToolbarDensitySelector>
             <GridToolbarExport></GridToolbarExport>
+            <TunnelGroupBar></TunnelGroupBar>
         </GridToolbarContainer>
     )
 }
@@ -33,13 +37,27 @@ const RegisterItem = (): JSX.Element => {
     )
 }
 
+const ImportItem = (): JSX.Element => {
+
+    const [ open, setOpen ] = useState(false);
+
+    return (
+        <Grid>
+            <Button size="small" startIcon={<FileDownloadOutlined />} onClick={() => setOpen(true)}>import</Button>
+            <Modal open={open}>
+                <TunnelImportPannel onClose={() => setOpen(false)}></TunnelImportPannel>
+            </Modal>
+        </Grid>
+    )
+}
+
 const ViewItem = (): JSX.Element => {
     const { update } = useTunnelCache()
 
     return (
         <Button size="small" startIcon={<ReplaySharp />} onClick={() => {
             update && update([])
-            reqViewTunnelInfo().then(tunnels => update && update(tunnels.map((elem, index) => ({...elem, id: index}))))
+            reqViewTunnelInfo().then(tunnels => update && update(tunnels))
         }}>load</Button>
     )
 }
\ No newline at end of file
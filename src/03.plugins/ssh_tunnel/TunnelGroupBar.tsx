@@ -0,0 +1,61 @@
+import { useCallback, useEffect, useState } from 'react'
+import { Chip, Grid } from '@mui/material'
+import { useSnackbar } from 'notistack'
+import { TunnelGroup } from './types'
+import { useTunnelCache } from './hooks'
+import { listenTunnelNotice, reqGroupTunnelInfo, reqViewTunnelGroup } from './event'
+
+const groupColor = (group: TunnelGroup) => group.failed > 0 ? "error"
+    : group.status === "running" ? "success"
+    : group.status === "partial" ? "warning"
+    : "info"
+
+// one chip per group, a click starts every member of a stopped or partly running group and stops a running one
+export function TunnelGroupBar(): JSX.Element {
+
+    const { tunnels, update } = useTunnelCache()
+    const [ groups, setGroups ] = useState<TunnelGroup[]>([])
+    const [ busy, setBusy ] = useState<string>()
+    const { enqueueSnackbar } = useSnackbar()
+
+    const refresh = useCallback(() => {
+        reqViewTunnelGroup().then(setGroups).catch(() => setGroups([]))
+    }, [])
+
+    useEffect(refresh, [refresh, tunnels.length])
+
+    useEffect(() => {
+        const unlisten = listenTunnelNotice(notice => {
+            if (notice.kind !== "connected" && notice.kind !== "closed") {
+                refresh()
+            }
+        })
+        return () => { unlisten.then(f => f()) }
+    }, [refresh])
+
+    const toggle = (group: TunnelGroup) => () => {
+        setBusy(group.name)
+        reqGroupTunnelInfo({name: group.name, status: group.status === "running" ? "stop" : "run"}, group.total)
+            .then(members => {
+                update && update(tunnels => tunnels.map(elem => members.find(member => member.id === elem.id) || elem))
+                members.filter(member => member.last_error)
+                    .forEach(member => enqueueSnackbar(`${group.name} ${member.bound_port || member.local_port}: ${member.last_error}`, {variant: "warning"}))
+            })
+            .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
+            .finally(() => {
+                setBusy(undefined)
+                refresh()
+            })
+    }
+
+    return (
+        <Grid container alignItems="center" columnSpacing={1} wrap="nowrap">
+            {groups.map(group => (
+                <Grid item key={group.name}>
+                    <Chip size="small" color={groupColor(group)} disabled={busy !== undefined}
+                        label={`${group.name} ${group.running}/${group.total}`} onClick={toggle(group)}></Chip>
+                </Grid>
+            ))}
+        </Grid>
+    )
+}
\ No newline at end of file
@@ -0,0 +1,112 @@
+import { forwardRef, ForwardedRef, useEffect, useState } from 'react'
+import { Paper, Box, Grid, TextField, Button, AppBar, Typography, List, ListItem, ListItemIcon, ListItemText, Checkbox } from '@mui/material'
+import { styled } from '@mui/system'
+import { useSnackbar } from 'notistack'
+import { ImportCandidate, TunnelInfo } from './types'
+import { useTunnelCache } from './hooks'
+import { reqImportTunnelInfo, reqRegisterTunnelInfo } from './event'
+
+export interface TunnelImportPannelProps {
+    children?: React.ReactNode;
+    ref?: React.Ref<unknown>;
+    onClose?: () => void,
+}
+
+const ImportPannelBox = styled(Box)(({theme}) => ({
+    padding: theme.spacing(2),
+}))
+
+const ImportPannelPaper = styled(Paper)({
+    width: 700,
+    position: "absolute",
+    transform: 'translate(-50%, -50%)',
+    top: '50%',
+    left: '50%',
+})
+
+const CandidateList = styled(List)({
+    maxHeight: 400,
+    overflow: "auto",
+    width: "100%",
+})
+
+const describe = (elem: ImportCandidate) => {
+    const {tunnel} = elem
+    const jumps = (tunnel.jump_hosts || []).map(jump => `${jump.username}@${jump.ssh_host}:${jump.ssh_port} > `).join("")
+    return `${tunnel.bind_address || "127.0.0.1"}:${tunnel.local_port} -> ${tunnel.remote_host}:${tunnel.remote_port} via ${jumps}${tunnel.username}@${tunnel.ssh_host}:${tunnel.ssh_port}`
+}
+
+// LocalForward entries of the ssh client config, the picked ones go through the usual register request
+export const TunnelImportPannel = forwardRef(({onClose}: TunnelImportPannelProps, ref: ForwardedRef<HTMLDivElement | null>) => {
+    const { tunnels, update } = useTunnelCache()
+    const [ path, setPath ] = useState("")
+    const [ candidates, setCandidates ] = useState<ImportCandidate[]>([])
+    const [ warnings, setWarnings ] = useState<string[]>([])
+    const [ checked, setChecked ] = useState<number[]>([])
+    const { enqueueSnackbar } = useSnackbar()
+
+    // tunnels may share a port, the same forward over the same host is what counts as already there
+    const registered = (elem: ImportCandidate) => tunnels.some(tunnel => tunnel.local_port === elem.tunnel.local_port
+        && tunnel.remote_host === elem.tunnel.remote_host && tunnel.remote_port === elem.tunnel.remote_port
+        && tunnel.ssh_host === elem.tunnel.ssh_host)
+
+    const load = () => {
+        reqImportTunnelInfo(path)
+            .then(preview => {
+                setCandidates(preview.candidates)
+                setWarnings(preview.warnings)
+                setChecked([])
+            })
+            .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
+    }
+
+    useEffect(load, [])
+
+    const toggle = (index: number) => () => setChecked(checked.includes(index) ? checked.filter(elem => elem !== index) : [...checked, index])
+
+    // one at a time, every register answers on the same event
+    const onImport = () => {
+        onClose && onClose()
+        checked.map(index => candidates[index].tunnel)
+            .reduce((prev, tunnel) => prev.then(() => reqRegisterTunnelInfo({...tunnel, status: "stop"} as TunnelInfo)
+                .then(tunnels => {update && update(tunnels)})
+                .catch(e => enqueueSnackbar(`${tunnel.local_port}: ${e.message}`, {variant: "error"}))), Promise.resolve())
+    }
+
+    return (
+        <ImportPannelPaper tabIndex={-1} ref={ref}>
+            <AppBar position="relative">
+                <Typography variant="overline" align="center">import from ssh config</Typography>
+            </AppBar>
+            <ImportPannelBox>
+                <Grid container direction="column" alignItems="center" rowSpacing={2}>
+                    <Grid item container alignItems="center" columnSpacing={2}>
+                        <Grid item xs={9}><TextField label="SSH Config" placeholder="~/.ssh/config" variant="standard" size="small" fullWidth value={path} onChange={e => setPath(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        <Grid item xs={3}><Button size="small" variant="text" onClick={load}>reload</Button></Grid>
+                    </Grid>
+                    <Grid item container>
+                        <CandidateList dense>
+                            {candidates.length === 0 && <ListItem><ListItemText secondary="no LocalForward found"></ListItemText></ListItem>}
+                            {candidates.map((elem, index) => (
+                                <ListItem key={index} button disabled={registered(elem)} onClick={toggle(index)}>
+                                    <ListItemIcon><Checkbox size="small" edge="start" checked={checked.includes(index)} disableRipple></Checkbox></ListItemIcon>
+                                    <ListItemText
+                                        primary={`${elem.alias}  ${describe(elem)}`}
+                                        secondary={registered(elem) ? "local port already registered" : elem.warnings.join(", ") || undefined}>
+                                    </ListItemText>
+                                </ListItem>
+                            ))}
+                            {warnings.map((warning, index) => (
+                                <ListItem key={`warning-${index}`}><ListItemText secondary={warning}></ListItemText></ListItem>
+                            ))}
+                        </CandidateList>
+                    </Grid>
+                    <Grid item container alignItems="center" justifyContent="space-between">
+                        <Button size="small" variant="text" disabled={checked.length === 0} onClick={onImport}>register selected</Button>
+                        <Button size="small" variant="text" onClick={() => onClose && onClose()}>cancel</Button>
+                    </Grid>
+                </Grid>
+            </ImportPannelBox>
+        </ImportPannelPaper>
+    )
+});
\ No newline at end of file
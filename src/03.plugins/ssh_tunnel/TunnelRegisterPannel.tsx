@@ -1,11 +1,11 @@
-import { forwardRef, ForwardedRef, useRef, useState } from 'react'
-import { Paper, Box, Grid, TextField, Button, AppBar, Typography, } from '@mui/material'
+import { forwardRef, ForwardedRef, useEffect, useRef, useState } from 'react'
+import { Paper, Box, Grid, TextField, Button, AppBar, Typography, MenuItem, FormControlLabel, Checkbox, } from '@mui/material'
 import { styled } from '@mui/system'
 import { useForm } from 'react-hook-form'
 import { useSnackbar } from 'notistack'
-import { TunnelInfo } from './types'
+import { AgentIdentity, JumpHost, TunnelInfo } from './types'
 import { useTunnelCache } from './hooks'
-import { reqRegisterTunnelInfo } from './event'
+import { reqRegisterTunnelInfo, reqViewAgentIdentityInfo } from './event'
 import { FileBrowser } from '../../01.components/FileBrowser'
 import { WindowSharp } from '@mui/icons-material'
 
@@ -19,6 +19,23 @@ const RegisterPannelBox = styled(Box)(({theme}) => ({
     padding: theme.spacing(2),
 }))
 
+// "user[:password]@host[:port]" hops in order, like ssh -J, a hop without password logs in through the agent
+const parseJumpHosts = (value: string): JumpHost[] => value.split(",")
+    .map(elem => elem.trim())
+    .filter(elem => elem.length > 0)
+    .map(elem => {
+        const at = elem.lastIndexOf("@")
+        const [username, ...password] = (at < 0 ? "" : elem.substring(0, at)).split(":")
+        const [ssh_host, ssh_port] = elem.substring(at + 1).split(":")
+        return {
+            ssh_host,
+            ssh_port: ssh_port ? Number(ssh_port) : 22,
+            username,
+            password: password.length > 0 ? password.join(":") : undefined,
+            agent: password.length === 0,
+        }
+    })
+
 const RegisterPannelPaper = styled(Paper)({
     width: 600,
     position: "absolute",
@@ -29,16 +46,48 @@ const RegisterPannelPaper = styled(Paper)({
 
 export const TunnelRegisterPannel = forwardRef(({onClose}: TunnelRegisterPannelProps, ref: ForwardedRef<HTMLDivElement | null>) => {
     const { handleSubmit, register } = useForm<TunnelInfo>()
-    const { update } = useTunnelCache()
+    const { tunnels, update } = useTunnelCache()
     const [ file, setFile ] = useState("")
+    const [ pastedKey, setPastedKey ] = useState("")
+    const [ identities, setIdentities ] = useState<AgentIdentity[]>([])
+    const [ identity, setIdentity ] = useState("none")
+    const [ jumps, setJumps ] = useState("")
+    const [ proxyKind, setProxyKind ] = useState<"none" | "http" | "socks5" | "command">("none")
+    const [ proxyAddress, setProxyAddress ] = useState("")
+    const [ proxyUsername, setProxyUsername ] = useState("")
+    const [ proxyPassword, setProxyPassword ] = useState("")
+    const [ proxyCommand, setProxyCommand ] = useState("")
+    const [ kind, setKind ] = useState<"local" | "dynamic" | "remote">("local")
+    const [ socksUsername, setSocksUsername ] = useState("")
+    const [ socksPassword, setSocksPassword ] = useState("")
+    const [ allowed, setAllowed ] = useState("")
+    const [ dependsOn, setDependsOn ] = useState<string[]>([])
     const { enqueueSnackbar } = useSnackbar()
 
     const fileRef = useRef<any>({})
 
+    useEffect(() => {
+        reqViewAgentIdentityInfo().then(setIdentities).catch(() => setIdentities([]))
+    }, [])
+
     const onSubmit = handleSubmit((data) => {
         onClose && onClose()
-        reqRegisterTunnelInfo({...data, status: "stop", private_key: file} as TunnelInfo)
-            .then(tunnels => {update && update(tunnels.map((elem, index) => ({...elem, id: index})))})
+        const agent = identity !== "none"
+        const agent_identity = agent && identity !== "any" ? identity : undefined
+        const socks_auth = kind === "dynamic" && socksUsername ? {username: socksUsername, password: socksPassword} : undefined
+        const remote = kind === "dynamic" ? {remote_port: 0, remote_host: ""} : {}
+        const allowed_clients = allowed.split(",").map(elem => elem.trim()).filter(elem => elem.length > 0)
+        const group = data.group?.trim() || undefined
+        const passphrase = data.passphrase || undefined
+        const certificate = data.certificate?.trim() || undefined
+        const [proxyHost, proxyPort] = proxyAddress.trim().split(":")
+        const proxy = proxyKind === "none" ? undefined
+            : proxyKind === "command" ? {kind: proxyKind, command: proxyCommand}
+            : {kind: proxyKind, host: proxyHost, port: Number(proxyPort), username: proxyUsername || undefined, password: proxyPassword || undefined}
+        // a pasted key wins over the picked file
+        const private_key = pastedKey.trim() || file
+        reqRegisterTunnelInfo({...data, ...remote, status: "stop", kind, socks_auth, allowed_clients, private_key, passphrase, certificate, agent, agent_identity, jump_hosts: parseJumpHosts(jumps), proxy, group, depends_on: dependsOn} as TunnelInfo)
+            .then(tunnels => {update && update(tunnels)})
             .catch(e => enqueueSnackbar(e.message, {variant: "error"}))
     })
 
@@ -51,16 +100,53 @@ export const TunnelRegisterPannel = forwardRef(({onClose}: TunnelRegisterPannelP
                 <form onSubmit={onSubmit}>
                     <Grid container direction="column" alignItems="center" rowSpacing={2}>
                         <Grid item container alignItems="center" columnSpacing={2}>
-                            <Grid item xs={3}><TextField label="Local Port" variant="standard" size="small" fullWidth {...register("local_port", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Local Port" placeholder="0 for any free port" variant="standard" size="small" fullWidth {...register("local_port", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            {kind !== "remote" && <>
+                                <Grid item xs={3}><TextField label="Bind Address" placeholder="127.0.0.1" variant="standard" size="small" fullWidth {...register("bind_address")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                                <Grid item xs={4}><FormControlLabel label={<Typography variant="caption">Allow Wildcard Bind</Typography>} control={
+                                    <Checkbox size="small" {...register("bind_wildcard")}></Checkbox>}></FormControlLabel></Grid>
+                            </>}
                         </Grid>
+                        {kind !== "remote" && <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={9}><TextField label="Allowed Clients" placeholder="127.0.0.1, 10.0.0.0/8, ::1" variant="standard" size="small" fullWidth value={allowed} onChange={e => setAllowed(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>}
                         <Grid item container alignItems="center" columnSpacing={2}>
-                            <Grid item xs={3}><TextField label="Remote Port" variant="standard" size="small" fullWidth {...register("remote_port", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
-                            <Grid item xs={3}><TextField label="Remote Host" variant="standard" size="small" fullWidth {...register("remote_host")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField select label="Kind" variant="standard" size="small" fullWidth value={kind} onChange={e => setKind(e.target.value as "local" | "dynamic" | "remote")} InputLabelProps={{shrink: true}}>
+                                <MenuItem value="local">local forward</MenuItem>
+                                <MenuItem value="dynamic">dynamic (SOCKS5)</MenuItem>
+                                <MenuItem value="remote">remote (reverse)</MenuItem>
+                            </TextField></Grid>
+                            {kind === "dynamic" && <>
+                                <Grid item xs={3}><TextField label="SOCKS Username" variant="standard" size="small" fullWidth value={socksUsername} onChange={e => setSocksUsername(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                                <Grid item xs={3}><TextField label="SOCKS Password" variant="standard" size="small" fullWidth value={socksPassword} onChange={e => setSocksPassword(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            </>}
+                            {kind === "remote" && <Grid item xs={3}><TextField label="Local Host" placeholder="127.0.0.1" variant="standard" size="small" fullWidth {...register("local_host")} InputLabelProps={{shrink: true}}></TextField></Grid>}
                         </Grid>
+                        {kind !== "dynamic" && <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={3}><TextField label={kind === "remote" ? "Remote Listen Port" : "Remote Port"} variant="standard" size="small" fullWidth {...register("remote_port", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label={kind === "remote" ? "Remote Listen Host" : "Remote Host"} variant="standard" size="small" fullWidth {...register("remote_host")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>}
                         <Grid item container alignItems="center" columnSpacing={2}>
                             <Grid item xs={3}><TextField label="SSH Port" variant="standard" size="small" fullWidth {...register("ssh_port", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
                             <Grid item xs={3}><TextField label="SSH Host" variant="standard" size="small" fullWidth {...register("ssh_host")} InputLabelProps={{shrink: true}}></TextField></Grid>
                         </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={9}><TextField label="Jump Hosts" placeholder="user:password@bastion:22, user@bastion2" variant="standard" size="small" fullWidth value={jumps} onChange={e => setJumps(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={3}><TextField select label="Proxy" variant="standard" size="small" fullWidth value={proxyKind} onChange={e => setProxyKind(e.target.value as "none" | "http" | "socks5" | "command")} InputLabelProps={{shrink: true}}>
+                                <MenuItem value="none">direct</MenuItem>
+                                <MenuItem value="http">HTTP CONNECT</MenuItem>
+                                <MenuItem value="socks5">SOCKS5</MenuItem>
+                                <MenuItem value="command">ProxyCommand</MenuItem>
+                            </TextField></Grid>
+                            {proxyKind === "command" && <Grid item xs={9}><TextField label="Proxy Command" placeholder="ssh -W %h:%p bastion" variant="standard" size="small" fullWidth value={proxyCommand} onChange={e => setProxyCommand(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>}
+                            {(proxyKind === "http" || proxyKind === "socks5") && <>
+                                <Grid item xs={3}><TextField label="Proxy Address" placeholder="proxy:3128" variant="standard" size="small" fullWidth value={proxyAddress} onChange={e => setProxyAddress(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                                <Grid item xs={3}><TextField label="Proxy Username" variant="standard" size="small" fullWidth value={proxyUsername} onChange={e => setProxyUsername(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                                <Grid item xs={3}><TextField label="Proxy Password" type="password" variant="standard" size="small" fullWidth value={proxyPassword} onChange={e => setProxyPassword(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            </>}
+                        </Grid>
                         <Grid item container alignItems="center" columnSpacing={2}>
                             <Grid item xs={3}><TextField label="Username" variant="standard" size="small" fullWidth {...register("username")} InputLabelProps={{shrink: true}}></TextField></Grid>
                             <Grid item xs={3}><TextField label="Password" variant="standard" size="small" fullWidth {...register("password")} InputLabelProps={{shrink: true}}></TextField></Grid>
@@ -68,6 +154,36 @@ export const TunnelRegisterPannel = forwardRef(({onClose}: TunnelRegisterPannelP
                                 Array.isArray(file)? setFile(file.join(",")) : setFile(file)
                             }}></FileBrowser></Grid>
                         </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={6}><TextField label="Private Key" placeholder="paste an OpenSSH, PEM, PKCS#8 or PuTTY key" variant="standard" size="small" fullWidth multiline maxRows={4} value={pastedKey} onChange={e => setPastedKey(e.target.value)} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Passphrase" type="password" placeholder="password if empty" variant="standard" size="small" fullWidth {...register("passphrase")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={9}><TextField label="Certificate" placeholder="path or pasted line, <private key>-cert.pub when empty" variant="standard" size="small" fullWidth {...register("certificate")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={6}><TextField select label="SSH Agent" variant="standard" size="small" fullWidth value={identity} onChange={e => setIdentity(e.target.value)} InputLabelProps={{shrink: true}}>
+                                <MenuItem value="none">not used</MenuItem>
+                                <MenuItem value="any">any identity</MenuItem>
+                                {identities.map(elem => <MenuItem key={elem.id} value={elem.id}>{`${elem.comment} (${elem.key_type})`}</MenuItem>)}
+                            </TextField></Grid>
+                            <Grid item xs={4}><FormControlLabel label={<Typography variant="caption">Keyboard-Interactive</Typography>} control={
+                                <Checkbox size="small" {...register("interactive")}></Checkbox>}></FormControlLabel></Grid>
+                        </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={3}><TextField label="Keepalive (s)" placeholder="30" variant="standard" size="small" fullWidth {...register("keepalive_interval", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Keepalive Count" placeholder="3" variant="standard" size="small" fullWidth {...register("keepalive_count_max", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Reconnect Attempts" placeholder="8" variant="standard" size="small" fullWidth {...register("reconnect_attempts", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Reconnect Delay (ms)" placeholder="1000" variant="standard" size="small" fullWidth {...register("reconnect_delay", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                        </Grid>
+                        <Grid item container alignItems="center" columnSpacing={2}>
+                            <Grid item xs={3}><TextField label="Drain Timeout (s)" placeholder="0" variant="standard" size="small" fullWidth {...register("drain_timeout", {valueAsNumber: true})} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={3}><TextField label="Group" placeholder="staging-eu" variant="standard" size="small" fullWidth {...register("group")} InputLabelProps={{shrink: true}}></TextField></Grid>
+                            <Grid item xs={6}><TextField select label="Depends On" variant="standard" size="small" fullWidth value={dependsOn} SelectProps={{multiple: true}}
+                                onChange={e => setDependsOn(e.target.value as unknown as string[])} InputLabelProps={{shrink: true}}>
+                                {tunnels.map(elem => <MenuItem key={elem.id} value={elem.id}>{`${elem.local_port} -> ${elem.remote_host}:${elem.remote_port} via ${elem.ssh_host}`}</MenuItem>)}
+                            </TextField></Grid>
+                        </Grid>
                         <Grid item container alignItems="center" justifyContent="space-between">
                             <Button size="small" variant="text" type="submit">register</Button>
                             <Button size="small" variant="text" type="reset" onClick={() => onClose && onClose()}>cancel</Button>
@@ -0,0 +1,46 @@
+import { useEffect, useState } from 'react'
+import { useTheme } from '@mui/material'
+import { listenTunnelMetrics } from './event'
+import { TunnelMetrics } from './types'
+
+const SPARKLINE_POINTS = 30
+const SPARKLINE_WIDTH = 90
+const SPARKLINE_HEIGHT = 20
+
+export interface TunnelSparklineProps {
+    tunnel_id?: string,
+}
+
+// bytes per second of one tunnel, both directions, from the pushed metrics snapshots
+export function TunnelSparkline({tunnel_id}: TunnelSparklineProps): JSX.Element {
+
+    const theme = useTheme()
+    const [ rates, setRates ] = useState<number[]>([])
+
+    useEffect(() => {
+        let last: {metrics: TunnelMetrics, at: number} | undefined
+        const unlisten = listenTunnelMetrics(notice => {
+            if (notice.id !== tunnel_id) {
+                return
+            }
+            const {metrics, at} = notice
+            if (last && at > last.at) {
+                const bytes = metrics.bytes_in + metrics.bytes_out - last.metrics.bytes_in - last.metrics.bytes_out
+                const rate = Math.max(bytes, 0) * 1000 / (at - last.at)
+                setRates(rates => [...rates, rate].slice(-SPARKLINE_POINTS))
+            }
+            last = {metrics, at}
+        })
+        return () => { unlisten.then(f => f()) }
+    }, [tunnel_id])
+
+    const max = Math.max(...rates, 1)
+    const step = SPARKLINE_WIDTH / (SPARKLINE_POINTS - 1)
+    const points = rates.map((rate, index) => `${(index + SPARKLINE_POINTS - rates.length) * step},${SPARKLINE_HEIGHT - rate / max * SPARKLINE_HEIGHT}`).join(" ")
+
+    return (
+        <svg width={SPARKLINE_WIDTH} height={SPARKLINE_HEIGHT}>
+            <polyline fill="none" stroke={theme.palette.primary.main} strokeWidth={1} points={points}></polyline>
+        </svg>
+    )
+}
\ No newline at end of file
@@ -1,6 +1,6 @@
 import { getCurrent } from '@tauri-apps/api/window'
 import { Request, Response } from '../../00.event/event'
-import { TunnelInfo } from './types'
+import { AgentIdentity, AuthAnswer, AuthPrompt, HostKeyEntry, HostKeyPrompt, ImportPreview, TunnelClient, TunnelGroup, TunnelGroupControl, TunnelInfo, TunnelNotice, TunnelProbeReport } from './types'
 
 const REQ_TUNNEL_TIMEOUT = 10000 //ms
 
@@ -19,6 +19,48 @@ const RESP_DELETE_TUNNEL_INFO = "resp-delete-tunnel-info"
 const REQ_STATE_TUNNEL_INFO = "req-state-tunnel-info"
 const RESP_STAET_TUNNEL_INFO = "resp-state-tunnel-info"
 
+const REQ_CLIENTS_TUNNEL_INFO = "req-clients-tunnel-info"
+const RESP_CLIENTS_TUNNEL_INFO = "resp-clients-tunnel-info"
+
+const REQ_KILL_TUNNEL_CLIENT = "req-kill-tunnel-client"
+const RESP_KILL_TUNNEL_CLIENT = "resp-kill-tunnel-client"
+
+const REQ_GROUP_TUNNEL_INFO = "req-group-tunnel-info"
+const RESP_GROUP_TUNNEL_INFO = "resp-group-tunnel-info"
+
+const REQ_VIEW_TUNNEL_GROUP = "req-view-tunnel-group"
+const RESP_VIEW_TUNNEL_GROUP = "resp-view-tunnel-group"
+
+const REQ_TEST_TUNNEL_INFO = "req-test-tunnel-info"
+const RESP_TEST_TUNNEL_INFO = "resp-test-tunnel-info"
+
+const PROMPT_HOSTKEY_TUNNEL_INFO = "prompt-hostkey-tunnel-info"
+
+const REQ_VIEW_HOSTKEY_INFO = "req-view-hostkey-info"
+const RESP_VIEW_HOSTKEY_INFO = "resp-view-hostkey-info"
+
+const REQ_REMOVE_HOSTKEY_INFO = "req-remove-hostkey-info"
+const RESP_REMOVE_HOSTKEY_INFO = "resp-remove-hostkey-info"
+
+const REQ_TRUST_HOSTKEY_INFO = "req-trust-hostkey-info"
+const RESP_TRUST_HOSTKEY_INFO = "resp-trust-hostkey-info"
+
+const REQ_VIEW_AGENT_IDENTITY_INFO = "req-view-agent-identity-info"
+const RESP_VIEW_AGENT_IDENTITY_INFO = "resp-view-agent-identity-info"
+
+const REQ_IMPORT_TUNNEL_INFO = "req-import-tunnel-info"
+const RESP_IMPORT_TUNNEL_INFO = "resp-import-tunnel-info"
+
+const PROMPT_AUTH_TUNNEL_INFO = "prompt-auth-tunnel-info"
+
+const REQ_ANSWER_AUTH_TUNNEL_INFO = "req-answer-auth-tunnel-info"
+const RESP_ANSWER_AUTH_TUNNEL_INFO = "resp-answer-auth-tunnel-info"
+
+const AUTH_ANSWER_TIMEOUT = 90000 //ms
+
+const NOTICE_STATE_TUNNEL_INFO = "notice-state-tunnel-info"
+const NOTICE_METRICS_TUNNEL_INFO = "notice-metrics-tunnel-info"
+
 const TUNNEL_TIMEOUT_ERR = {name: "ssh-tunnel-timeout", message: "SSH TUNNEL Request Time Out!!!"} as Error
 const TUNNEL_UNKNOWN_ERR = {name: "ssh-tunnel-unknown", message: "SSH TUNNEL Unknown Error!!!"} as Error
 
@@ -81,7 +123,9 @@ export const reqDeleteTunnelInfo = (tunnel: TunnelInfo) => {
 export const reqStateTunnelInfo = (tunnel: TunnelInfo) => {
     getCurrent().emit(REQ_STATE_TUNNEL_INFO, JSON.stringify({data: tunnel} as Request<TunnelInfo>))
     return new Promise<TunnelInfo[]>((resolve, reject) => {
-        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+        // keyboard-interactive tunnels wait for the user to answer the prompts, stopping ones for their clients to leave
+        const timeout = (tunnel.interactive ? REQ_TUNNEL_TIMEOUT + AUTH_ANSWER_TIMEOUT : REQ_TUNNEL_TIMEOUT) + (tunnel.drain_timeout || 0) * 1000
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), timeout)
 
         getCurrent().once<string>(RESP_STAET_TUNNEL_INFO, e => {  
             clearTimeout(tid)
@@ -90,4 +134,178 @@ export const reqStateTunnelInfo = (tunnel: TunnelInfo) => {
             r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
         })
     })
+}
+
+// the members start one after the other, each of them may take as long as a single tunnel
+export const reqGroupTunnelInfo = (control: TunnelGroupControl, members: number) => {
+    getCurrent().emit(REQ_GROUP_TUNNEL_INFO, JSON.stringify({data: control} as Request<TunnelGroupControl>))
+    return new Promise<TunnelInfo[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT * Math.max(members, 1))
+
+        getCurrent().once<string>(RESP_GROUP_TUNNEL_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<TunnelInfo[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const reqViewTunnelGroup = () => {
+    getCurrent().emit(REQ_VIEW_TUNNEL_GROUP, JSON.stringify({} as Request<undefined>))
+    return new Promise<TunnelGroup[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_VIEW_TUNNEL_GROUP, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<TunnelGroup[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const reqTestTunnelInfo = (tunnel: TunnelInfo) => {
+    getCurrent().emit(REQ_TEST_TUNNEL_INFO, JSON.stringify({data: tunnel} as Request<TunnelInfo>))
+    return new Promise<TunnelProbeReport>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT * 6)
+
+        getCurrent().once<string>(RESP_TEST_TUNNEL_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<TunnelProbeReport>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const listenHostKeyPrompt = (handler: (prompt: HostKeyPrompt) => void) => {
+    return getCurrent().listen<string>(PROMPT_HOSTKEY_TUNNEL_INFO, e => handler(JSON.parse(e.payload) as HostKeyPrompt))
+}
+
+export const reqViewHostKeyInfo = () => {
+    getCurrent().emit(REQ_VIEW_HOSTKEY_INFO, JSON.stringify({} as Request<undefined>))
+    return new Promise<HostKeyEntry[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_VIEW_HOSTKEY_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<HostKeyEntry[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const reqRemoveHostKeyInfo = (hostkey: HostKeyEntry) => {
+    getCurrent().emit(REQ_REMOVE_HOSTKEY_INFO, JSON.stringify({data: hostkey} as Request<HostKeyEntry>))
+    return new Promise<HostKeyEntry[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_REMOVE_HOSTKEY_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<HostKeyEntry[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+// pins the prompted key, re-pinning replaces whatever was stored for the host
+export const reqTrustHostKeyInfo = (prompt: HostKeyPrompt) => {
+    getCurrent().emit(REQ_TRUST_HOSTKEY_INFO, JSON.stringify({data: prompt} as Request<HostKeyPrompt>))
+    return new Promise<HostKeyEntry[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_TRUST_HOSTKEY_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<HostKeyEntry[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const reqViewAgentIdentityInfo = () => {
+    getCurrent().emit(REQ_VIEW_AGENT_IDENTITY_INFO, JSON.stringify({} as Request<undefined>))
+    return new Promise<AgentIdentity[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_VIEW_AGENT_IDENTITY_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<AgentIdentity[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+// an empty path reads ~/.ssh/config
+export const reqImportTunnelInfo = (path?: string) => {
+    getCurrent().emit(REQ_IMPORT_TUNNEL_INFO, JSON.stringify({data: path} as Request<string>))
+    return new Promise<ImportPreview>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_IMPORT_TUNNEL_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<ImportPreview>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const listenAuthPrompt = (handler: (prompt: AuthPrompt) => void) => {
+    return getCurrent().listen<string>(PROMPT_AUTH_TUNNEL_INFO, e => handler(JSON.parse(e.payload) as AuthPrompt))
+}
+
+export const reqClientsTunnelInfo = (tunnel: TunnelInfo) => {
+    getCurrent().emit(REQ_CLIENTS_TUNNEL_INFO, JSON.stringify({data: tunnel} as Request<TunnelInfo>))
+    return new Promise<TunnelClient[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_CLIENTS_TUNNEL_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<TunnelClient[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+// without an id every client of the tunnel is dropped
+export const reqKillTunnelClient = (tunnel: TunnelInfo, id?: number) => {
+    getCurrent().emit(REQ_KILL_TUNNEL_CLIENT, JSON.stringify({data: {tunnel, id}} as Request<{tunnel: TunnelInfo, id?: number}>))
+    return new Promise<TunnelClient[]>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_KILL_TUNNEL_CLIENT, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<TunnelClient[]>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
+}
+
+export const listenTunnelNotice = (handler: (notice: TunnelNotice) => void) => {
+    return getCurrent().listen<string>(NOTICE_STATE_TUNNEL_INFO, e => handler(JSON.parse(e.payload) as TunnelNotice))
+}
+
+export const listenTunnelMetrics = (handler: (notice: TunnelNotice) => void) => {
+    return getCurrent().listen<string>(NOTICE_METRICS_TUNNEL_INFO, e => handler(JSON.parse(e.payload) as TunnelNotice))
+}
+
+export const reqAnswerAuthInfo = (answer: AuthAnswer) => {
+    getCurrent().emit(REQ_ANSWER_AUTH_TUNNEL_INFO, JSON.stringify({data: answer} as Request<AuthAnswer>))
+    return new Promise<boolean>((resolve, reject) => {
+        const tid = setTimeout(() => reject(TUNNEL_TIMEOUT_ERR), REQ_TUNNEL_TIMEOUT)
+
+        getCurrent().once<string>(RESP_ANSWER_AUTH_TUNNEL_INFO, e => {  
+            clearTimeout(tid)
+
+            const r = JSON.parse(e.payload) as Response<boolean>
+            r.data? resolve(r.data) : reject(r.err || TUNNEL_UNKNOWN_ERR)
+        })
+    })
 }
\ No newline at end of file
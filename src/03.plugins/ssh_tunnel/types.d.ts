@@ -1,16 +1,215 @@
 import { Dispatch, SetStateAction } from 'react'
 
 export interface TunnelInfo {
-    id?: number,
+    id?: string,
     local_port?: number,
+    bound_port?: number,
     remote_port?: number,
     remote_host?: string,
     ssh_port?: number,
     ssh_host?: string,
     username?: string,
     password?: string,
+    // a key file path, or the key itself when pasted
     private_key?: string,
-    status?: "add" | "delete" | "update" | "run" | "stop",
+    passphrase?: string,
+    // an OpenSSH user certificate path or line, `<private_key>-cert.pub` is picked up without
+    certificate?: string,
+    status?: "add" | "delete" | "update" | "run" | "stop" | "connecting" | "reconnecting" | "failed",
+    kind?: "local" | "dynamic" | "remote",
+    socks_auth?: SocksAuth,
+    local_host?: string,
+    bind_address?: string,
+    bind_wildcard?: boolean,
+    allowed_clients?: string[],
+    max_channels?: number,
+    keepalive_interval?: number,
+    keepalive_count_max?: number,
+    reconnect_attempts?: number,
+    reconnect_delay?: number,
+    drain_timeout?: number,
+    system_known_hosts?: boolean,
+    agent?: boolean,
+    agent_identity?: string,
+    interactive?: boolean,
+    jump_hosts?: JumpHost[],
+    // in front of the first ssh host, the first jump host when there are any
+    proxy?: TunnelProxy,
+    group?: string,
+    depends_on?: string[],
+    failures?: number,
+    last_error?: string,
+    certificate_info?: CertificateInfo,
+    metrics?: TunnelMetrics,
+    // other destinations a running local tunnel was asked to reach, a cluster's nodes behind the ssh server
+    targets?: TunnelTarget[],
+}
+
+export interface TunnelTarget {
+    host: string,
+    port: number,
+    bound_port: number,
+}
+
+// times in milliseconds, `valid_before` missing for a certificate that never expires
+export interface CertificateInfo {
+    key_type: string,
+    key_id: string,
+    serial: number,
+    principals: string[],
+    valid_after: number,
+    valid_before?: number,
+    signing_ca?: string,
+    expiring: boolean,
+    expired: boolean,
+}
+
+export interface TunnelClient {
+    id: number,
+    peer: string,
+    started: number,
+    bytes_in: number,
+    bytes_out: number,
+    idle: number,
+}
+
+export interface TunnelMetrics {
+    active: number,
+    total: number,
+    bytes_in: number,
+    bytes_out: number,
+    closed: number,
+    duration_total: number,
+    duration_max: number,
+    connect: number,
+    handshake: number,
+    auth: number,
+    logins: number,
+    errors: number,
+}
+
+export interface SocksAuth {
+    username: string,
+    password: string,
+}
+
+// `host` and `port` for http and socks5, `command` (%h, %p and %r expanded) for a ProxyCommand
+export interface TunnelProxy {
+    kind: "http" | "socks5" | "command",
+    host?: string,
+    port?: number,
+    username?: string,
+    password?: string,
+    command?: string,
+}
+
+export interface JumpHost {
+    ssh_host: string,
+    ssh_port: number,
+    username: string,
+    password?: string,
+    private_key?: string,
+    passphrase?: string,
+    certificate?: string,
+    agent?: boolean,
+    agent_identity?: string,
+    interactive?: boolean,
+}
+
+export interface TunnelProbeStage {
+    stage: "connect" | "handshake" | "hostkey" | "auth" | "channel",
+    host: string,
+    success: boolean,
+    elapsed: number,
+    detail?: string,
+    kind?: string,
+    message?: string,
+}
+
+export interface TunnelProbeReport {
+    local_port: number,
+    success: boolean,
+    stages: TunnelProbeStage[],
+    hostkey?: HostKeyPrompt,
+}
+
+export interface HostKeyPrompt {
+    host: string,
+    port: number,
+    state: "unknown" | "mismatch",
+    key_type: string,
+    fingerprint: string,
+    key: string,
+}
+
+export interface AuthPromptField {
+    text: string,
+    echo: boolean,
+}
+
+export interface AuthPrompt {
+    id: number,
+    tunnel: string,
+    ssh_host: string,
+    name: string,
+    instructions: string,
+    prompts: AuthPromptField[],
+}
+
+export interface AuthAnswer {
+    id: number,
+    answers: string[],
+    cancel?: boolean,
+}
+
+export interface AgentIdentity {
+    id: string,
+    key_type: string,
+    comment: string,
+}
+
+export interface ImportCandidate {
+    alias: string,
+    forward: string,
+    tunnel: TunnelInfo,
+    warnings: string[],
+}
+
+export interface ImportPreview {
+    candidates: ImportCandidate[],
+    warnings: string[],
+}
+
+export interface TunnelGroup {
+    name: string,
+    status: "running" | "partial" | "stopped",
+    total: number,
+    running: number,
+    failed: number,
+    members: string[],
+}
+
+export interface TunnelGroupControl {
+    name: string,
+    status: "run" | "stop",
+}
+
+export interface TunnelNotice {
+    id: string,
+    local_port: number,
+    bound_port?: number,
+    kind: "started" | "stopped" | "connected" | "closed" | "error" | "state" | "metrics",
+    status: TunnelInfo["status"],
+    peer?: string,
+    message?: string,
+    failures: number,
+    metrics: TunnelMetrics,
+    at: number,
+}
+
+export interface HostKeyEntry {
+    host: string,
+    key: string,
 }
 
 export interface TunnelCache {